@@ -0,0 +1,162 @@
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::path::Path;
+
+use netcom::backup::ConfigFile;
+use netcom::diff::{diff, DiffEntry, DiffOptions, SnapshotDiff};
+use netcom::netcom_client_sync::NetcomClientSync;
+use netcom::snapshot::ParameterSnapshot;
+use serde_json::json;
+
+use crate::output::{Format, Table};
+
+/// Either side of a comparison: a backup file if the argument names an
+/// existing file, otherwise a device read live.
+enum Source {
+    File(ParameterSnapshot),
+    Device(String),
+}
+
+impl Source {
+    fn parse(arg: &str) -> Result<Source, Box<dyn Error>> {
+        if Path::new(arg).is_file() {
+            let file = ConfigFile::from_json(&std::fs::read_to_string(arg)?)?;
+            Ok(Source::File(file.to_snapshot()))
+        } else {
+            Ok(Source::Device(arg.to_string()))
+        }
+    }
+
+    fn snapshot(
+        self,
+        client: &mut NetcomClientSync,
+        params: &[&str],
+    ) -> Result<ParameterSnapshot, Box<dyn Error>> {
+        match self {
+            Source::File(snapshot) => Ok(snapshot),
+            Source::Device(device) => Ok(client.backup(&device, params)?.to_snapshot()),
+        }
+    }
+}
+
+/// Compares two sources and prints the differences. Devices are read for
+/// `params`, or else for the parameters found in the backup files. Returns
+/// whether any differences were found.
+pub fn run(
+    client: &mut NetcomClientSync,
+    left: &str,
+    right: &str,
+    params: &[String],
+    options: &DiffOptions,
+    format: Format,
+) -> Result<bool, Box<dyn Error>> {
+    let left = Source::parse(left)?;
+    let right = Source::parse(right)?;
+
+    let mut names: BTreeSet<String> = params.iter().cloned().collect();
+    if names.is_empty() {
+        for source in [&left, &right] {
+            if let Source::File(snapshot) = source {
+                names.extend(snapshot.values.keys().cloned());
+            }
+        }
+    }
+    let names: Vec<&str> = names.iter().map(String::as_str).collect();
+    let needs_params = [&left, &right]
+        .iter()
+        .any(|s| matches!(s, Source::Device(_)));
+    if needs_params && names.is_empty() {
+        return Err("No parameters to read; give --params or compare with a backup file".into());
+    }
+
+    let left = left.snapshot(client, &names)?;
+    let right = right.snapshot(client, &names)?;
+    let result = diff(&left, &right, options);
+
+    match format {
+        Format::Json => println!("{}", result.to_json()?),
+        _ => print!("{}", diff_table(&result).render(format)),
+    }
+    Ok(!result.is_empty())
+}
+
+fn diff_table(diff: &SnapshotDiff) -> Table {
+    let mut table = Table::new(&["change", "param", "left", "right"]);
+    for entry in &diff.entries {
+        let (change, left, right) = match entry {
+            DiffEntry::Added { value, .. } => ("added", None, *value),
+            DiffEntry::Removed { value, .. } => ("removed", *value, None),
+            DiffEntry::Changed { left, right, .. } => ("changed", *left, *right),
+        };
+        table.push(vec![
+            json!(change),
+            json!(entry.param()),
+            json!(left),
+            json!(right),
+        ]);
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use netcom::mock_server::MockServer;
+
+    #[test]
+    fn should_compare_device_with_backup_file() {
+        let server = MockServer::start()
+            .with_device("unit-1", &[("sp1", 20.0), ("sp2", 45.0)])
+            .with_device("unit-2", &[("sp1", 20.0), ("sp2", 46.0)]);
+        let mut client = NetcomClientSync::new("127.0.0.1", server.port());
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("unit-1.json");
+        let file = client.backup("unit-1", &["sp1", "sp2"]).unwrap();
+        std::fs::write(&path, file.to_json().unwrap()).unwrap();
+        let path = path.to_str().unwrap();
+
+        let options = DiffOptions::default();
+        assert!(run(&mut client, path, "unit-2", &[], &options, Format::Json).unwrap());
+        assert!(!run(&mut client, path, "unit-1", &[], &options, Format::Json).unwrap());
+
+        let options = DiffOptions {
+            ignore: vec!["sp2".to_string()],
+            ..Default::default()
+        };
+        assert!(run(&mut client, "unit-1", "unit-2", &[], &options, Format::Json).is_err());
+        let params = ["sp1".to_string(), "sp2".to_string()];
+        assert!(!run(
+            &mut client,
+            "unit-1",
+            "unit-2",
+            &params,
+            &options,
+            Format::Json
+        )
+        .unwrap());
+    }
+
+    #[test]
+    fn should_tabulate_diff_entries() {
+        let diff = SnapshotDiff {
+            left: "unit-1".to_string(),
+            right: "unit-2".to_string(),
+            entries: vec![
+                DiffEntry::Added {
+                    param: "new".to_string(),
+                    value: Some(1.0),
+                },
+                DiffEntry::Changed {
+                    param: "sp2".to_string(),
+                    left: Some(45.0),
+                    right: None,
+                },
+            ],
+        };
+        assert_eq!(
+            diff_table(&diff).render(Format::Csv),
+            "change,param,left,right\nadded,new,,1.0\nchanged,sp2,45.0,\n"
+        );
+    }
+}
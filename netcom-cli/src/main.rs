@@ -0,0 +1,230 @@
+mod compare;
+mod output;
+mod shell;
+mod spec;
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::process::ExitCode;
+use std::thread;
+use std::time::Duration;
+
+use chrono::{Local, SecondsFormat};
+use clap::{Parser, Subcommand};
+use netcom::diff::DiffOptions;
+use netcom::netcom::{RdOp, WrOp, DEFAULT_PORT};
+use netcom::netcom_client_sync::NetcomClientSync;
+use serde_json::{json, Value};
+
+use crate::output::{Format, Table};
+
+#[derive(Parser)]
+#[command(
+    name = "netcom-cli",
+    version,
+    about = "Command-line client for netcom servers"
+)]
+struct Cli {
+    /// Server hostname
+    #[arg(long, env = "NETCOM_HOST", default_value = "localhost", global = true)]
+    host: String,
+
+    /// Server port
+    #[arg(long, env = "NETCOM_PORT", default_value_t = DEFAULT_PORT, global = true)]
+    port: u16,
+
+    /// Output format
+    #[arg(long, value_enum, default_value = "table", global = true)]
+    format: Format,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List the devices known to the server
+    Devices,
+
+    /// Read parameters from a device
+    Read {
+        device: String,
+
+        /// Parameters as `param[:type]`
+        #[arg(required = true, value_parser = spec::parse_read)]
+        params: Vec<RdOp>,
+    },
+
+    /// Write parameters to a device
+    Write {
+        device: String,
+
+        /// Assignments as `param=value[:type]`
+        #[arg(required = true, value_parser = spec::parse_write)]
+        params: Vec<WrOp>,
+    },
+
+    /// Read parameters from a device repeatedly
+    Watch {
+        device: String,
+
+        /// Parameters as `param[:type]`
+        #[arg(required = true, value_parser = spec::parse_read)]
+        params: Vec<RdOp>,
+
+        /// Polling interval in milliseconds
+        #[arg(long, default_value_t = 1000)]
+        interval: u64,
+
+        /// Stop after this many reads
+        #[arg(long)]
+        count: Option<u64>,
+    },
+
+    /// Show the server version
+    Info,
+
+    /// Compare the parameters of two devices or backup files
+    ///
+    /// Exits with status 1 if there are differences.
+    Diff {
+        /// Device name, or path to a backup file
+        left: String,
+
+        /// Device name, or path to a backup file
+        right: String,
+
+        /// Parameters to read from devices; defaults to those in the backup files
+        #[arg(long, value_delimiter = ',')]
+        params: Vec<String>,
+
+        /// Largest difference between two values that still counts as equal
+        #[arg(long, default_value_t = 0.0)]
+        tolerance: f64,
+
+        /// Parameters to leave out, where `*` matches any characters
+        #[arg(long)]
+        ignore: Vec<String>,
+    },
+
+    /// Start an interactive shell on a single connection
+    Shell,
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let mut client = NetcomClientSync::new(&cli.host, cli.port);
+
+    match run(&cli, &mut client) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("netcom-cli: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(cli: &Cli, client: &mut NetcomClientSync) -> Result<ExitCode, Box<dyn Error>> {
+    match &cli.command {
+        Command::Devices => {
+            let mut table = Table::new(&["id", "network", "name", "type", "description"]);
+            for d in client.get_device_list()? {
+                table.push(vec![
+                    json!(d.id),
+                    json!(d.network),
+                    json!(d.name),
+                    json!(d.device_type),
+                    json!(d.description),
+                ]);
+            }
+            print!("{}", table.render(cli.format));
+        }
+        Command::Read { device, params } => {
+            let order: Vec<String> = params.iter().map(|op| op.address().to_string()).collect();
+            let result = client.read_parameters(device, params.clone())?;
+            print!("{}", result_table(&order, &result, None).render(cli.format));
+        }
+        Command::Write { device, params } => {
+            let order: Vec<String> = params.iter().map(|op| op.address().to_string()).collect();
+            let result = client.write_parameters(device, params.clone())?;
+            print!("{}", result_table(&order, &result, None).render(cli.format));
+        }
+        Command::Watch {
+            device,
+            params,
+            interval,
+            count,
+        } => {
+            let order: Vec<String> = params.iter().map(|op| op.address().to_string()).collect();
+            let mut n = 0;
+            loop {
+                let result = client.read_parameters(device, params.clone())?;
+                let time = Local::now().to_rfc3339_opts(SecondsFormat::Millis, false);
+                let table = result_table(&order, &result, Some(&time));
+                if n == 0 {
+                    print!("{}", table.render(cli.format));
+                } else {
+                    print!("{}", table.render_rows(cli.format));
+                }
+
+                n += 1;
+                if count.is_some_and(|count| n >= count) {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(*interval));
+            }
+        }
+        Command::Info => {
+            client.connect()?;
+            let mut table = Table::new(&["host", "port", "version"]);
+            table.push(vec![
+                json!(cli.host),
+                json!(cli.port),
+                json!(client.version()),
+            ]);
+            print!("{}", table.render(cli.format));
+        }
+        Command::Diff {
+            left,
+            right,
+            params,
+            tolerance,
+            ignore,
+        } => {
+            let options = DiffOptions {
+                tolerance: *tolerance,
+                ignore: ignore.clone(),
+            };
+            if compare::run(client, left, right, params, &options, cli.format)? {
+                return Ok(ExitCode::from(1));
+            }
+        }
+        Command::Shell => shell::run(client, cli.format)?,
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// One row per requested parameter, in the order given on the command line.
+fn result_table(
+    order: &[String],
+    result: &HashMap<String, Option<f64>>,
+    time: Option<&str>,
+) -> Table {
+    let mut table = match time {
+        Some(_) => Table::new(&["time", "param", "value"]),
+        None => Table::new(&["param", "value"]),
+    };
+
+    for p in order {
+        let value = match result.get(p) {
+            Some(Some(v)) => json!(v),
+            _ => Value::Null,
+        };
+        let mut row = vec![json!(p), value];
+        if let Some(time) = time {
+            row.insert(0, json!(time));
+        }
+        table.push(row);
+    }
+    table
+}
@@ -0,0 +1,175 @@
+use clap::ValueEnum;
+use serde_json::{Map, Value};
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Format {
+    Table,
+    Json,
+    Csv,
+}
+
+/// Rows of command output, rendered as an aligned table, a JSON array of
+/// objects or CSV.
+pub struct Table {
+    headers: Vec<&'static str>,
+    rows: Vec<Vec<Value>>,
+}
+
+impl Table {
+    pub fn new(headers: &[&'static str]) -> Self {
+        Table {
+            headers: headers.to_vec(),
+            rows: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, row: Vec<Value>) {
+        self.rows.push(row);
+    }
+
+    pub fn render(&self, format: Format) -> String {
+        match format {
+            Format::Table => self.render_table(true),
+            Format::Json => self.render_json(),
+            Format::Csv => self.render_csv(true),
+        }
+    }
+
+    /// Renders the rows only, for output that is appended to over time.
+    /// JSON is written as one array per call.
+    pub fn render_rows(&self, format: Format) -> String {
+        match format {
+            Format::Table => self.render_table(false),
+            Format::Json => self.render_json(),
+            Format::Csv => self.render_csv(false),
+        }
+    }
+
+    fn render_table(&self, with_header: bool) -> String {
+        let cells: Vec<Vec<String>> = self
+            .rows
+            .iter()
+            .map(|row| row.iter().map(cell_text).collect())
+            .collect();
+
+        let widths: Vec<usize> = self
+            .headers
+            .iter()
+            .enumerate()
+            .map(|(i, h)| {
+                cells
+                    .iter()
+                    .map(|row| row[i].len())
+                    .chain(std::iter::once(h.len()))
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+
+        let mut out = String::new();
+        let mut line = |row: &[String]| {
+            let padded: Vec<String> = row
+                .iter()
+                .zip(&widths)
+                .map(|(c, w)| format!("{:<w$}", c, w = w))
+                .collect();
+            out.push_str(padded.join("  ").trim_end());
+            out.push('\n');
+        };
+
+        if with_header {
+            let headers: Vec<String> = self.headers.iter().map(|h| h.to_string()).collect();
+            line(&headers);
+        }
+        for row in &cells {
+            line(row);
+        }
+        out
+    }
+
+    fn render_json(&self) -> String {
+        let objects: Vec<Value> = self
+            .rows
+            .iter()
+            .map(|row| {
+                let object: Map<String, Value> = self
+                    .headers
+                    .iter()
+                    .zip(row)
+                    .map(|(h, v)| (h.to_string(), v.clone()))
+                    .collect();
+                Value::Object(object)
+            })
+            .collect();
+        format!("{}\n", Value::Array(objects))
+    }
+
+    fn render_csv(&self, with_header: bool) -> String {
+        let mut out = String::new();
+        if with_header {
+            out.push_str(&self.headers.join(","));
+            out.push('\n');
+        }
+        for row in &self.rows {
+            let fields: Vec<String> = row
+                .iter()
+                .map(|v| match v {
+                    Value::Null => String::new(),
+                    v => csv_escape(&cell_text(v)),
+                })
+                .collect();
+            out.push_str(&fields.join(","));
+            out.push('\n');
+        }
+        out
+    }
+}
+
+fn cell_text(value: &Value) -> String {
+    match value {
+        Value::Null => "-".to_string(),
+        Value::String(s) => s.clone(),
+        v => v.to_string(),
+    }
+}
+
+fn csv_escape(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn sample() -> Table {
+        let mut table = Table::new(&["param", "value"]);
+        table.push(vec![json!("temp"), json!(21.5)]);
+        table.push(vec![json!("mode, set"), Value::Null]);
+        table
+    }
+
+    #[test]
+    fn should_render_aligned_table() {
+        assert_eq!(
+            sample().render(Format::Table),
+            "param      value\ntemp       21.5\nmode, set  -\n"
+        );
+    }
+
+    #[test]
+    fn should_render_csv_and_json() {
+        assert_eq!(
+            sample().render(Format::Csv),
+            "param,value\ntemp,21.5\n\"mode, set\",\n"
+        );
+        assert_eq!(
+            sample().render(Format::Json),
+            "[{\"param\":\"temp\",\"value\":21.5},{\"param\":\"mode, set\",\"value\":null}]\n"
+        );
+    }
+}
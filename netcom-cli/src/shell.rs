@@ -0,0 +1,303 @@
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use std::path::PathBuf;
+
+use netcom::netcom::{NetcomError, RdOp, WrOp};
+use netcom::netcom_client_sync::NetcomClientSync;
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use serde_json::{json, Value};
+
+use crate::output::{Format, Table};
+use crate::spec;
+
+const COMMANDS: [&str; 8] = [
+    "devices", "exit", "help", "info", "quit", "read", "use", "write",
+];
+
+const HELP: &str = "\
+devices                        list devices
+use <device>                   read and write <device> by default
+use                            clear the default device
+read [device] param[:type]...  read parameters
+write [device] p=v[:type]...   write parameters
+info                           show the server version
+exit, quit                     leave the shell";
+
+/// Shell state that completion depends on: the known devices, the
+/// parameter addresses used so far and the device selected with `use`.
+#[derive(Default)]
+struct ShellHelper {
+    devices: Vec<String>,
+    params: BTreeSet<String>,
+    device: Option<String>,
+}
+
+impl ShellHelper {
+    fn candidates(&self, line: &str, pos: usize) -> (usize, Vec<String>) {
+        let line = &line[..pos];
+        let start = line.rfind(' ').map_or(0, |i| i + 1);
+        let word = &line[start..];
+        let words: Vec<&str> = line[..start].split_whitespace().collect();
+
+        let pool: Vec<&str> = match words.as_slice() {
+            [] => COMMANDS.to_vec(),
+            ["use"] => self.devices.iter().map(String::as_str).collect(),
+            ["read" | "write"] if self.device.is_none() => {
+                self.devices.iter().map(String::as_str).collect()
+            }
+            ["read" | "write", ..] => self.params.iter().map(String::as_str).collect(),
+            _ => vec![],
+        };
+
+        let matches = pool
+            .into_iter()
+            .filter(|c| c.starts_with(word))
+            .map(String::from)
+            .collect();
+        (start, matches)
+    }
+
+    fn prompt(&self) -> String {
+        match &self.device {
+            Some(device) => format!("netcom:{}> ", device),
+            None => "netcom> ".to_string(),
+        }
+    }
+}
+
+impl Completer for ShellHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(self.candidates(line, pos))
+    }
+}
+
+impl Hinter for ShellHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ShellHelper {}
+
+impl Validator for ShellHelper {}
+
+impl Helper for ShellHelper {}
+
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".netcom_history"))
+}
+
+pub fn run(client: &mut NetcomClientSync, format: Format) -> Result<(), Box<dyn Error>> {
+    let mut editor: Editor<ShellHelper, DefaultHistory> = Editor::new()?;
+    editor.set_helper(Some(ShellHelper::default()));
+
+    let history = history_path();
+    if let Some(path) = &history {
+        let _ = editor.load_history(path);
+    }
+
+    client.connect()?;
+    if let Some(helper) = editor.helper_mut() {
+        helper.devices = device_names(client)?;
+    }
+    println!(
+        "Connected to netcom server {}. Type `help` for commands.",
+        client.version().unwrap_or("(unknown version)")
+    );
+
+    loop {
+        let prompt = editor.helper().map(|h| h.prompt()).unwrap_or_default();
+        let line = match editor.readline(&prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        editor.add_history_entry(line)?;
+
+        let helper = editor.helper_mut().unwrap();
+        match execute(helper, client, line, format) {
+            Ok(true) => break,
+            Ok(false) => {}
+            Err(e) => {
+                println!("error: {}", e);
+                // The client drops a connection left out of step with the
+                // server and opens a new one on the next request.
+                if !client.is_connected() {
+                    println!("Connection closed, the next command reconnects.");
+                }
+            }
+        }
+    }
+
+    if let Some(path) = &history {
+        let _ = editor.save_history(path);
+    }
+    Ok(())
+}
+
+fn device_names(client: &mut NetcomClientSync) -> Result<Vec<String>, NetcomError> {
+    let mut names: Vec<String> = client
+        .get_device_list()?
+        .into_iter()
+        .map(|d| d.name)
+        .collect();
+    names.sort();
+    Ok(names)
+}
+
+/// Runs one shell command. Returns `true` when the shell should exit.
+fn execute(
+    helper: &mut ShellHelper,
+    client: &mut NetcomClientSync,
+    line: &str,
+    format: Format,
+) -> Result<bool, Box<dyn Error>> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+
+    match words.as_slice() {
+        ["exit" | "quit"] => return Ok(true),
+        ["help"] => println!("{}", HELP),
+        ["info"] => println!("{}", client.version().unwrap_or("(unknown version)")),
+        ["devices"] => {
+            let mut table = Table::new(&["id", "name", "type", "description"]);
+            let devices = client.get_device_list()?;
+            helper.devices = devices.iter().map(|d| d.name.clone()).collect();
+            helper.devices.sort();
+            for d in devices {
+                table.push(vec![
+                    json!(d.id),
+                    json!(d.name),
+                    json!(d.device_type),
+                    json!(d.description),
+                ]);
+            }
+            print!("{}", table.render(format));
+        }
+        ["use"] => helper.device = None,
+        ["use", device] => {
+            if !helper.devices.iter().any(|d| d == device) {
+                return Err(NetcomError::DeviceNotFound.into());
+            }
+            helper.device = Some(device.to_string());
+        }
+        ["read", args @ ..] => {
+            let (device, args) = split_device(helper, args)?;
+            let ops = args
+                .iter()
+                .map(|a| spec::parse_read(a))
+                .collect::<Result<Vec<RdOp>, String>>()?;
+            let order: Vec<String> = ops.iter().map(|op| op.address().to_string()).collect();
+
+            let result = client.read_parameters(&device, ops)?;
+            helper.params.extend(order.iter().cloned());
+            print!("{}", result_table(&order, &result).render(format));
+        }
+        ["write", args @ ..] => {
+            let (device, args) = split_device(helper, args)?;
+            let ops = args
+                .iter()
+                .map(|a| spec::parse_write(a))
+                .collect::<Result<Vec<WrOp>, String>>()?;
+            let order: Vec<String> = ops.iter().map(|op| op.address().to_string()).collect();
+
+            let result = client.write_parameters(&device, ops)?;
+            helper.params.extend(order.iter().cloned());
+            print!("{}", result_table(&order, &result).render(format));
+        }
+        _ => return Err(format!("Unknown command {:?}, type `help`", line).into()),
+    }
+
+    Ok(false)
+}
+
+/// Takes the device from the `use` context, or else from the first argument.
+fn split_device<'a>(
+    helper: &ShellHelper,
+    args: &'a [&'a str],
+) -> Result<(String, &'a [&'a str]), String> {
+    let (device, args) = match (&helper.device, args) {
+        (Some(device), args) => (device.clone(), args),
+        (None, [device, args @ ..]) => (device.to_string(), args),
+        (None, []) => return Err("No device given and none selected with `use`".to_string()),
+    };
+
+    if args.is_empty() {
+        return Err("No parameters given".to_string());
+    }
+    Ok((device, args))
+}
+
+/// Like the one-shot output, with a status column that calls out parameters
+/// the server returned no value for.
+fn result_table(order: &[String], result: &HashMap<String, Option<f64>>) -> Table {
+    let mut table = Table::new(&["param", "value", "status"]);
+    for p in order {
+        let (value, status) = match result.get(p) {
+            Some(Some(v)) => (json!(v), "ok"),
+            Some(None) => (Value::Null, "failed"),
+            None => (Value::Null, "missing"),
+        };
+        table.push(vec![json!(p), value, json!(status)]);
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn helper() -> ShellHelper {
+        ShellHelper {
+            devices: vec!["boiler-1".to_string(), "pump-3".to_string()],
+            params: ["speed", "setpoint"]
+                .iter()
+                .map(|p| p.to_string())
+                .collect(),
+            device: None,
+        }
+    }
+
+    #[test]
+    fn should_complete_commands_devices_and_params() {
+        let mut h = helper();
+        assert_eq!(h.candidates("re", 2), (0, vec!["read".to_string()]));
+        assert_eq!(h.candidates("use p", 5), (4, vec!["pump-3".to_string()]));
+        assert_eq!(h.candidates("read b", 6), (5, vec!["boiler-1".to_string()]));
+        assert_eq!(
+            h.candidates("read pump-3 s", 13),
+            (12, vec!["setpoint".to_string(), "speed".to_string()])
+        );
+
+        h.device = Some("pump-3".to_string());
+        assert_eq!(h.candidates("write sp", 8), (6, vec!["speed".to_string()]));
+    }
+
+    #[test]
+    fn should_take_device_from_context_or_first_argument() {
+        let mut h = helper();
+        let (device, args) = split_device(&h, &["pump-3", "speed"]).unwrap();
+        assert_eq!((device.as_str(), args), ("pump-3", &["speed"][..]));
+        assert!(split_device(&h, &["pump-3"]).is_err());
+
+        h.device = Some("boiler-1".to_string());
+        let (device, args) = split_device(&h, &["speed"]).unwrap();
+        assert_eq!((device.as_str(), args), ("boiler-1", &["speed"][..]));
+    }
+}
@@ -0,0 +1,82 @@
+use netcom::netcom::{RdOp, WrOp};
+
+/// Parses `param[:type]` into a read operation.
+pub fn parse_read(spec: &str) -> Result<RdOp, String> {
+    match spec.split_once(':') {
+        Some((p, t)) if !p.is_empty() && !t.is_empty() => Ok(RdOp::WithType {
+            p: p.to_string(),
+            t: t.to_string(),
+        }),
+        None if !spec.is_empty() => Ok(RdOp::Default {
+            p: spec.to_string(),
+        }),
+        _ => Err(format!(
+            "Invalid parameter {:?}, expected param[:type]",
+            spec
+        )),
+    }
+}
+
+/// Parses `param=value[:type]` into a write operation.
+pub fn parse_write(spec: &str) -> Result<WrOp, String> {
+    let invalid = || format!("Invalid assignment {:?}, expected param=value[:type]", spec);
+
+    let (p, rest) = spec.split_once('=').ok_or_else(invalid)?;
+    if p.is_empty() {
+        return Err(invalid());
+    }
+
+    let (value, t) = match rest.split_once(':') {
+        Some((value, t)) if !t.is_empty() => (value, Some(t)),
+        Some(_) => return Err(invalid()),
+        None => (rest, None),
+    };
+    let v: f64 = value.trim().parse().map_err(|_| invalid())?;
+
+    Ok(match t {
+        Some(t) => WrOp::WithType {
+            p: p.to_string(),
+            t: t.to_string(),
+            v,
+        },
+        None => WrOp::Default {
+            p: p.to_string(),
+            v,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_parse_read_specs() {
+        assert!(matches!(
+            parse_read("temp").unwrap(),
+            RdOp::Default { p } if p == "temp"
+        ));
+        assert!(matches!(
+            parse_read("temp:i16").unwrap(),
+            RdOp::WithType { p, t } if p == "temp" && t == "i16"
+        ));
+        assert!(parse_read("").is_err());
+        assert!(parse_read("temp:").is_err());
+    }
+
+    #[test]
+    fn should_parse_write_specs() {
+        assert!(matches!(
+            parse_write("sp=21.5").unwrap(),
+            WrOp::Default { p, v } if p == "sp" && v == 21.5
+        ));
+        assert!(matches!(
+            parse_write("mode=2:u16").unwrap(),
+            WrOp::WithType { p, t, v } if p == "mode" && t == "u16" && v == 2.0
+        ));
+        assert!(parse_write("sp").is_err());
+        assert!(parse_write("=1").is_err());
+        assert!(parse_write("sp=abc").is_err());
+        assert!(parse_write("sp=1:").is_err());
+    }
+}
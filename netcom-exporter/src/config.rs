@@ -0,0 +1,143 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+pub use netcom::config::ConfigError;
+use netcom::netcom::RdOp;
+use serde::Deserialize;
+
+/// Exporter configuration, usually loaded from a TOML file:
+///
+/// ```toml
+/// interval_ms = 5000
+///
+/// [[metric]]
+/// name = "boiler_temperature_celsius"
+/// help = "Boiler water temperature"
+/// device = "boiler-1"
+/// param = "temp"
+/// type = "i16"
+/// ```
+///
+/// Every sample is labelled with its device and parameter, so several
+/// mappings may share a metric name.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default = "default_interval_ms")]
+    pub interval_ms: u64,
+
+    #[serde(default, rename = "metric")]
+    pub metrics: Vec<MetricConfig>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MetricConfig {
+    pub name: String,
+    pub help: Option<String>,
+    pub device: String,
+    pub param: String,
+    #[serde(rename = "type")]
+    pub param_type: Option<String>,
+}
+
+fn default_interval_ms() -> u64 {
+    5000
+}
+
+impl Config {
+    pub fn parse(s: &str) -> Result<Config, ConfigError> {
+        let config: Config = netcom::config::parse(s)?;
+        if let Some(m) = config.metrics.iter().find(|m| !is_valid_name(&m.name)) {
+            return Err(ConfigError::Invalid(format!(
+                "invalid metric name {:?}",
+                m.name
+            )));
+        }
+        Ok(config)
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_millis(self.interval_ms)
+    }
+
+    /// The mappings grouped by device, so that each device is polled with a
+    /// single read request.
+    pub fn by_device(&self) -> BTreeMap<&str, Vec<&MetricConfig>> {
+        let mut devices: BTreeMap<&str, Vec<&MetricConfig>> = BTreeMap::new();
+        for m in &self.metrics {
+            devices.entry(&m.device).or_default().push(m);
+        }
+        devices
+    }
+}
+
+impl MetricConfig {
+    pub fn rdop(&self) -> RdOp {
+        match &self.param_type {
+            Some(t) => RdOp::WithType {
+                p: self.param.clone(),
+                t: t.clone(),
+            },
+            None => RdOp::Default {
+                p: self.param.clone(),
+            },
+        }
+    }
+}
+
+/// Prometheus metric names match `[a-zA-Z_:][a-zA-Z0-9_:]*`.
+fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_' || c == ':')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_parse_config() {
+        let config = Config::parse(
+            r#"
+            [[metric]]
+            name = "boiler_temperature_celsius"
+            device = "boiler-1"
+            param = "temp"
+            type = "i16"
+
+            [[metric]]
+            name = "pump_speed_rpm"
+            help = "Pump speed"
+            device = "pump-3"
+            param = "speed"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.interval(), Duration::from_secs(5));
+        assert_eq!(config.metrics.len(), 2);
+        assert!(matches!(
+            config.metrics[0].rdop(),
+            RdOp::WithType { p, t } if p == "temp" && t == "i16"
+        ));
+        assert_eq!(
+            config.by_device().into_keys().collect::<Vec<_>>(),
+            ["boiler-1", "pump-3"]
+        );
+    }
+
+    #[test]
+    fn should_reject_invalid_metric_names() {
+        let config = "[[metric]]\nname = \"9lives\"\ndevice = \"d\"\nparam = \"p\"";
+        assert!(matches!(
+            Config::parse(config),
+            Err(ConfigError::Invalid(s)) if s.contains("\"9lives\"")
+        ));
+        assert!(matches!(
+            Config::parse("interval = 1"),
+            Err(ConfigError::Parse(_))
+        ));
+    }
+}
@@ -0,0 +1,186 @@
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use axum::extract::State;
+use axum::http::header;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Router;
+use netcom::netcom_client_async::NetcomClientAsync;
+use tokio::time::MissedTickBehavior;
+
+use crate::config::Config;
+use crate::metrics::Metrics;
+
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Polls the parameters listed in a `Config` and keeps `Metrics` up to date.
+///
+/// Each device is read with one request per interval. Errors are counted by
+/// kind and remove the samples of the device until the next good read; a
+/// broken connection is dropped and re-established on the next poll.
+pub struct Exporter {
+    client: NetcomClientAsync,
+    config: Config,
+    metrics: Arc<Mutex<Metrics>>,
+}
+
+impl Exporter {
+    pub fn new(client: NetcomClientAsync, config: Config) -> Self {
+        let mut metrics = Metrics::default();
+        for m in &config.metrics {
+            if let Some(help) = &m.help {
+                metrics.set_help(&m.name, help);
+            }
+        }
+
+        Exporter {
+            client,
+            config,
+            metrics: Arc::new(Mutex::new(metrics)),
+        }
+    }
+
+    pub fn metrics(&self) -> Arc<Mutex<Metrics>> {
+        self.metrics.clone()
+    }
+
+    /// A router serving the metrics on `/metrics`.
+    pub fn router(&self) -> Router {
+        Router::new()
+            .route("/metrics", get(render))
+            .with_state(self.metrics())
+    }
+
+    /// Reads every configured device once.
+    pub async fn poll(&mut self) {
+        if !self.client.is_connected() {
+            if let Err(e) = self.client.connect().await {
+                let mut metrics = self.metrics.lock().unwrap();
+                metrics.record_error(&e);
+                metrics.set_up(false);
+                for m in &self.config.metrics {
+                    metrics.set_value(&m.name, &m.device, &m.param, None);
+                }
+                return;
+            }
+        }
+
+        for (device, mappings) in self.config.by_device() {
+            let rdops = mappings.iter().map(|m| m.rdop()).collect();
+            let start = Instant::now();
+            let result = self.client.read_parameters(device, rdops).await;
+            let latency = start.elapsed();
+
+            let mut metrics = self.metrics.lock().unwrap();
+            metrics.record_request(latency);
+            if let Err(e) = &result {
+                metrics.record_error(e);
+            }
+            for m in mappings {
+                let value = match &result {
+                    Ok(values) => values.get(&m.param).copied().flatten(),
+                    Err(_) => None,
+                };
+                metrics.set_value(&m.name, &m.device, &m.param, value);
+            }
+        }
+
+        // Requests reconnect on their own after the client dropped a broken
+        // connection, so both follow from the client once the poll is done.
+        let mut metrics = self.metrics.lock().unwrap();
+        metrics.set_up(self.client.is_connected());
+        metrics.set_reconnects(self.client.connect_count().saturating_sub(1));
+    }
+
+    /// Polls at the configured interval, forever.
+    pub async fn run(&mut self) {
+        let mut ticker = tokio::time::interval(self.config.interval());
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        loop {
+            ticker.tick().await;
+            self.poll().await;
+        }
+    }
+}
+
+async fn render(State(metrics): State<Arc<Mutex<Metrics>>>) -> impl IntoResponse {
+    let body = metrics.lock().unwrap().render();
+    ([(header::CONTENT_TYPE, CONTENT_TYPE)], body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use netcom::mock_server::MockServer;
+
+    const CONFIG: &str = r#"
+        [[metric]]
+        name = "pump_speed_rpm"
+        device = "pump-3"
+        param = "speed"
+
+        [[metric]]
+        name = "pump_speed_rpm"
+        device = "pump-4"
+        param = "speed"
+    "#;
+
+    #[tokio::test]
+    async fn should_export_polled_values_and_errors() {
+        let server = MockServer::start().with_device("pump-3", &[("speed", 1200.0)]);
+        let client = NetcomClientAsync::new("127.0.0.1", server.port());
+        let mut exporter = Exporter::new(client, Config::parse(CONFIG).unwrap());
+
+        exporter.poll().await;
+        server.set_value("pump-3", "speed", 900.0);
+        exporter.poll().await;
+
+        let text = exporter.metrics().lock().unwrap().render();
+        assert!(text.contains("pump_speed_rpm{device=\"pump-3\",param=\"speed\"} 900\n"));
+        assert!(!text.contains("device=\"pump-4\""));
+        assert!(text.contains("netcom_up 1\n"));
+        assert!(text.contains("netcom_request_duration_seconds_count 4\n"));
+        assert!(text.contains("netcom_errors_total{kind=\"device_not_found\"} 2\n"));
+        assert!(text.contains("netcom_reconnects_total 0\n"));
+    }
+
+    #[tokio::test]
+    async fn should_count_implicit_reconnects() {
+        let server = MockServer::start().with_device("pump-3", &[("speed", 1200.0)]);
+        let client = NetcomClientAsync::new("127.0.0.1", server.port());
+        let mut exporter = Exporter::new(client, Config::parse(CONFIG).unwrap());
+        exporter.poll().await;
+
+        // A request after a dropped connection reconnects by itself.
+        exporter.client.disconnect();
+        let rdops = vec![netcom::netcom::RdOp::Default {
+            p: "speed".to_string(),
+        }];
+        exporter
+            .client
+            .read_parameters("pump-3", rdops)
+            .await
+            .unwrap();
+        exporter.poll().await;
+
+        let text = exporter.metrics().lock().unwrap().render();
+        assert!(text.contains("netcom_reconnects_total 1\n"));
+        assert!(text.contains("netcom_up 1\n"));
+    }
+
+    #[tokio::test]
+    async fn should_count_failed_connections() {
+        let port = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap().port()
+        };
+        let client = NetcomClientAsync::new("127.0.0.1", port);
+        let mut exporter = Exporter::new(client, Config::parse(CONFIG).unwrap());
+
+        exporter.poll().await;
+        let text = exporter.metrics().lock().unwrap().render();
+        assert!(text.contains("netcom_up 0\n"));
+        assert!(text.contains("netcom_errors_total{kind=\"stream\"} 1\n"));
+    }
+}
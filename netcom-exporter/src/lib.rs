@@ -0,0 +1,4 @@
+//! Exports netcom device parameters and client health as Prometheus metrics.
+pub mod config;
+pub mod exporter;
+pub mod metrics;
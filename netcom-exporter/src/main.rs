@@ -0,0 +1,51 @@
+use std::error::Error;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use clap::Parser;
+use netcom::netcom::DEFAULT_PORT;
+use netcom::netcom_client_async::NetcomClientAsync;
+use netcom_exporter::config::Config;
+use netcom_exporter::exporter::Exporter;
+
+#[derive(Parser)]
+#[command(
+    name = "netcom-exporter",
+    version,
+    about = "Prometheus exporter for netcom device parameters"
+)]
+struct Cli {
+    /// TOML file mapping device parameters to metrics
+    config: PathBuf,
+
+    /// Address to serve `/metrics` on
+    #[arg(long, env = "NETCOM_EXPORTER_LISTEN", default_value = "127.0.0.1:9187")]
+    listen: SocketAddr,
+
+    /// Server hostname
+    #[arg(long, env = "NETCOM_HOST", default_value = "localhost")]
+    host: String,
+
+    /// Server port
+    #[arg(long, env = "NETCOM_PORT", default_value_t = DEFAULT_PORT)]
+    port: u16,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let cli = Cli::parse();
+    let config = Config::parse(&std::fs::read_to_string(&cli.config)?)?;
+
+    let client = NetcomClientAsync::new(&cli.host, cli.port);
+    let mut exporter = Exporter::new(client, config);
+    let app = exporter.router();
+    tokio::spawn(async move { exporter.run().await });
+
+    let listener = tokio::net::TcpListener::bind(cli.listen).await?;
+    println!(
+        "Serving metrics on http://{}/metrics",
+        listener.local_addr()?
+    );
+    axum::serve(listener, app).await?;
+    Ok(())
+}
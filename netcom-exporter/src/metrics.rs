@@ -0,0 +1,212 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::Duration;
+
+use netcom::netcom::NetcomError;
+
+/// Upper bounds of the request latency histogram, in seconds.
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+#[derive(Default)]
+struct Histogram {
+    /// Cumulative counts per bucket in `LATENCY_BUCKETS`.
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(self.buckets.iter_mut()) {
+            if value <= *bound {
+                *count += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+/// Device values and client health, rendered in the Prometheus text format.
+///
+/// Device values are gauges labelled with `device` and `param`. A parameter
+/// that could not be read has no sample, rather than a stale one.
+#[derive(Default)]
+pub struct Metrics {
+    help: BTreeMap<String, String>,
+    values: BTreeMap<String, BTreeMap<(String, String), f64>>,
+    latency: Histogram,
+    reconnects: u64,
+    errors: BTreeMap<&'static str, u64>,
+    up: bool,
+}
+
+impl Metrics {
+    pub fn set_help(&mut self, name: &str, help: &str) {
+        self.help.insert(name.to_string(), help.to_string());
+    }
+
+    /// Sets a sample of `name`, or removes it when `value` is `None`.
+    pub fn set_value(&mut self, name: &str, device: &str, param: &str, value: Option<f64>) {
+        let key = (device.to_string(), param.to_string());
+        match value {
+            Some(v) => {
+                self.values
+                    .entry(name.to_string())
+                    .or_default()
+                    .insert(key, v);
+            }
+            None => {
+                if let Some(samples) = self.values.get_mut(name) {
+                    samples.remove(&key);
+                }
+            }
+        }
+    }
+
+    pub fn record_request(&mut self, latency: Duration) {
+        self.latency.observe(latency.as_secs_f64());
+    }
+
+    pub fn record_error(&mut self, e: &NetcomError) {
+        *self.errors.entry(e.kind()).or_default() += 1;
+    }
+
+    pub fn set_reconnects(&mut self, reconnects: u64) {
+        self.reconnects = reconnects;
+    }
+
+    pub fn set_up(&mut self, up: bool) {
+        self.up = up;
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        for (name, samples) in &self.values {
+            if let Some(help) = self.help.get(name) {
+                let _ = writeln!(out, "# HELP {} {}", name, escape_help(help));
+            }
+            let _ = writeln!(out, "# TYPE {} gauge", name);
+            for ((device, param), v) in samples {
+                let _ = writeln!(
+                    out,
+                    "{}{{device=\"{}\",param=\"{}\"}} {}",
+                    name,
+                    escape_label(device),
+                    escape_label(param),
+                    format_float(*v)
+                );
+            }
+        }
+
+        out.push_str("# HELP netcom_up Whether the last poll reached the server.\n");
+        out.push_str("# TYPE netcom_up gauge\n");
+        let _ = writeln!(out, "netcom_up {}", u8::from(self.up));
+
+        out.push_str("# HELP netcom_request_duration_seconds Latency of read requests.\n");
+        out.push_str("# TYPE netcom_request_duration_seconds histogram\n");
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(self.latency.buckets) {
+            let _ = writeln!(
+                out,
+                "netcom_request_duration_seconds_bucket{{le=\"{}\"}} {}",
+                bound, count
+            );
+        }
+        let _ = writeln!(
+            out,
+            "netcom_request_duration_seconds_bucket{{le=\"+Inf\"}} {}",
+            self.latency.count
+        );
+        let _ = writeln!(
+            out,
+            "netcom_request_duration_seconds_sum {}",
+            format_float(self.latency.sum)
+        );
+        let _ = writeln!(
+            out,
+            "netcom_request_duration_seconds_count {}",
+            self.latency.count
+        );
+
+        out.push_str(
+            "# HELP netcom_reconnects_total Connections re-established after a failure.\n",
+        );
+        out.push_str("# TYPE netcom_reconnects_total counter\n");
+        let _ = writeln!(out, "netcom_reconnects_total {}", self.reconnects);
+
+        out.push_str("# HELP netcom_errors_total Failed requests by error kind.\n");
+        out.push_str("# TYPE netcom_errors_total counter\n");
+        for (kind, count) in &self.errors {
+            let _ = writeln!(out, "netcom_errors_total{{kind=\"{}\"}} {}", kind, count);
+        }
+
+        out
+    }
+}
+
+/// Formats a sample value, spelling special values the way Prometheus
+/// parses them.
+fn format_float(v: f64) -> String {
+    if v.is_nan() {
+        "NaN".to_string()
+    } else if v == f64::INFINITY {
+        "+Inf".to_string()
+    } else if v == f64::NEG_INFINITY {
+        "-Inf".to_string()
+    } else {
+        v.to_string()
+    }
+}
+
+fn escape_help(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn escape_label(s: &str) -> String {
+    escape_help(s).replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_render_text_format() {
+        let mut metrics = Metrics::default();
+        metrics.set_help("pump_speed_rpm", "Pump speed");
+        metrics.set_value("pump_speed_rpm", "pump-3", "speed", Some(1200.0));
+        metrics.set_value("pump_speed_rpm", "pump-\"4\"", "speed", Some(900.5));
+        metrics.set_value("tank_level", "tank", "level", Some(1.0));
+        metrics.set_value("tank_level", "tank", "level", None);
+        metrics.record_request(Duration::from_millis(20));
+        metrics.record_error(&NetcomError::DeviceNotFound);
+        metrics.set_reconnects(1);
+
+        let text = metrics.render();
+        assert!(text.starts_with("# HELP pump_speed_rpm Pump speed\n# TYPE pump_speed_rpm gauge\n"));
+        assert!(text.contains("pump_speed_rpm{device=\"pump-3\",param=\"speed\"} 1200\n"));
+        assert!(text.contains("pump_speed_rpm{device=\"pump-\\\"4\\\"\",param=\"speed\"} 900.5\n"));
+        assert!(!text.contains("tank_level{"));
+        assert!(text.contains("netcom_request_duration_seconds_bucket{le=\"0.01\"} 0\n"));
+        assert!(text.contains("netcom_request_duration_seconds_bucket{le=\"0.025\"} 1\n"));
+        assert!(text.contains("netcom_request_duration_seconds_count 1\n"));
+        assert!(text.contains("netcom_reconnects_total 1\n"));
+        assert!(text.contains("netcom_errors_total{kind=\"device_not_found\"} 1\n"));
+    }
+
+    #[test]
+    fn should_render_special_values() {
+        let mut metrics = Metrics::default();
+        metrics.set_value("level", "tank-1", "level", Some(f64::NAN));
+        metrics.set_value("level", "tank-2", "level", Some(f64::INFINITY));
+        metrics.set_value("level", "tank-3", "level", Some(f64::NEG_INFINITY));
+
+        let text = metrics.render();
+        assert!(text.contains("level{device=\"tank-1\",param=\"level\"} NaN\n"));
+        assert!(text.contains("level{device=\"tank-2\",param=\"level\"} +Inf\n"));
+        assert!(text.contains("level{device=\"tank-3\",param=\"level\"} -Inf\n"));
+    }
+}
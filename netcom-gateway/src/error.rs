@@ -0,0 +1,111 @@
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use netcom::netcom::NetcomError;
+use serde_json::json;
+
+/// A `NetcomError` returned from a handler, rendered as
+/// `{"error": <kind>, "message": <text>}` with a matching status code. A
+/// write stopped by a dry-run guard is not a failure: it is rendered as
+/// `{"dry_run": true, "request": <write request>}` with 202 Accepted.
+pub struct ApiError(pub NetcomError);
+
+impl From<NetcomError> for ApiError {
+    fn from(e: NetcomError) -> Self {
+        ApiError(e)
+    }
+}
+
+pub fn status(e: &NetcomError) -> StatusCode {
+    match e {
+        NetcomError::DeviceNotFound => StatusCode::NOT_FOUND,
+        NetcomError::InvalidValue(_) => StatusCode::BAD_REQUEST,
+        NetcomError::WriteBlocked(_) => StatusCode::FORBIDDEN,
+        NetcomError::DryRun(_) => StatusCode::ACCEPTED,
+        NetcomError::AuditFailed { .. } | NetcomError::Tls(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        NetcomError::NotConnected | NetcomError::StreamError(_) => StatusCode::SERVICE_UNAVAILABLE,
+        NetcomError::NetstringError(_)
+        | NetcomError::JsonError(_)
+        | NetcomError::Utf8Error(_)
+        | NetcomError::ResponseError(_) => StatusCode::BAD_GATEWAY,
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = match &self.0 {
+            NetcomError::DryRun(req) => json!({ "dry_run": true, "request": req }),
+            e => json!({ "error": e.kind(), "message": e.to_string() }),
+        };
+        (status(&self.0), Json(body)).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use netcom::guard::GuardRule;
+    use netcom::guard::WriteRequestDto;
+    use netcom::netstring::NetstringError;
+    use std::collections::HashMap;
+    use std::io;
+
+    #[test]
+    fn should_give_every_error_a_status() {
+        let invalid_utf8 = vec![0xff];
+        let rule = GuardRule::Device {
+            device: "x".to_string(),
+        };
+        let cases = [
+            (NetcomError::NotConnected, StatusCode::SERVICE_UNAVAILABLE),
+            (
+                NetcomError::StreamError(io::ErrorKind::ConnectionReset.into()),
+                StatusCode::SERVICE_UNAVAILABLE,
+            ),
+            (
+                NetcomError::NetstringError(NetstringError::Malformed),
+                StatusCode::BAD_GATEWAY,
+            ),
+            (
+                NetcomError::JsonError(serde_json::from_str::<()>("x").unwrap_err()),
+                StatusCode::BAD_GATEWAY,
+            ),
+            (
+                NetcomError::Utf8Error(std::str::from_utf8(&invalid_utf8).unwrap_err()),
+                StatusCode::BAD_GATEWAY,
+            ),
+            (
+                NetcomError::ResponseError("x".to_string()),
+                StatusCode::BAD_GATEWAY,
+            ),
+            (NetcomError::DeviceNotFound, StatusCode::NOT_FOUND),
+            (
+                NetcomError::InvalidValue("x".to_string()),
+                StatusCode::BAD_REQUEST,
+            ),
+            (NetcomError::WriteBlocked(rule), StatusCode::FORBIDDEN),
+            (
+                NetcomError::DryRun(WriteRequestDto {
+                    r: "write".to_string(),
+                    device: "x".to_string(),
+                    p: HashMap::new(),
+                }),
+                StatusCode::ACCEPTED,
+            ),
+            (
+                NetcomError::AuditFailed {
+                    error: io::ErrorKind::StorageFull.into(),
+                    result: Box::new(Ok(HashMap::new())),
+                },
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+            (
+                NetcomError::Tls("x".to_string()),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+        ];
+        for (e, expected) in cases {
+            assert_eq!(status(&e), expected, "{}", e.kind());
+        }
+    }
+}
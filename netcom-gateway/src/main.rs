@@ -0,0 +1,47 @@
+mod error;
+mod routes;
+
+use std::error::Error;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use clap::Parser;
+use netcom::netcom::DEFAULT_PORT;
+use netcom::netcom_client_async::NetcomClientAsync;
+
+#[derive(Parser)]
+#[command(
+    name = "netcom-gateway",
+    version,
+    about = "HTTP gateway to a netcom server"
+)]
+struct Cli {
+    /// Address to serve HTTP on
+    #[arg(long, env = "NETCOM_GATEWAY_LISTEN", default_value = "127.0.0.1:8080")]
+    listen: SocketAddr,
+
+    /// Server hostname
+    #[arg(long, env = "NETCOM_HOST", default_value = "localhost")]
+    host: String,
+
+    /// Server port
+    #[arg(long, env = "NETCOM_PORT", default_value_t = DEFAULT_PORT)]
+    port: u16,
+
+    /// Polling interval for watched parameters in milliseconds
+    #[arg(long, default_value_t = 1000)]
+    watch_interval: u64,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let cli = Cli::parse();
+
+    let client = NetcomClientAsync::new(&cli.host, cli.port);
+    let app = routes::router(client, Duration::from_millis(cli.watch_interval));
+
+    let listener = tokio::net::TcpListener::bind(cli.listen).await?;
+    println!("Serving on http://{}", listener.local_addr()?);
+    axum::serve(listener, app).await?;
+    Ok(())
+}
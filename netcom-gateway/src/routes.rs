@@ -0,0 +1,284 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::extract::{Path, Query, State};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::routing::get;
+use axum::{Json, Router};
+use netcom::live::{LiveValue, LiveValues};
+use netcom::netcom::{NetcomError, RdOp, WrOp};
+use netcom::netcom_client_async::NetcomClientAsync;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::Mutex;
+use tokio_stream::{Stream, StreamExt, StreamMap};
+
+use crate::error::ApiError;
+
+type Values = HashMap<String, Option<f64>>;
+
+/// State shared by the handlers: one connection to the netcom server,
+/// locked for one request at a time, and the poller behind the watch
+/// endpoint, which uses the same connection.
+#[derive(Clone)]
+struct AppState {
+    client: Arc<Mutex<NetcomClientAsync>>,
+    live: Arc<LiveValues>,
+}
+
+impl AppState {
+    /// Runs `f` on the client, holding the lock for the whole request.
+    async fn with_client<T, F>(&self, f: F) -> Result<T, ApiError>
+    where
+        F: AsyncFnOnce(&mut NetcomClientAsync) -> Result<T, NetcomError>,
+    {
+        let mut client = self.client.lock().await;
+        Ok(f(&mut client).await?)
+    }
+}
+
+/// Query string of the read and watch endpoints: `p=a,b:type,...`.
+#[derive(Deserialize)]
+struct ParamsQuery {
+    p: String,
+}
+
+impl ParamsQuery {
+    fn rdops(&self) -> Result<Vec<RdOp>, ApiError> {
+        self.p
+            .split(',')
+            .map(|spec| match spec.split_once(':') {
+                Some((p, t)) if !p.is_empty() && !t.is_empty() => Ok(RdOp::WithType {
+                    p: p.to_string(),
+                    t: t.to_string(),
+                }),
+                None if !spec.is_empty() => Ok(RdOp::Default {
+                    p: spec.to_string(),
+                }),
+                _ => Err(NetcomError::InvalidValue(format!(
+                    "Invalid parameter {:?}, expected param[:type]",
+                    spec
+                ))
+                .into()),
+            })
+            .collect()
+    }
+}
+
+/// A value in the body of a write, either a number or `{"v": .., "t": ..}`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum WriteValue {
+    Simple(f64),
+    WithType { v: f64, t: String },
+}
+
+pub fn router(client: NetcomClientAsync, watch_interval: Duration) -> Router {
+    let client = Arc::new(Mutex::new(client));
+    let live = Arc::new(LiveValues::start_shared(client.clone(), watch_interval));
+
+    Router::new()
+        .route("/devices", get(list_devices))
+        .route("/devices/{name}/params", get(read_params).put(write_params))
+        .route("/devices/{name}/watch", get(watch_params))
+        .with_state(AppState { client, live })
+}
+
+async fn list_devices(State(state): State<AppState>) -> Result<Json<Value>, ApiError> {
+    let devices = state
+        .with_client(async |client| client.get_device_list().await)
+        .await?;
+    Ok(Json(json!(devices)))
+}
+
+async fn read_params(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Query(query): Query<ParamsQuery>,
+) -> Result<Json<Values>, ApiError> {
+    let rdops = query.rdops()?;
+    let result = state
+        .with_client(async |client| client.read_parameters(&name, rdops).await)
+        .await?;
+    Ok(Json(result))
+}
+
+async fn write_params(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Json(body): Json<HashMap<String, WriteValue>>,
+) -> Result<Json<Values>, ApiError> {
+    let wrops = body
+        .into_iter()
+        .map(|(p, value)| match value {
+            WriteValue::Simple(v) => WrOp::Default { p, v },
+            WriteValue::WithType { v, t } => WrOp::WithType { p, t, v },
+        })
+        .collect();
+    let result = state
+        .with_client(async |client| client.write_parameters(&name, wrops).await)
+        .await?;
+    Ok(Json(result))
+}
+
+/// Streams a `value` event `{"p": .., "v": ..}` whenever a watched value
+/// changes, starting with the first value read, and an `error` event
+/// `{"p": .., "error": kind, "message": ..}` when reading it fails. The
+/// parameters are read once up front so that an unknown device fails with
+/// 404 rather than a stream of errors.
+async fn watch_params(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Query(query): Query<ParamsQuery>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiError> {
+    let rdops = query.rdops()?;
+    let first = rdops.clone();
+    state
+        .with_client(async |client| client.read_parameters(&name, first).await)
+        .await?;
+
+    let mut streams = StreamMap::new();
+    for op in rdops {
+        let p = op.address().to_string();
+        streams.insert(p, state.live.stream_status(&name, op, 0.0));
+    }
+
+    let events = streams.filter_map(|(p, v)| {
+        let event = match v {
+            LiveValue::Pending => return None,
+            LiveValue::Value(v) => Event::default()
+                .event("value")
+                .data(json!({ "p": p, "v": v }).to_string()),
+            LiveValue::Failed { kind, message } => Event::default()
+                .event("error")
+                .data(json!({ "p": p, "error": kind, "message": message }).to_string()),
+        };
+        Some(Ok(event))
+    });
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::{Body, Bytes};
+    use axum::http::{Request, StatusCode};
+    use http_body_util::BodyExt;
+    use netcom::mock_server::MockServer;
+    use tower::ServiceExt;
+
+    fn app(server: &MockServer) -> Router {
+        let client = NetcomClientAsync::new("127.0.0.1", server.port());
+        router(client, Duration::from_millis(10))
+    }
+
+    async fn send(app: &Router, method: &str, uri: &str, body: &str) -> (StatusCode, Value) {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn should_list_devices() {
+        let server = MockServer::start()
+            .with_device("boiler-1", &[])
+            .with_device("pump-3", &[]);
+        let (status, body) = send(&app(&server), "GET", "/devices", "").await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body[0]["name"], "boiler-1");
+        assert_eq!(body[1]["name"], "pump-3");
+        assert_eq!(body[1]["type"], "mock");
+    }
+
+    #[tokio::test]
+    async fn should_read_and_write_params() {
+        let server = MockServer::start().with_device("pump-3", &[("speed", 1200.0), ("mode", 1.0)]);
+        let app = app(&server);
+
+        let (status, body) = send(&app, "GET", "/devices/pump-3/params?p=speed,mode", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!({ "speed": 1200.0, "mode": 1.0 }));
+
+        let write = r#"{"speed": 900, "mode": {"v": 2, "t": "u16"}}"#;
+        let (status, body) = send(&app, "PUT", "/devices/pump-3/params", write).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!({ "speed": 900.0, "mode": 2.0 }));
+        assert_eq!(server.value("pump-3", "mode"), Some(2.0));
+    }
+
+    #[tokio::test]
+    async fn should_map_errors_to_status_codes() {
+        let server = MockServer::start().with_device("pump-3", &[("speed", 1200.0)]);
+        let app = app(&server);
+
+        let (status, body) = send(&app, "GET", "/devices/missing/params?p=speed", "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["error"], "device_not_found");
+
+        let (status, body) = send(&app, "GET", "/devices/pump-3/params?p=speed:", "").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "invalid_value");
+
+        let (status, _) = send(&app, "GET", "/devices/missing/watch?p=speed", "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn should_stream_watched_values() {
+        let server = MockServer::start().with_device("tank", &[("level", 50.0)]);
+        let request = Request::get("/devices/tank/watch?p=level")
+            .body(Body::empty())
+            .unwrap();
+        // Kept alive for the poller, which the router owns.
+        let app = app(&server);
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let mut body = response.into_body();
+        let mut next_event = async || -> String {
+            loop {
+                let frame = body.frame().await.unwrap().unwrap();
+                let data: Bytes = frame.into_data().unwrap();
+                let text = String::from_utf8(data.to_vec()).unwrap();
+                if text.starts_with("event: value") {
+                    return text;
+                }
+            }
+        };
+
+        assert!(next_event().await.contains(r#"{"p":"level","v":50.0}"#));
+        server.set_value("tank", "level", 51.5);
+        assert!(next_event().await.contains(r#"{"p":"level","v":51.5}"#));
+    }
+
+    #[tokio::test]
+    async fn should_watch_typed_params_by_address() {
+        let server = MockServer::start().with_device("tank", &[("level", 50.0)]);
+        let request = Request::get("/devices/tank/watch?p=level:u16")
+            .body(Body::empty())
+            .unwrap();
+        let app = app(&server);
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let mut body = response.into_body();
+        loop {
+            let frame = body.frame().await.unwrap().unwrap();
+            let text = String::from_utf8(frame.into_data().unwrap().to_vec()).unwrap();
+            if text.contains(r#""v":50.0"#) {
+                assert!(text.contains(r#"{"p":"level","v":50.0}"#));
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use netcom::config::default_interval_ms;
+pub use netcom::config::{ConfigError, DeviceConfig};
+use serde::Deserialize;
+
+/// Logger configuration, usually loaded from a TOML file:
+///
+/// ```toml
+/// interval_ms = 1000
+/// format = "csv"
+/// directory = "logs"
+/// rotate = "daily"
+///
+/// [[device]]
+/// name = "pump-3"
+/// params = ["speed", "pressure"]
+/// types = { pressure = "i16" }
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default = "default_interval_ms")]
+    pub interval_ms: u64,
+
+    #[serde(default)]
+    pub format: Format,
+
+    #[serde(default = "default_directory")]
+    pub directory: PathBuf,
+
+    /// Start of every file name, followed by the rotation period.
+    #[serde(default = "default_prefix")]
+    pub prefix: String,
+
+    #[serde(default)]
+    pub rotate: Rotation,
+
+    #[serde(default, rename = "device")]
+    pub devices: Vec<DeviceConfig>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Csv,
+    Influx,
+}
+
+/// How often a new file is started. Files are named after the UTC period
+/// they cover, e.g. `netcom-20240131.csv` for daily rotation.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Rotation {
+    Never,
+    Hourly,
+    #[default]
+    Daily,
+}
+
+fn default_directory() -> PathBuf {
+    PathBuf::from(".")
+}
+
+fn default_prefix() -> String {
+    "netcom".to_string()
+}
+
+impl Config {
+    pub fn parse(s: &str) -> Result<Config, ConfigError> {
+        netcom::config::parse(s)
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_millis(self.interval_ms)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_parse_config() {
+        let config = Config::parse(
+            r#"
+            format = "influx"
+            rotate = "hourly"
+
+            [[device]]
+            name = "pump-3"
+            params = ["speed"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.format, Format::Influx);
+        assert_eq!(config.rotate, Rotation::Hourly);
+        assert_eq!(config.directory, PathBuf::from("."));
+        assert_eq!(config.devices[0].params, ["speed"]);
+        assert!(Config::parse("format = \"xml\"").is_err());
+    }
+}
@@ -0,0 +1,5 @@
+//! Logs netcom device parameters to rotating CSV or InfluxDB line-protocol
+//! files.
+pub mod config;
+pub mod logger;
+pub mod writer;
@@ -0,0 +1,120 @@
+use std::io;
+
+use chrono::Utc;
+use netcom::netcom_client_async::NetcomClientAsync;
+use tokio::time::MissedTickBehavior;
+
+use crate::config::Config;
+use crate::writer::{Reading, RotatingWriter, Sample};
+
+/// Polls the parameters listed in a `Config` and logs every reading.
+///
+/// Each device is read with one request per interval, and every listed
+/// parameter gets a row each time, so gaps in the data show up as `null` or
+/// `failed` rows rather than missing ones.
+pub struct Logger {
+    client: NetcomClientAsync,
+    config: Config,
+    writer: RotatingWriter,
+}
+
+impl Logger {
+    pub fn new(client: NetcomClientAsync, config: Config) -> Self {
+        let writer = RotatingWriter::new(&config);
+        Logger {
+            client,
+            config,
+            writer,
+        }
+    }
+
+    /// Reads every configured device once and flushes the rows to disk.
+    pub async fn poll(&mut self) -> io::Result<()> {
+        for device in &self.config.devices {
+            let result = self
+                .client
+                .read_parameters(&device.name, device.rdops())
+                .await;
+            let timestamp = Utc::now();
+
+            for p in &device.params {
+                let reading = match &result {
+                    Ok(values) => match values.get(p) {
+                        Some(Some(v)) => Reading::Value(*v),
+                        _ => Reading::Null,
+                    },
+                    Err(e) => Reading::Failed(e.kind()),
+                };
+                self.writer.write(&Sample {
+                    timestamp,
+                    device: &device.name,
+                    param: p,
+                    reading,
+                })?;
+            }
+        }
+        self.writer.flush()
+    }
+
+    /// Polls at the configured interval until writing fails.
+    pub async fn run(&mut self) -> io::Result<()> {
+        let mut ticker = tokio::time::interval(self.config.interval());
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        loop {
+            ticker.tick().await;
+            self.poll().await?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use netcom::mock_server::MockServer;
+
+    #[tokio::test]
+    async fn should_log_values_nulls_and_failures() {
+        let server = MockServer::start().with_device("pump-3", &[("speed", 1200.0)]);
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config::parse(&format!(
+            r#"
+            directory = {:?}
+            rotate = "never"
+
+            [[device]]
+            name = "pump-3"
+            params = ["speed", "torque"]
+
+            [[device]]
+            name = "boiler-1"
+            params = ["temp"]
+            "#,
+            dir.path()
+        ))
+        .unwrap();
+
+        let client = NetcomClientAsync::new("127.0.0.1", server.port());
+        let mut logger = Logger::new(client, config);
+        logger.poll().await.unwrap();
+        server.set_value("pump-3", "speed", 900.0);
+        logger.poll().await.unwrap();
+
+        let log = std::fs::read_to_string(dir.path().join("netcom.csv")).unwrap();
+        let rows: Vec<Vec<&str>> = log
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').skip(1).collect())
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                ["pump-3", "speed", "1200", "ok", ""],
+                ["pump-3", "torque", "", "null", ""],
+                ["boiler-1", "temp", "", "failed", "device_not_found"],
+                ["pump-3", "speed", "900", "ok", ""],
+                ["pump-3", "torque", "", "null", ""],
+                ["boiler-1", "temp", "", "failed", "device_not_found"],
+            ]
+        );
+    }
+}
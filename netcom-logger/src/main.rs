@@ -0,0 +1,37 @@
+use std::error::Error;
+use std::path::PathBuf;
+
+use clap::Parser;
+use netcom::netcom::DEFAULT_PORT;
+use netcom::netcom_client_async::NetcomClientAsync;
+use netcom_logger::config::Config;
+use netcom_logger::logger::Logger;
+
+#[derive(Parser)]
+#[command(
+    name = "netcom-logger",
+    version,
+    about = "Logs netcom device parameters to CSV or InfluxDB line-protocol files"
+)]
+struct Cli {
+    /// TOML file listing the devices and parameters to log
+    config: PathBuf,
+
+    /// Server hostname
+    #[arg(long, env = "NETCOM_HOST", default_value = "localhost")]
+    host: String,
+
+    /// Server port
+    #[arg(long, env = "NETCOM_PORT", default_value_t = DEFAULT_PORT)]
+    port: u16,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let cli = Cli::parse();
+    let config = Config::parse(&std::fs::read_to_string(&cli.config)?)?;
+
+    let client = NetcomClientAsync::new(&cli.host, cli.port);
+    Logger::new(client, config).run().await?;
+    Ok(())
+}
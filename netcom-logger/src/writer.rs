@@ -0,0 +1,258 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+
+use crate::config::{Config, Format, Rotation};
+
+/// Measurement name of the line-protocol output.
+const MEASUREMENT: &str = "netcom";
+
+const CSV_HEADER: &str = "timestamp,device,param,value,status,error";
+
+/// The outcome of reading one parameter.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Reading {
+    Value(f64),
+    /// The server answered without a value.
+    Null,
+    /// The request failed, with the `NetcomError` kind.
+    Failed(&'static str),
+}
+
+#[derive(Clone, Debug)]
+pub struct Sample<'a> {
+    pub timestamp: DateTime<Utc>,
+    pub device: &'a str,
+    pub param: &'a str,
+    pub reading: Reading,
+}
+
+impl Format {
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Csv => "csv",
+            Format::Influx => "lp",
+        }
+    }
+
+    fn header(self) -> Option<&'static str> {
+        match self {
+            Format::Csv => Some(CSV_HEADER),
+            Format::Influx => None,
+        }
+    }
+
+    /// One line of output, without the line break.
+    ///
+    /// CSV rows have an empty value and a `null` or `failed` status for
+    /// missing readings. Line-protocol points carry a `status` field, plus a
+    /// `value` field when there is one and an `error` field on failure.
+    ///
+    /// Line protocol has no way to write NaN or infinity, so both formats
+    /// leave such values out and set the status to `nan`, `inf` or `-inf`.
+    pub fn line(self, sample: &Sample) -> String {
+        match self {
+            Format::Csv => {
+                let (value, status, error) = match sample.reading {
+                    Reading::Value(v) if !v.is_finite() => (String::new(), non_finite(v), ""),
+                    Reading::Value(v) => (v.to_string(), "ok", ""),
+                    Reading::Null => (String::new(), "null", ""),
+                    Reading::Failed(kind) => (String::new(), "failed", kind),
+                };
+                format!(
+                    "{},{},{},{},{},{}",
+                    sample
+                        .timestamp
+                        .to_rfc3339_opts(SecondsFormat::Millis, true),
+                    csv_field(sample.device),
+                    csv_field(sample.param),
+                    value,
+                    status,
+                    error
+                )
+            }
+            Format::Influx => {
+                let fields = match sample.reading {
+                    Reading::Value(v) if !v.is_finite() => format!("status=\"{}\"", non_finite(v)),
+                    Reading::Value(v) => format!("value={},status=\"ok\"", v),
+                    Reading::Null => "status=\"null\"".to_string(),
+                    Reading::Failed(kind) => format!("status=\"failed\",error=\"{}\"", kind),
+                };
+                format!(
+                    "{},device={},param={} {} {}",
+                    MEASUREMENT,
+                    tag_value(sample.device),
+                    tag_value(sample.param),
+                    fields,
+                    sample.timestamp.timestamp_nanos_opt().unwrap_or_default()
+                )
+            }
+        }
+    }
+}
+
+/// The status of a NaN or infinite value.
+fn non_finite(v: f64) -> &'static str {
+    if v.is_nan() {
+        "nan"
+    } else if v > 0.0 {
+        "inf"
+    } else {
+        "-inf"
+    }
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+fn tag_value(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, ',' | '=' | ' ') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Appends samples to files that are rotated by time. Existing files are
+/// appended to, so restarting the logger within a period continues its file.
+pub struct RotatingWriter {
+    directory: PathBuf,
+    prefix: String,
+    format: Format,
+    rotation: Rotation,
+    current: Option<(PathBuf, BufWriter<File>)>,
+}
+
+impl RotatingWriter {
+    pub fn new(config: &Config) -> Self {
+        RotatingWriter {
+            directory: config.directory.clone(),
+            prefix: config.prefix.clone(),
+            format: config.format,
+            rotation: config.rotate,
+            current: None,
+        }
+    }
+
+    /// The file that samples taken at `timestamp` go to.
+    pub fn path(&self, timestamp: DateTime<Utc>) -> PathBuf {
+        let period = match self.rotation {
+            Rotation::Never => String::new(),
+            Rotation::Hourly => timestamp.format("-%Y%m%d-%H").to_string(),
+            Rotation::Daily => timestamp.format("-%Y%m%d").to_string(),
+        };
+        let name = format!("{}{}.{}", self.prefix, period, self.format.extension());
+        self.directory.join(name)
+    }
+
+    pub fn write(&mut self, sample: &Sample) -> io::Result<()> {
+        let path = self.path(sample.timestamp);
+        let line = self.format.line(sample);
+        let file = match &mut self.current {
+            Some((current, file)) if *current == path => file,
+            _ => self.open(path)?,
+        };
+        writeln!(file, "{}", line)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        match &mut self.current {
+            Some((_, file)) => file.flush(),
+            None => Ok(()),
+        }
+    }
+
+    fn open(&mut self, path: PathBuf) -> io::Result<&mut BufWriter<File>> {
+        self.flush()?;
+        fs::create_dir_all(&self.directory)?;
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let empty = file.metadata()?.len() == 0;
+
+        let mut file = BufWriter::new(file);
+        if let (true, Some(header)) = (empty, self.format.header()) {
+            writeln!(file, "{}", header)?;
+        }
+        Ok(&mut self.current.insert((path, file)).1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn sample(reading: Reading) -> Sample<'static> {
+        Sample {
+            timestamp: Utc.with_ymd_and_hms(2024, 1, 31, 23, 59, 30).unwrap(),
+            device: "pump 3",
+            param: "speed,rpm",
+            reading,
+        }
+    }
+
+    #[test]
+    fn should_format_csv_rows() {
+        assert_eq!(
+            Format::Csv.line(&sample(Reading::Value(1200.5))),
+            "2024-01-31T23:59:30.000Z,pump 3,\"speed,rpm\",1200.5,ok,"
+        );
+        assert_eq!(
+            Format::Csv.line(&sample(Reading::Null)),
+            "2024-01-31T23:59:30.000Z,pump 3,\"speed,rpm\",,null,"
+        );
+        assert_eq!(
+            Format::Csv.line(&sample(Reading::Failed("device_not_found"))),
+            "2024-01-31T23:59:30.000Z,pump 3,\"speed,rpm\",,failed,device_not_found"
+        );
+        assert_eq!(
+            Format::Csv.line(&sample(Reading::Value(f64::NAN))),
+            "2024-01-31T23:59:30.000Z,pump 3,\"speed,rpm\",,nan,"
+        );
+    }
+
+    #[test]
+    fn should_format_line_protocol() {
+        assert_eq!(
+            Format::Influx.line(&sample(Reading::Value(1200.0))),
+            "netcom,device=pump\\ 3,param=speed\\,rpm value=1200,status=\"ok\" 1706745570000000000"
+        );
+        assert_eq!(
+            Format::Influx.line(&sample(Reading::Failed("stream"))),
+            "netcom,device=pump\\ 3,param=speed\\,rpm status=\"failed\",error=\"stream\" 1706745570000000000"
+        );
+        assert_eq!(
+            Format::Influx.line(&sample(Reading::Value(f64::NEG_INFINITY))),
+            "netcom,device=pump\\ 3,param=speed\\,rpm status=\"-inf\" 1706745570000000000"
+        );
+    }
+
+    #[test]
+    fn should_rotate_files_by_period() {
+        let dir = tempfile::tempdir().unwrap();
+        let config =
+            Config::parse(&format!("directory = {:?}\nrotate = \"daily\"", dir.path())).unwrap();
+        let mut writer = RotatingWriter::new(&config);
+
+        let mut s = sample(Reading::Value(1.0));
+        writer.write(&s).unwrap();
+        s.timestamp += chrono::Duration::minutes(1);
+        writer.write(&s).unwrap();
+        writer.flush().unwrap();
+
+        let first = fs::read_to_string(dir.path().join("netcom-20240131.csv")).unwrap();
+        let second = fs::read_to_string(dir.path().join("netcom-20240201.csv")).unwrap();
+        assert_eq!(first.lines().count(), 2);
+        assert_eq!(second.lines().next(), Some(CSV_HEADER));
+        assert!(second.contains("2024-02-01T00:00:30.000Z"));
+    }
+}
@@ -1,100 +1,707 @@
 extern crate proc_macro;
 use proc_macro::TokenStream;
-// use proc_macro2::TokenStream as TokenStream2;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::format_ident;
 use quote::quote;
-use syn::{parse::Parse, parse_macro_input, Data, DeriveInput, Expr, Fields, Lit, Meta};
+use syn::{
+    parse_macro_input, Data, DeriveInput, Field, Fields, LitInt, LitStr, Path, Type, TypeArray,
+};
 
-#[proc_macro_derive(NetcomMap, attributes(param))]
+/// Settings collected from the `#[param(...)]` attribute of a single field.
+#[derive(Default)]
+struct ParamAttr {
+    p: Option<String>,
+    prefix: Option<String>,
+    start: Option<usize>,
+}
+
+impl ParamAttr {
+    fn parse(field: &Field) -> syn::Result<Option<ParamAttr>> {
+        let mut result: Option<ParamAttr> = None;
+
+        for attr in &field.attrs {
+            if !attr.path().is_ident("param") {
+                continue;
+            }
+
+            let param = result.get_or_insert_with(ParamAttr::default);
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("p") {
+                    param.p = Some(meta.value()?.parse::<LitStr>()?.value());
+                } else if meta.path.is_ident("prefix") {
+                    param.prefix = Some(meta.value()?.parse::<LitStr>()?.value());
+                } else if meta.path.is_ident("start") {
+                    param.start = Some(meta.value()?.parse::<LitInt>()?.base10_parse()?);
+                } else {
+                    return Err(meta.error("unsupported param attribute"));
+                }
+                Ok(())
+            })?;
+        }
+
+        if let Some(param) = &result {
+            if param.p.is_some() == param.prefix.is_some() {
+                return Err(syn::Error::new_spanned(
+                    field,
+                    "param attribute requires exactly one of `p` or `prefix`",
+                ));
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+/// Settings collected from the `#[netcom(...)]` attribute of the struct.
+#[derive(Default)]
+struct ContainerAttr {
+    device: Option<String>,
+    snapshot: bool,
+    /// Path of the `netcom` crate, set with `#[netcom(crate = "...")]` when
+    /// it is renamed or re-exported.
+    krate: Option<Path>,
+}
+
+impl ContainerAttr {
+    fn parse(input: &DeriveInput) -> syn::Result<ContainerAttr> {
+        let mut result = ContainerAttr::default();
+
+        for attr in &input.attrs {
+            if !attr.path().is_ident("netcom") {
+                continue;
+            }
+
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("device") {
+                    result.device = Some(meta.value()?.parse::<LitStr>()?.value());
+                } else if meta.path.is_ident("snapshot") {
+                    result.snapshot = true;
+                } else if meta.path.is_ident("crate") {
+                    result.krate = Some(meta.value()?.parse::<LitStr>()?.parse()?);
+                } else {
+                    return Err(meta.error("unsupported netcom attribute"));
+                }
+                Ok(())
+            })?;
+        }
+
+        Ok(result)
+    }
+
+    /// Path of the `netcom` crate in generated code, `::netcom` by default.
+    fn krate(&self) -> TokenStream2 {
+        match &self.krate {
+            Some(path) => quote! { #path },
+            None => quote! { ::netcom },
+        }
+    }
+}
+
+/// Generated code for one field, spliced into the `NetcomSync` impl.
+struct FieldCode {
+    metadata: TokenStream2,
+    to_rdops: TokenStream2,
+    to_wrops: TokenStream2,
+    /// Converts the field's values from `result` into a local, failing on
+    /// the first value that does not convert.
+    convert: TokenStream2,
+    /// Assigns the converted local to the field.
+    assign: TokenStream2,
+}
+
+/// Splits a field into its address template, whether it is a nested
+/// `NetcomMap` struct (`prefix`) rather than a value (`p`), and the array
+/// type if the field is a fixed-size array.
+fn classify<'a>(
+    field: &'a Field,
+    param: &'a ParamAttr,
+) -> syn::Result<(&'a str, bool, Option<&'a TypeArray>)> {
+    let (template, nested) = match (&param.p, &param.prefix) {
+        (Some(p), _) => (p.as_str(), false),
+        (_, Some(prefix)) => (prefix.as_str(), true),
+        _ => unreachable!(),
+    };
+
+    let array = match &field.ty {
+        Type::Array(array) => {
+            if !template.contains("{}") {
+                return Err(syn::Error::new_spanned(
+                    field,
+                    "array fields require an index template containing `{}`",
+                ));
+            }
+            Some(array)
+        }
+        _ => {
+            if param.start.is_some() {
+                return Err(syn::Error::new_spanned(
+                    field,
+                    "`start` is only supported on array fields",
+                ));
+            }
+            None
+        }
+    };
+
+    Ok((template, nested, array))
+}
+
+fn field_code(field: &Field, param: &ParamAttr, krate: &TokenStream2) -> syn::Result<FieldCode> {
+    let field_name = field.ident.as_ref().unwrap();
+    let field_name_str = field_name.to_string();
+    let start = param.start.unwrap_or(0);
+    let (template, nested, array) = classify(field, param)?;
+    let local = format_ident!("__{}", field_name);
+
+    let code = match (array, nested) {
+        (None, false) => FieldCode {
+            metadata: quote! {
+                map.insert(#field_name_str.to_string(), #template.to_string());
+            },
+            to_rdops: quote! {
+                ops.push(#krate::netcom::RdOp::Default {
+                    p: #template.to_string(),
+                });
+            },
+            to_wrops: quote! {
+                ops.push(#krate::netcom::WrOp::Default {
+                    p: #template.to_string(),
+                    v: f64::from(self.#field_name),
+                });
+            },
+            convert: {
+                let ty = &field.ty;
+                quote! {
+                    let #local = match result.get(#template) {
+                        Some(Some(v)) => Some(<#ty as ::core::convert::TryFrom<f64>>::try_from(*v)?),
+                        _ => None,
+                    };
+                }
+            },
+            assign: quote! {
+                if let Some(v) = #local {
+                    self.#field_name = v;
+                }
+            },
+        },
+        (Some(array), false) => {
+            let len = &array.len;
+            let elem = &array.elem;
+            FieldCode {
+                metadata: quote! {
+                    for i in 0..#len {
+                        map.insert(
+                            format!("{}[{}]", #field_name_str, i),
+                            #template.replace("{}", &(i + #start).to_string()),
+                        );
+                    }
+                },
+                to_rdops: quote! {
+                    for i in 0..self.#field_name.len() {
+                        ops.push(#krate::netcom::RdOp::Default {
+                            p: #template.replace("{}", &(i + #start).to_string()),
+                        });
+                    }
+                },
+                to_wrops: quote! {
+                    for (i, v) in self.#field_name.iter().enumerate() {
+                        ops.push(#krate::netcom::WrOp::Default {
+                            p: #template.replace("{}", &(i + #start).to_string()),
+                            v: f64::from(*v),
+                        });
+                    }
+                },
+                convert: quote! {
+                    let mut #local = Vec::new();
+                    for i in 0..#len {
+                        let p = #template.replace("{}", &(i + #start).to_string());
+                        if let Some(Some(v)) = result.get(&p) {
+                            #local.push((i, <#elem as ::core::convert::TryFrom<f64>>::try_from(*v)?));
+                        }
+                    }
+                },
+                assign: quote! {
+                    for (i, v) in #local {
+                        self.#field_name[i] = v;
+                    }
+                },
+            }
+        }
+        (None, true) => {
+            let ty = &field.ty;
+            FieldCode {
+                metadata: quote! {
+                    for (k, v) in <#ty>::metadata() {
+                        map.insert(format!("{}.{}", #field_name_str, k), format!("{}{}", #template, v));
+                    }
+                },
+                to_rdops: quote! {
+                    ops.extend(#krate::netcom::NetcomSync::to_rdops_prefixed(
+                        &self.#field_name,
+                        #template,
+                    ));
+                },
+                to_wrops: quote! {
+                    ops.extend(#krate::netcom::NetcomSync::to_wrops_prefixed(
+                        &self.#field_name,
+                        #template,
+                    ));
+                },
+                convert: quote! {
+                    #krate::netcom::NetcomSync::check_result_prefixed(&self.#field_name, #template, result)?;
+                },
+                assign: quote! {
+                    #krate::netcom::NetcomSync::try_apply_result_prefixed(
+                        &mut self.#field_name,
+                        #template,
+                        result,
+                    )?;
+                },
+            }
+        }
+        (Some(array), true) => {
+            let len = &array.len;
+            let elem = &array.elem;
+            FieldCode {
+                metadata: quote! {
+                    for i in 0..#len {
+                        let prefix = #template.replace("{}", &(i + #start).to_string());
+                        for (k, v) in <#elem>::metadata() {
+                            map.insert(format!("{}[{}].{}", #field_name_str, i, k), format!("{}{}", prefix, v));
+                        }
+                    }
+                },
+                to_rdops: quote! {
+                    for (i, item) in self.#field_name.iter().enumerate() {
+                        let prefix = #template.replace("{}", &(i + #start).to_string());
+                        ops.extend(#krate::netcom::NetcomSync::to_rdops_prefixed(item, &prefix));
+                    }
+                },
+                to_wrops: quote! {
+                    for (i, item) in self.#field_name.iter().enumerate() {
+                        let prefix = #template.replace("{}", &(i + #start).to_string());
+                        ops.extend(#krate::netcom::NetcomSync::to_wrops_prefixed(item, &prefix));
+                    }
+                },
+                convert: quote! {
+                    for (i, item) in self.#field_name.iter().enumerate() {
+                        let prefix = #template.replace("{}", &(i + #start).to_string());
+                        #krate::netcom::NetcomSync::check_result_prefixed(item, &prefix, result)?;
+                    }
+                },
+                assign: quote! {
+                    for (i, item) in self.#field_name.iter_mut().enumerate() {
+                        let prefix = #template.replace("{}", &(i + #start).to_string());
+                        #krate::netcom::NetcomSync::try_apply_result_prefixed(item, &prefix, result)?;
+                    }
+                },
+            }
+        }
+    };
+
+    Ok(code)
+}
+
+/// Generated code for one field of the `<Struct>Patch` type.
+struct PatchCode {
+    field: TokenStream2,
+    default: TokenStream2,
+    builder: TokenStream2,
+    to_wrops: TokenStream2,
+}
+
+/// The patch type of a nested `NetcomMap` struct, e.g. `ChannelPatch` for
+/// `Channel`.
+fn patch_type(ty: &Type) -> syn::Result<Type> {
+    match ty {
+        Type::Path(type_path) => {
+            let mut type_path = type_path.clone();
+            let last = type_path.path.segments.last_mut().unwrap();
+            last.ident = format_ident!("{}Patch", last.ident);
+            Ok(Type::Path(type_path))
+        }
+        _ => Err(syn::Error::new_spanned(
+            ty,
+            "nested param fields must name a NetcomMap struct",
+        )),
+    }
+}
+
+fn patch_code(field: &Field, param: &ParamAttr, krate: &TokenStream2) -> syn::Result<PatchCode> {
+    let field_name = field.ident.as_ref().unwrap();
+    // Prefixed so that no field can clash with `new` or the trait methods.
+    let builder_name = format_ident!("with_{}", field_name);
+    let vis = &field.vis;
+    let start = param.start.unwrap_or(0);
+    let (template, nested, array) = classify(field, param)?;
+
+    let code = match (array, nested) {
+        (None, false) => {
+            let ty = &field.ty;
+            PatchCode {
+                field: quote! { #vis #field_name: Option<#ty> },
+                default: quote! { #field_name: None },
+                builder: quote! {
+                    pub fn #builder_name(mut self, value: #ty) -> Self {
+                        self.#field_name = Some(value);
+                        self
+                    }
+                },
+                to_wrops: quote! {
+                    if let Some(v) = self.#field_name {
+                        ops.push(#krate::netcom::WrOp::Default {
+                            p: #template.to_string(),
+                            v: f64::from(v),
+                        });
+                    }
+                },
+            }
+        }
+        (Some(array), false) => {
+            let elem = &array.elem;
+            let len = &array.len;
+            PatchCode {
+                field: quote! { #vis #field_name: [Option<#elem>; #len] },
+                default: quote! { #field_name: [None; #len] },
+                builder: quote! {
+                    pub fn #builder_name(mut self, index: usize, value: #elem) -> Self {
+                        self.#field_name[index] = Some(value);
+                        self
+                    }
+                },
+                to_wrops: quote! {
+                    for (i, v) in self.#field_name.iter().enumerate() {
+                        if let Some(v) = v {
+                            ops.push(#krate::netcom::WrOp::Default {
+                                p: #template.replace("{}", &(i + #start).to_string()),
+                                v: f64::from(*v),
+                            });
+                        }
+                    }
+                },
+            }
+        }
+        (None, true) => {
+            let ty = patch_type(&field.ty)?;
+            PatchCode {
+                field: quote! { #vis #field_name: #ty },
+                default: quote! { #field_name: <#ty>::default() },
+                builder: quote! {
+                    pub fn #builder_name(mut self, patch: #ty) -> Self {
+                        self.#field_name = patch;
+                        self
+                    }
+                },
+                to_wrops: quote! {
+                    ops.extend(#krate::netcom::NetcomPatch::to_wrops_prefixed(
+                        &self.#field_name,
+                        #template,
+                    ));
+                },
+            }
+        }
+        (Some(array), true) => {
+            let elem = patch_type(&array.elem)?;
+            let len = &array.len;
+            PatchCode {
+                field: quote! { #vis #field_name: [#elem; #len] },
+                default: quote! {
+                    #field_name: ::std::array::from_fn(|_| <#elem>::default())
+                },
+                builder: quote! {
+                    pub fn #builder_name(mut self, index: usize, patch: #elem) -> Self {
+                        self.#field_name[index] = patch;
+                        self
+                    }
+                },
+                to_wrops: quote! {
+                    for (i, item) in self.#field_name.iter().enumerate() {
+                        let prefix = #template.replace("{}", &(i + #start).to_string());
+                        ops.extend(#krate::netcom::NetcomPatch::to_wrops_prefixed(item, &prefix));
+                    }
+                },
+            }
+        }
+    };
+
+    Ok(code)
+}
+
+#[proc_macro_derive(NetcomMap, attributes(param, netcom))]
 pub fn netcom_map_derive(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
+    match netcom_map_impl(&input) {
+        Ok(tokens) => TokenStream::from(tokens),
+        Err(e) => TokenStream::from(e.to_compile_error()),
+    }
+}
+
+fn netcom_map_impl(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let container = ContainerAttr::parse(input)?;
+    let krate = container.krate();
     let struct_name = &input.ident;
+    let patch_name = format_ident!("{}Patch", struct_name);
+    let vis = &input.vis;
 
     let mut metadata_entries = vec![];
     let mut to_wrops_entries = vec![];
     let mut to_rdops_entries = vec![];
-    let mut apply_result_entries = vec![];
-    let mut field_idents = vec![];
-
-    if let Data::Struct(data_struct) = input.data {
-        if let Fields::Named(fields) = data_struct.fields {
-            for field in fields.named {
-                let field_name = field.ident.unwrap();
-                let field_name_str = field_name.to_string();
-                let mut param_value: Option<String> = None;
-
-                for attr in field.attrs {
-                    if attr.path().is_ident("param") {
-                        if let Ok(meta) = attr.parse_args_with(syn::Meta::parse) {
-                            if let Meta::NameValue(name_value) = meta {
-                                if name_value.path.is_ident("p") {
-                                    if let Expr::Lit(expr_lit) = name_value.value {
-                                        if let Lit::Str(lit_str) = expr_lit.lit {
-                                            param_value = Some(lit_str.value());
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
+    let mut convert_entries = vec![];
+    let mut assign_entries = vec![];
+    let mut patch_fields = vec![];
+    let mut patch_defaults = vec![];
+    let mut patch_builders = vec![];
+    let mut patch_wrops_entries = vec![];
 
-                if let Some(param_value) = param_value {
-                    metadata_entries.push(quote! {
-                        map.insert(#field_name_str.to_string(), #param_value.to_string());
-                    });
+    if let Data::Struct(data_struct) = &input.data {
+        if let Fields::Named(fields) = &data_struct.fields {
+            for field in &fields.named {
+                if let Some(param) = ParamAttr::parse(field)? {
+                    let code = field_code(field, &param, &krate)?;
+                    metadata_entries.push(code.metadata);
+                    to_rdops_entries.push(code.to_rdops);
+                    to_wrops_entries.push(code.to_wrops);
+                    convert_entries.push(code.convert);
+                    assign_entries.push(code.assign);
 
-                    to_rdops_entries.push(quote! {
-                        RdOp::Default {
-                            p: #param_value.to_string(),
-                        }
-                    });
+                    let patch = patch_code(field, &param, &krate)?;
+                    patch_fields.push(patch.field);
+                    patch_defaults.push(patch.default);
+                    patch_builders.push(patch.builder);
+                    patch_wrops_entries.push(patch.to_wrops);
+                }
+            }
+        }
+    }
 
-                    to_wrops_entries.push(quote! {
-                        WrOp::Default {
-                            p: #param_value.to_string(),
-                            v: self.#field_name,
-                        }
-                    });
+    let device_impl = match &container.device {
+        Some(device) => quote! {
+            impl #krate::netcom::NetcomDevice for #struct_name {
+                const DEVICE: &'static str = #device;
+            }
+        },
+        None => quote! {},
+    };
 
-                    apply_result_entries.push(quote! {
-                        if let Some(v) = result.get(#param_value) {
-                            if let Some(vv) = v {
-                                self.#field_name = *vv;
-                            }
-                        }
-                    });
+    let snapshot_impl = if container.snapshot {
+        quote! {
+            impl #krate::snapshot::NetcomSnapshot for #struct_name {}
+        }
+    } else {
+        quote! {}
+    };
 
-                    field_idents.push(quote! {
-                        #field_name: 0.0
-                    });
+    let patch_impl = quote! {
+        #[derive(Debug, Clone)]
+        #vis struct #patch_name {
+            #(#patch_fields),*
+        }
+
+        impl Default for #patch_name {
+            fn default() -> Self {
+                #patch_name {
+                    #(#patch_defaults),*
                 }
             }
         }
-    }
 
-    let expanded = quote! {
+        impl #patch_name {
+            pub fn new() -> Self {
+                Self::default()
+            }
+
+            #(#patch_builders)*
+        }
+
+        impl #krate::netcom::NetcomPatch for #patch_name {
+            fn to_wrops(&self) -> Vec<#krate::netcom::WrOp> {
+                let mut ops = Vec::new();
+                #(#patch_wrops_entries)*
+                ops
+            }
+        }
+    };
+
+    Ok(quote! {
+        #device_impl
+        #snapshot_impl
+
         impl #struct_name {
-            pub fn metadata() -> HashMap<String, String> {
-                let mut map = HashMap::new();
+            pub fn metadata() -> ::std::collections::HashMap<String, String> {
+                let mut map = ::std::collections::HashMap::new();
                 #(#metadata_entries)*
                 map
             }
         }
 
-        impl NetcomSync for #struct_name {
-            fn to_rdops(&self) -> Vec<RdOp> {
-                vec![#(#to_rdops_entries),*]
+        impl #krate::netcom::NetcomSync for #struct_name {
+            fn to_rdops(&self) -> Vec<#krate::netcom::RdOp> {
+                let mut ops = Vec::new();
+                #(#to_rdops_entries)*
+                ops
+            }
+
+            fn to_wrops(&self) -> Vec<#krate::netcom::WrOp> {
+                let mut ops = Vec::new();
+                #(#to_wrops_entries)*
+                ops
+            }
+
+            fn apply_result(&mut self, result: &::std::collections::HashMap<String, Option<f64>>) {
+                let _ = #krate::netcom::NetcomSync::try_apply_result(self, result);
+            }
+
+            fn check_result(
+                &self,
+                result: &::std::collections::HashMap<String, Option<f64>>,
+            ) -> Result<(), #krate::netcom::NetcomError> {
+                #(#convert_entries)*
+                Ok(())
             }
 
-            fn to_wrops(&self) -> Vec<WrOp> {
-                vec![#(#to_wrops_entries),*]
+            // Nested structs are checked before anything is assigned, so
+            // that a value failing to convert leaves every field unchanged.
+            fn try_apply_result(
+                &mut self,
+                result: &::std::collections::HashMap<String, Option<f64>>,
+            ) -> Result<(), #krate::netcom::NetcomError> {
+                #(#convert_entries)*
+                #(#assign_entries)*
+                Ok(())
             }
+        }
+
+        #patch_impl
+    })
+}
+
+/// Derives `From<T> for f64` and `TryFrom<f64> for T` for an enum whose
+/// variants carry explicit discriminants, so that it can be used as a field
+/// type in a `NetcomMap` struct. The enum must be `Copy`.
+///
+/// A single tuple variant holding an `i64`, such as `Unknown(i64)`, captures
+/// whole values that match no discriminant. Rust only accepts explicit
+/// discriminants next to it with a primitive representation, e.g.
+/// `#[repr(i64)]`. Without such a variant, unknown values are reported as
+/// `NetcomError::InvalidValue`, as are values with a fractional part or
+/// outside the range of `i64`, with or without one.
+#[proc_macro_derive(NetcomEnum, attributes(netcom))]
+pub fn netcom_enum_derive(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match netcom_enum_impl(&input) {
+        Ok(tokens) => TokenStream::from(tokens),
+        Err(e) => TokenStream::from(e.to_compile_error()),
+    }
+}
+
+fn is_i64(ty: &Type) -> bool {
+    matches!(ty, Type::Path(type_path) if type_path.qself.is_none() && type_path.path.is_ident("i64"))
+}
+
+fn netcom_enum_impl(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let container = ContainerAttr::parse(input)?;
+    if container.device.is_some() || container.snapshot {
+        return Err(syn::Error::new_spanned(
+            input,
+            "NetcomEnum only supports `#[netcom(crate = \"...\")]`",
+        ));
+    }
+    let krate = container.krate();
+    let enum_name = &input.ident;
+    let enum_name_str = enum_name.to_string();
+
+    let data_enum = match &input.data {
+        Data::Enum(data_enum) => data_enum,
+        _ => {
+            return Err(syn::Error::new_spanned(
+                input,
+                "NetcomEnum can only be derived for enums",
+            ))
+        }
+    };
+
+    let mut to_value_arms = vec![];
+    let mut from_value_arms = vec![];
+    let mut fallback = None;
+
+    for variant in &data_enum.variants {
+        let variant_name = &variant.ident;
+
+        match &variant.fields {
+            Fields::Unit => {
+                let discriminant = match &variant.discriminant {
+                    Some((_, expr)) => expr,
+                    None => {
+                        return Err(syn::Error::new_spanned(
+                            variant,
+                            "NetcomEnum variants require an explicit discriminant",
+                        ))
+                    }
+                };
 
-            fn apply_result(&mut self, result: &HashMap<String, Option<f64>>) {
-                #(#apply_result_entries)*
+                to_value_arms.push(quote! {
+                    #enum_name::#variant_name => (#discriminant) as f64
+                });
+                from_value_arms.push(quote! {
+                    n if n == (#discriminant) as i64 => Ok(#enum_name::#variant_name)
+                });
+            }
+            Fields::Unnamed(fields)
+                if fields.unnamed.len() == 1
+                    && is_i64(&fields.unnamed[0].ty)
+                    && fallback.is_none() =>
+            {
+                to_value_arms.push(quote! {
+                    #enum_name::#variant_name(n) => n as f64
+                });
+                fallback = Some(variant_name);
+            }
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    variant,
+                    "NetcomEnum variants must be fieldless, except for one `Unknown(i64)` fallback",
+                ))
             }
         }
+    }
+
+    let fallback_arm = match fallback {
+        Some(variant_name) => quote! {
+            n => Ok(#enum_name::#variant_name(n))
+        },
+        None => quote! {
+            _ => Err(#krate::netcom::NetcomError::InvalidValue(format!(
+                "{} is not a valid {}",
+                value, #enum_name_str
+            )))
+        },
     };
 
-    TokenStream::from(expanded)
+    Ok(quote! {
+        impl From<#enum_name> for f64 {
+            fn from(value: #enum_name) -> f64 {
+                match value {
+                    #(#to_value_arms),*
+                }
+            }
+        }
+
+        impl ::core::convert::TryFrom<f64> for #enum_name {
+            type Error = #krate::netcom::NetcomError;
+
+            fn try_from(value: f64) -> Result<Self, #krate::netcom::NetcomError> {
+                if value.fract() != 0.0 || !(i64::MIN as f64..i64::MAX as f64).contains(&value) {
+                    return Err(#krate::netcom::NetcomError::InvalidValue(format!(
+                        "{} is not a valid {}",
+                        value, #enum_name_str
+                    )));
+                }
+
+                match value as i64 {
+                    #(#from_value_arms,)*
+                    #fallback_arm
+                }
+            }
+        }
+    })
 }
@@ -0,0 +1,320 @@
+use std::time::Duration;
+
+use netcom::netcom::{NetcomError, WrOp};
+use netcom::netcom_client_async::NetcomClientAsync;
+use rumqttc::{AsyncClient, Event, MqttOptions, Packet, Publish, QoS};
+use serde::Deserialize;
+use serde_json::json;
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+use tokio::time::MissedTickBehavior;
+
+use crate::config::{is_topic_level, Config, DeviceConfig};
+
+/// Delay before the MQTT event loop tries to reconnect to the broker.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// The payload of a `set` topic, either a number or `{"v": .., "t": ..}`.
+/// The value is written with the type configured for the parameter, which
+/// a `t` in the payload must match.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SetValue {
+    Simple(f64),
+    WithType { v: f64, t: String },
+}
+
+/// The write requested by the `set` payload of `param`.
+fn set_wrop(config: &DeviceConfig, param: &str, payload: &[u8]) -> Result<WrOp, NetcomError> {
+    let value =
+        serde_json::from_slice(payload).map_err(|e| NetcomError::InvalidValue(e.to_string()))?;
+    match value {
+        SetValue::Simple(v) => Ok(config.wrop(param, v)),
+        SetValue::WithType { v, t } if config.types.get(param) == Some(&t) => {
+            Ok(config.wrop(param, v))
+        }
+        SetValue::WithType { t, .. } => Err(NetcomError::InvalidValue(format!(
+            "{} is not configured with type {}",
+            param, t
+        ))),
+    }
+}
+
+/// `<prefix>/<device>/<param>`, carrying the last polled value as JSON, or
+/// `null` if it could not be read. Values are published retained.
+pub fn value_topic(prefix: &str, device: &str, param: &str) -> String {
+    format!("{}/{}/{}", prefix, device, param)
+}
+
+/// `<prefix>/<device>/<param>/set/result`, carrying the outcome of a write
+/// as `{"ok": true, "value": ..}` or `{"ok": false, "error": .., "message": ..}`.
+pub fn result_topic(prefix: &str, device: &str, param: &str) -> String {
+    format!("{}/{}/{}/set/result", prefix, device, param)
+}
+
+/// Splits `<prefix>/<device>/<param>/set` into the device and parameter.
+pub fn parse_set_topic<'a>(prefix: &str, topic: &'a str) -> Option<(&'a str, &'a str)> {
+    let rest = topic.strip_prefix(prefix)?.strip_prefix('/')?;
+    let rest = rest.strip_suffix("/set")?;
+    let (device, param) = rest.split_once('/')?;
+    (is_topic_level(device) && is_topic_level(param)).then_some((device, param))
+}
+
+/// Publishes polled parameter values to MQTT and writes values published to
+/// the matching `set` topics.
+///
+/// Writes are only accepted for the parameters listed in the `writes` of
+/// their device. Any other write is answered with a `write_blocked` error.
+pub struct Bridge {
+    client: NetcomClientAsync,
+    config: Config,
+}
+
+impl Bridge {
+    pub fn new(client: NetcomClientAsync, config: Config) -> Self {
+        Bridge { client, config }
+    }
+
+    /// Connects to the broker and runs the bridge. It only returns if the
+    /// MQTT event loop stops, so it is usually spawned as a task or awaited
+    /// last in `main`.
+    pub async fn run(&mut self, options: MqttOptions) {
+        let (mqtt, mut eventloop) = AsyncClient::new(options, 64);
+        let (commands_tx, mut commands) = mpsc::unbounded_channel();
+        let filter = format!("{}/+/+/set", self.config.prefix);
+
+        // The event loop is driven by its own task so that publishing never
+        // waits on a netcom request. The task is aborted when `run` returns
+        // or is dropped.
+        let mut tasks = JoinSet::new();
+        let subscriber = mqtt.clone();
+        tasks.spawn(async move {
+            loop {
+                match eventloop.poll().await {
+                    // Subscriptions do not outlive a clean session.
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                        let _ = subscriber.try_subscribe(&filter, QoS::AtLeastOnce);
+                    }
+                    Ok(Event::Incoming(Packet::Publish(publish))) => {
+                        if commands_tx.send(publish).is_err() {
+                            return;
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(_) => tokio::time::sleep(RECONNECT_DELAY).await,
+                }
+            }
+        });
+
+        let mut ticker = tokio::time::interval(self.config.interval());
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => self.publish_values(&mqtt).await,
+                command = commands.recv() => match command {
+                    Some(publish) => self.handle_set(&mqtt, publish).await,
+                    None => return,
+                },
+            }
+        }
+    }
+
+    async fn publish_values(&mut self, mqtt: &AsyncClient) {
+        for device in &self.config.devices {
+            let result = self
+                .client
+                .read_parameters(&device.name, device.rdops())
+                .await;
+
+            for p in &device.params {
+                let value = match &result {
+                    Ok(values) => values.get(p).copied().flatten(),
+                    Err(_) => None,
+                };
+                let topic = value_topic(&self.config.prefix, &device.name, p);
+                let _ = mqtt
+                    .publish(topic, QoS::AtMostOnce, true, json!(value).to_string())
+                    .await;
+            }
+        }
+    }
+
+    async fn handle_set(&mut self, mqtt: &AsyncClient, publish: Publish) {
+        let prefix = &self.config.prefix;
+        let Some((device, param)) = parse_set_topic(prefix, &publish.topic) else {
+            return;
+        };
+
+        let result = match self.config.check_write(device, param) {
+            Ok(config) => set_wrop(config, param, &publish.payload),
+            Err(rule) => Err(NetcomError::WriteBlocked(rule)),
+        };
+        let result = match result {
+            Ok(wrop) => self.client.write_parameters(device, vec![wrop]).await,
+            Err(e) => Err(e),
+        };
+
+        let response = match result {
+            Ok(values) => {
+                let value = values.get(param).copied().flatten();
+                let topic = value_topic(prefix, device, param);
+                let _ = mqtt
+                    .publish(topic, QoS::AtMostOnce, true, json!(value).to_string())
+                    .await;
+                json!({ "ok": true, "value": value })
+            }
+            Err(e) => json!({ "ok": false, "error": e.kind(), "message": e.to_string() }),
+        };
+        let topic = result_topic(prefix, device, param);
+        let _ = mqtt
+            .publish(topic, QoS::AtLeastOnce, false, response.to_string())
+            .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_broker::MockBroker;
+    use netcom::mock_server::MockServer;
+    use serde_json::Value;
+
+    const CONFIG: &str = r#"
+        interval_ms = 20
+
+        [[device]]
+        name = "pump-3"
+        params = ["speed"]
+        writes = ["speed"]
+    "#;
+
+    /// A broker client subscribed to `filter`, with its publishes on a channel.
+    async fn observer(port: u16, filter: &str) -> (AsyncClient, mpsc::UnboundedReceiver<Publish>) {
+        let (client, mut eventloop) =
+            AsyncClient::new(MqttOptions::new("observer", "127.0.0.1", port), 16);
+        client.subscribe(filter, QoS::AtMostOnce).await.unwrap();
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Ok(event) = eventloop.poll().await {
+                if let Event::Incoming(Packet::Publish(publish)) = event {
+                    let _ = tx.send(publish);
+                }
+            }
+        });
+        (client, rx)
+    }
+
+    async fn next_on(rx: &mut mpsc::UnboundedReceiver<Publish>, topic: &str) -> Value {
+        loop {
+            let publish = rx.recv().await.unwrap();
+            if publish.topic == topic {
+                return serde_json::from_slice(&publish.payload).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn should_parse_set_topics() {
+        assert_eq!(
+            parse_set_topic("netcom", "netcom/pump-3/speed/set"),
+            Some(("pump-3", "speed"))
+        );
+        assert_eq!(parse_set_topic("netcom", "netcom/pump-3/speed"), None);
+        assert_eq!(parse_set_topic("netcom", "other/pump-3/speed/set"), None);
+        assert_eq!(parse_set_topic("netcom", "netcom/a/b/c/set"), None);
+    }
+
+    #[test]
+    fn should_write_sets_with_configured_types() {
+        let config = Config::parse(
+            r#"
+            [[device]]
+            name = "pump-3"
+            params = ["speed", "mode"]
+            writes = ["speed", "mode"]
+            types = { mode = "u16" }
+            "#,
+        )
+        .unwrap();
+        let device = &config.devices[0];
+
+        assert!(matches!(
+            set_wrop(device, "mode", b"2").unwrap(),
+            WrOp::WithType { t, v, .. } if t == "u16" && v == 2.0
+        ));
+        assert!(matches!(
+            set_wrop(device, "mode", br#"{"v": 2, "t": "u16"}"#).unwrap(),
+            WrOp::WithType { t, .. } if t == "u16"
+        ));
+        assert!(matches!(
+            set_wrop(device, "speed", b"900").unwrap(),
+            WrOp::Default { v, .. } if v == 900.0
+        ));
+        assert!(matches!(
+            set_wrop(device, "mode", br#"{"v": 2, "t": "i32"}"#),
+            Err(NetcomError::InvalidValue(_))
+        ));
+        assert!(matches!(
+            set_wrop(device, "speed", br#"{"v": 900, "t": "u16"}"#),
+            Err(NetcomError::InvalidValue(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn should_publish_values_and_write_sets() {
+        let server = MockServer::start().with_device("pump-3", &[("speed", 1200.0)]);
+        let broker = MockBroker::start().await;
+
+        let client = NetcomClientAsync::new("127.0.0.1", server.port());
+        let mut bridge = Bridge::new(client, Config::parse(CONFIG).unwrap());
+        let options = MqttOptions::new("bridge", "127.0.0.1", broker.port());
+        let task = tokio::spawn(async move { bridge.run(options).await });
+
+        let (observer, mut rx) = observer(broker.port(), "netcom/#").await;
+        assert_eq!(next_on(&mut rx, "netcom/pump-3/speed").await, 1200.0);
+
+        // The bridge subscribes after connecting, so retry until it answers.
+        let response = loop {
+            observer
+                .publish("netcom/pump-3/speed/set", QoS::AtMostOnce, false, "900")
+                .await
+                .unwrap();
+            let result = tokio::time::timeout(
+                Duration::from_millis(100),
+                next_on(&mut rx, "netcom/pump-3/speed/set/result"),
+            );
+            if let Ok(response) = result.await {
+                break response;
+            }
+        };
+        assert_eq!(response, json!({ "ok": true, "value": 900.0 }));
+        assert_eq!(server.value("pump-3", "speed"), Some(900.0));
+        assert_eq!(next_on(&mut rx, "netcom/pump-3/speed").await, 900.0);
+
+        observer
+            .publish("netcom/boiler-1/temp/set", QoS::AtMostOnce, false, "60")
+            .await
+            .unwrap();
+        let response = next_on(&mut rx, "netcom/boiler-1/temp/set/result").await;
+        assert_eq!(response["error"], "write_blocked");
+
+        observer
+            .publish("netcom/pump-3/pressure/set", QoS::AtMostOnce, false, "4")
+            .await
+            .unwrap();
+        let response = next_on(&mut rx, "netcom/pump-3/pressure/set/result").await;
+        assert_eq!(response["error"], "write_blocked");
+        assert_eq!(server.value("pump-3", "pressure"), None);
+
+        observer
+            .publish("netcom/pump-3/speed/set", QoS::AtMostOnce, false, "fast")
+            .await
+            .unwrap();
+        let response = next_on(&mut rx, "netcom/pump-3/speed/set/result").await;
+        assert_eq!(response["error"], "invalid_value");
+
+        task.abort();
+    }
+}
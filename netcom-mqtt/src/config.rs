@@ -0,0 +1,139 @@
+use std::time::Duration;
+
+use netcom::config::default_interval_ms;
+pub use netcom::config::{ConfigError, DeviceConfig};
+use netcom::guard::GuardRule;
+use serde::Deserialize;
+
+/// Bridge configuration, usually loaded from a TOML file:
+///
+/// ```toml
+/// interval_ms = 1000
+/// prefix = "netcom"
+///
+/// [[device]]
+/// name = "pump-3"
+/// params = ["speed", "pressure"]
+/// writes = ["speed"]
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default = "default_interval_ms")]
+    pub interval_ms: u64,
+
+    /// First level of every topic.
+    #[serde(default = "default_prefix")]
+    pub prefix: String,
+
+    #[serde(default, rename = "device")]
+    pub devices: Vec<DeviceConfig>,
+}
+
+fn default_prefix() -> String {
+    "netcom".to_string()
+}
+
+impl Config {
+    pub fn parse(s: &str) -> Result<Config, ConfigError> {
+        let config: Config = netcom::config::parse(s)?;
+
+        let levels = std::iter::once(&config.prefix).chain(
+            config
+                .devices
+                .iter()
+                .flat_map(|d| std::iter::once(&d.name).chain(&d.params).chain(&d.writes)),
+        );
+        for level in levels {
+            if !is_topic_level(level) {
+                let message = format!("{:?} cannot be used in a topic", level);
+                return Err(ConfigError::Invalid(message));
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_millis(self.interval_ms)
+    }
+
+    /// Checks that `param` of `device` is listed as writable, returning the
+    /// device's config.
+    pub fn check_write(&self, device: &str, param: &str) -> Result<&DeviceConfig, GuardRule> {
+        let Some(config) = self.devices.iter().find(|d| d.name == device) else {
+            return Err(GuardRule::Device {
+                device: device.to_string(),
+            });
+        };
+        if !config.writes.iter().any(|p| p == param) {
+            return Err(GuardRule::Param {
+                device: device.to_string(),
+                param: param.to_string(),
+            });
+        }
+        Ok(config)
+    }
+}
+
+/// Whether `s` can be used as a single level of a topic name.
+pub fn is_topic_level(s: &str) -> bool {
+    !s.is_empty() && !s.contains(['/', '+', '#'])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_parse_config() {
+        let config = Config::parse(
+            r#"
+            [[device]]
+            name = "pump-3"
+            params = ["speed", "pressure"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.interval(), Duration::from_secs(1));
+        assert_eq!(config.prefix, "netcom");
+        assert_eq!(config.devices[0].params, ["speed", "pressure"]);
+        assert!(config.devices[0].writes.is_empty());
+    }
+
+    #[test]
+    fn should_only_allow_listed_writes() {
+        let config = Config::parse(
+            r#"
+            [[device]]
+            name = "pump-3"
+            params = ["speed", "pressure"]
+            writes = ["speed"]
+            "#,
+        )
+        .unwrap();
+
+        assert!(config.check_write("pump-3", "speed").is_ok());
+        assert!(matches!(
+            config.check_write("pump-3", "pressure"),
+            Err(GuardRule::Param { .. })
+        ));
+        assert!(matches!(
+            config.check_write("boiler-1", "temp"),
+            Err(GuardRule::Device { .. })
+        ));
+    }
+
+    #[test]
+    fn should_reject_wildcards_in_topics() {
+        let config = "[[device]]\nname = \"pump/3\"\nparams = [\"speed\"]";
+        assert!(matches!(
+            Config::parse(config),
+            Err(ConfigError::Invalid(s)) if s.contains("\"pump/3\"")
+        ));
+        assert!(matches!(
+            Config::parse("prefix = \"#\""),
+            Err(ConfigError::Invalid(_))
+        ));
+    }
+}
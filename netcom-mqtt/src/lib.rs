@@ -0,0 +1,6 @@
+//! Bridges netcom device parameters to an MQTT broker.
+pub mod bridge;
+pub mod config;
+
+#[cfg(test)]
+mod mock_broker;
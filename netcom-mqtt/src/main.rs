@@ -0,0 +1,51 @@
+use std::error::Error;
+use std::path::PathBuf;
+
+use clap::Parser;
+use netcom::netcom::DEFAULT_PORT;
+use netcom::netcom_client_async::NetcomClientAsync;
+use netcom_mqtt::bridge::Bridge;
+use netcom_mqtt::config::Config;
+use rumqttc::MqttOptions;
+
+#[derive(Parser)]
+#[command(
+    name = "netcom-mqtt",
+    version,
+    about = "Bridge between a netcom server and an MQTT broker"
+)]
+struct Cli {
+    /// TOML file listing the devices and parameters to publish
+    config: PathBuf,
+
+    /// Server hostname
+    #[arg(long, env = "NETCOM_HOST", default_value = "localhost")]
+    host: String,
+
+    /// Server port
+    #[arg(long, env = "NETCOM_PORT", default_value_t = DEFAULT_PORT)]
+    port: u16,
+
+    /// MQTT broker hostname
+    #[arg(long, env = "MQTT_HOST", default_value = "localhost")]
+    mqtt_host: String,
+
+    /// MQTT broker port
+    #[arg(long, env = "MQTT_PORT", default_value_t = 1883)]
+    mqtt_port: u16,
+
+    /// MQTT client id
+    #[arg(long, default_value = "netcom-mqtt")]
+    client_id: String,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let cli = Cli::parse();
+    let config = Config::parse(&std::fs::read_to_string(&cli.config)?)?;
+
+    let client = NetcomClientAsync::new(&cli.host, cli.port);
+    let options = MqttOptions::new(cli.client_id, cli.mqtt_host, cli.mqtt_port);
+    Bridge::new(client, config).run(options).await;
+    Ok(())
+}
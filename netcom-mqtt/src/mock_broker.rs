@@ -0,0 +1,119 @@
+use std::sync::{Arc, Mutex};
+
+use bytes::BytesMut;
+use rumqttc::{
+    matches, ConnAck, ConnectReturnCode, Packet, PubAck, QoS, SubAck, SubscribeReasonCode,
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+
+const MAX_PACKET_SIZE: usize = 64 * 1024;
+
+struct Session {
+    filters: Vec<String>,
+    tx: mpsc::UnboundedSender<Packet>,
+}
+
+/// In-process MQTT 3.1.1 broker used by the unit tests. It accepts any
+/// client and forwards publishes to every matching subscription at QoS 0.
+/// Retained messages and persistent sessions are not supported.
+pub struct MockBroker {
+    port: u16,
+}
+
+impl MockBroker {
+    pub async fn start() -> MockBroker {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let sessions = Arc::new(Mutex::new(Vec::new()));
+
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(stream, sessions.clone()));
+            }
+        });
+
+        MockBroker { port }
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+}
+
+async fn serve(stream: TcpStream, sessions: Arc<Mutex<Vec<Session>>>) {
+    let (mut reader, mut writer) = stream.into_split();
+    let (tx, mut rx) = mpsc::unbounded_channel::<Packet>();
+
+    tokio::spawn(async move {
+        while let Some(packet) = rx.recv().await {
+            let mut buf = BytesMut::new();
+            packet.write(&mut buf, MAX_PACKET_SIZE).unwrap();
+            if writer.write_all(&buf).await.is_err() {
+                return;
+            }
+        }
+    });
+
+    let id = {
+        let mut sessions = sessions.lock().unwrap();
+        sessions.push(Session {
+            filters: Vec::new(),
+            tx: tx.clone(),
+        });
+        sessions.len() - 1
+    };
+
+    let mut buf = BytesMut::new();
+    loop {
+        let packet = match Packet::read(&mut buf, MAX_PACKET_SIZE) {
+            Ok(packet) => packet,
+            Err(rumqttc::Error::InsufficientBytes(_)) => match reader.read_buf(&mut buf).await {
+                Ok(0) | Err(_) => break,
+                Ok(_) => continue,
+            },
+            Err(_) => break,
+        };
+
+        match packet {
+            Packet::Connect(_) => {
+                let ack = ConnAck::new(ConnectReturnCode::Success, false);
+                let _ = tx.send(Packet::ConnAck(ack));
+            }
+            Packet::Subscribe(subscribe) => {
+                let codes = subscribe
+                    .filters
+                    .iter()
+                    .map(|f| SubscribeReasonCode::Success(f.qos))
+                    .collect();
+                let mut sessions = sessions.lock().unwrap();
+                sessions[id]
+                    .filters
+                    .extend(subscribe.filters.into_iter().map(|f| f.path));
+                let _ = tx.send(Packet::SubAck(SubAck::new(subscribe.pkid, codes)));
+            }
+            Packet::Publish(publish) => {
+                if publish.qos == QoS::AtLeastOnce {
+                    let _ = tx.send(Packet::PubAck(PubAck::new(publish.pkid)));
+                }
+                let mut forward = publish.clone();
+                forward.qos = QoS::AtMostOnce;
+                forward.pkid = 0;
+                forward.retain = false;
+                for session in sessions.lock().unwrap().iter() {
+                    if session.filters.iter().any(|f| matches(&publish.topic, f)) {
+                        let _ = session.tx.send(Packet::Publish(forward.clone()));
+                    }
+                }
+            }
+            Packet::PingReq => {
+                let _ = tx.send(Packet::PingResp);
+            }
+            Packet::Disconnect => break,
+            _ => {}
+        }
+    }
+
+    sessions.lock().unwrap()[id].filters.clear();
+}
@@ -0,0 +1,331 @@
+//! Audit trail of the writes a client sends to the server.
+//!
+//! Every parameter of every write request sent by a client with an
+//! `AuditLog` becomes one `AuditEntry`, including writes that fail. Writes
+//! stopped by a `WriteGuard`, and dry-run writes, never reach the server and
+//! are not recorded.
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::dto::{WrValueDto, WriteRequestDto};
+use crate::netcom::{NetcomError, RdOp};
+
+/// One parameter written to a device.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    #[serde(with = "crate::timestamp")]
+    pub timestamp: SystemTime,
+    /// The name last pushed with `push_client_info`, if any.
+    pub client: Option<String>,
+    pub device: String,
+    pub param: String,
+    /// The value read just before the write, if the log reads previous
+    /// values, the read succeeded and the device had one.
+    pub previous: Option<f64>,
+    pub new: f64,
+    /// The value echoed by the server, `None` if it rejected the value or
+    /// the write failed.
+    pub echo: Option<f64>,
+    /// The error the write failed with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Destination of audit entries.
+pub trait AuditSink: Send {
+    fn record(&mut self, entry: &AuditEntry) -> io::Result<()>;
+}
+
+/// Appends entries to a file, one JSON object per line.
+pub struct JsonLinesSink {
+    file: File,
+}
+
+impl JsonLinesSink {
+    /// Opens `path` for appending, creating it if needed.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(JsonLinesSink { file })
+    }
+}
+
+impl AuditSink for JsonLinesSink {
+    fn record(&mut self, entry: &AuditEntry) -> io::Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        // A single write per entry, so that lines from several processes
+        // appending to the same file do not interleave.
+        self.file.write_all(&line)?;
+        self.file.flush()
+    }
+}
+
+pub struct AuditLog {
+    sink: Box<dyn AuditSink>,
+    read_previous: bool,
+}
+
+impl AuditLog {
+    pub fn new(sink: impl AuditSink + 'static) -> Self {
+        AuditLog {
+            sink: Box::new(sink),
+            read_previous: false,
+        }
+    }
+
+    /// Reads the parameters before each write to record their previous
+    /// values, at the cost of an extra request per write.
+    pub fn read_previous(mut self, enabled: bool) -> Self {
+        self.read_previous = enabled;
+        self
+    }
+
+    pub fn reads_previous(&self) -> bool {
+        self.read_previous
+    }
+
+    /// Records the outcome of `req` and passes `result` on. Failing to
+    /// record is an error even if the write succeeded, so it returns
+    /// `NetcomError::AuditFailed` carrying `result`.
+    pub(crate) fn record(
+        &mut self,
+        client: Option<&str>,
+        req: &WriteRequestDto,
+        previous: Option<&HashMap<String, Option<f64>>>,
+        result: Result<HashMap<String, Option<f64>>, NetcomError>,
+    ) -> Result<HashMap<String, Option<f64>>, NetcomError> {
+        let timestamp = SystemTime::now();
+        let mut params: Vec<(&String, &WrValueDto)> = req.p.iter().collect();
+        params.sort_by(|a, b| a.0.cmp(b.0));
+
+        for (param, v) in params {
+            let (echo, error) = match &result {
+                Ok(echo) => (echo.get(param).copied().flatten(), None),
+                Err(e) => (None, Some(e.to_string())),
+            };
+            let entry = AuditEntry {
+                timestamp,
+                client: client.map(str::to_string),
+                device: req.device.clone(),
+                param: param.clone(),
+                previous: previous.and_then(|values| values.get(param).copied().flatten()),
+                new: match v {
+                    WrValueDto::Simple(v) => *v,
+                    WrValueDto::Detailed { v, .. } => *v,
+                },
+                echo,
+                error,
+            };
+            if let Err(error) = self.sink.record(&entry) {
+                return Err(NetcomError::AuditFailed {
+                    error,
+                    result: Box::new(result),
+                });
+            }
+        }
+        result
+    }
+}
+
+/// Read operations fetching the previous values of the parameters in `req`.
+pub(crate) fn previous_rdops(req: &WriteRequestDto) -> Vec<RdOp> {
+    req.p
+        .iter()
+        .map(|(p, v)| match v {
+            WrValueDto::Simple(_) => RdOp::Default { p: p.clone() },
+            WrValueDto::Detailed { t, .. } => RdOp::WithType {
+                p: p.clone(),
+                t: t.clone(),
+            },
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::mock_server::MockServer;
+    use crate::netcom::{NetcomMap, WrOp};
+    use crate::netcom_client_sync::NetcomClientSync;
+
+    #[derive(Clone, Default)]
+    struct MemorySink(Arc<Mutex<Vec<AuditEntry>>>);
+
+    impl AuditSink for MemorySink {
+        fn record(&mut self, entry: &AuditEntry) -> io::Result<()> {
+            self.0.lock().unwrap().push(entry.clone());
+            Ok(())
+        }
+    }
+
+    struct FullSink;
+
+    impl AuditSink for FullSink {
+        fn record(&mut self, _: &AuditEntry) -> io::Result<()> {
+            Err(io::ErrorKind::StorageFull.into())
+        }
+    }
+
+    #[derive(Default, NetcomMap)]
+    #[netcom(crate = "crate")]
+    struct Setpoints {
+        #[param(p = "sp1")]
+        sp1: f64,
+
+        #[param(p = "sp2")]
+        sp2: f64,
+    }
+
+    #[test]
+    fn should_record_writes_with_previous_values() {
+        let server = MockServer::start()
+            .with_device("unit-1", &[("sp1", 20.0), ("sp2", 45.0)])
+            .with_read_only("unit-1", "sp2");
+        let sink = MemorySink::default();
+        let mut client = NetcomClientSync::new("127.0.0.1", server.port());
+        client.set_audit_log(Some(AuditLog::new(sink.clone()).read_previous(true)));
+        client.push_client_info("commissioning").unwrap();
+
+        let params = Setpoints {
+            sp1: 21.5,
+            sp2: 50.0,
+        };
+        client.write_struct("unit-1", &params).unwrap();
+
+        let entries = sink.0.lock().unwrap().clone();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].client.as_deref(), Some("commissioning"));
+        assert_eq!(entries[0].device, "unit-1");
+        assert_eq!(entries[0].param, "sp1");
+        assert_eq!(entries[0].previous, Some(20.0));
+        assert_eq!(entries[0].new, 21.5);
+        assert_eq!(entries[0].echo, Some(21.5));
+        assert_eq!(entries[1].param, "sp2");
+        assert_eq!(entries[1].previous, Some(45.0));
+        assert_eq!(entries[1].echo, None);
+        assert_eq!(entries[1].error, None);
+    }
+
+    #[test]
+    fn should_record_failed_writes_without_reading() {
+        let server = MockServer::start().with_device("unit-1", &[("sp1", 20.0)]);
+        let sink = MemorySink::default();
+        let mut client = NetcomClientSync::new("127.0.0.1", server.port());
+        client.set_audit_log(Some(AuditLog::new(sink.clone())));
+
+        let wrops = vec![WrOp::Default {
+            p: "sp1".to_string(),
+            v: 1.0,
+        }];
+        client.write_parameters("missing", wrops).unwrap_err();
+
+        let entries = sink.0.lock().unwrap().clone();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].client, None);
+        assert_eq!(entries[0].previous, None);
+        assert_eq!(entries[0].error.as_deref(), Some("Device not found"));
+        assert_eq!(server.request_count("read"), 0);
+    }
+
+    #[test]
+    fn should_record_writes_whose_previous_value_cannot_be_read() {
+        let server = MockServer::start()
+            .with_device("unit-1", &[("sp1", 20.0)])
+            .with_write_only("unit-1", "sp1");
+        let sink = MemorySink::default();
+        let mut client = NetcomClientSync::new("127.0.0.1", server.port());
+        client.set_audit_log(Some(AuditLog::new(sink.clone()).read_previous(true)));
+
+        let wrops = vec![WrOp::Default {
+            p: "sp1".to_string(),
+            v: 21.0,
+        }];
+        let echo = client.write_parameters("unit-1", wrops).unwrap();
+        assert_eq!(echo.get("sp1"), Some(&Some(21.0)));
+
+        let entries = sink.0.lock().unwrap().clone();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].previous, None);
+        assert_eq!(entries[0].echo, Some(21.0));
+        assert_eq!(server.request_count("read"), 1);
+    }
+
+    #[test]
+    fn should_return_the_write_result_when_recording_fails() {
+        let server = MockServer::start().with_device("unit-1", &[("sp1", 20.0)]);
+        let mut client = NetcomClientSync::new("127.0.0.1", server.port());
+        client.set_audit_log(Some(AuditLog::new(FullSink)));
+
+        let wrops = vec![WrOp::Default {
+            p: "sp1".to_string(),
+            v: 21.0,
+        }];
+        match client.write_parameters("unit-1", wrops) {
+            Err(NetcomError::AuditFailed { error, result }) => {
+                assert_eq!(error.kind(), io::ErrorKind::StorageFull);
+                assert_eq!(result.unwrap().get("sp1"), Some(&Some(21.0)));
+            }
+            other => panic!("Expected an audit failure, got {:?}", other),
+        }
+        assert_eq!(server.value("unit-1", "sp1"), Some(21.0));
+    }
+
+    #[test]
+    fn should_append_json_lines() {
+        let server = MockServer::start().with_device("unit-1", &[("sp1", 20.0)]);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+
+        for v in [1.0, 2.0] {
+            let mut client = NetcomClientSync::new("127.0.0.1", server.port());
+            client.set_audit_log(Some(AuditLog::new(JsonLinesSink::open(&path).unwrap())));
+            let wrops = vec![WrOp::Default {
+                p: "sp1".to_string(),
+                v,
+            }];
+            client.write_parameters("unit-1", wrops).unwrap();
+        }
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        let entries: Vec<AuditEntry> = text
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].new, 1.0);
+        assert_eq!(entries[1].echo, Some(2.0));
+        assert!(!text.contains("error"));
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn should_record_async_writes() {
+        let server = MockServer::start().with_device("unit-1", &[("sp1", 20.0)]);
+        let sink = MemorySink::default();
+        let mut client =
+            crate::netcom_client_async::NetcomClientAsync::new("127.0.0.1", server.port());
+        client.set_audit_log(Some(AuditLog::new(sink.clone()).read_previous(true)));
+        client.push_client_info("scheduler").await.unwrap();
+
+        let params = Setpoints {
+            sp1: 22.0,
+            sp2: 0.0,
+        };
+        client.write_struct("unit-1", &params).await.unwrap();
+
+        let entries = sink.0.lock().unwrap().clone();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].client.as_deref(), Some("scheduler"));
+        assert_eq!(entries[0].previous, Some(20.0));
+        assert_eq!(entries[0].echo, Some(22.0));
+        assert_eq!(entries[1].previous, None);
+    }
+}
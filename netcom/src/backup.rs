@@ -0,0 +1,285 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::netcom::{NetcomError, RdOp, WrOp};
+use crate::snapshot::ParameterSnapshot;
+
+/// Format version written to new config files. Files with a newer version
+/// are rejected on load.
+pub const CONFIG_FILE_VERSION: u32 = 1;
+
+/// Saved parameter values of a device, as written by `backup` and read by
+/// `restore`. Parameters that could not be read are kept as `null` and are
+/// not restored.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ConfigFile {
+    pub version: u32,
+    pub device: String,
+    #[serde(with = "crate::timestamp")]
+    pub timestamp: SystemTime,
+    pub params: BTreeMap<String, Option<f64>>,
+}
+
+impl ConfigFile {
+    pub fn new(device: &str, values: &HashMap<String, Option<f64>>) -> Self {
+        ConfigFile {
+            version: CONFIG_FILE_VERSION,
+            device: device.to_string(),
+            timestamp: SystemTime::now(),
+            params: values.iter().map(|(k, v)| (k.clone(), *v)).collect(),
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String, NetcomError> {
+        serde_json::to_string_pretty(self).map_err(NetcomError::JsonError)
+    }
+
+    pub fn from_json(s: &str) -> Result<Self, NetcomError> {
+        let file: ConfigFile = serde_json::from_str(s).map_err(NetcomError::JsonError)?;
+        if file.version > CONFIG_FILE_VERSION {
+            return Err(NetcomError::InvalidValue(format!(
+                "Unsupported config file version {}",
+                file.version
+            )));
+        }
+        Ok(file)
+    }
+
+    pub fn to_snapshot(&self) -> ParameterSnapshot {
+        ParameterSnapshot {
+            device: self.device.clone(),
+            timestamp: self.timestamp,
+            values: self.params.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct RestoreOptions {
+    /// Read the current values instead of writing anything.
+    pub dry_run: bool,
+    /// Read the parameters back after writing, rather than trusting the
+    /// values echoed by the write.
+    pub verify: bool,
+    /// Largest difference between the requested and actual value that still
+    /// counts as restored.
+    pub tolerance: f64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RestoreStatus {
+    /// Dry run: the value differs from the file and would be written.
+    Pending,
+    /// Dry run: the value already matches the file.
+    Unchanged,
+    /// The device holds the requested value.
+    Restored,
+    /// The device holds a different value, usually limited to its range.
+    Clamped,
+    /// The device returned no value for the parameter.
+    Failed,
+    /// The file holds no value for the parameter, so it was not written.
+    Skipped,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ParamOutcome {
+    pub param: String,
+    pub requested: Option<f64>,
+    /// The value reported by the device: the current value for a dry run,
+    /// otherwise the echoed or read-back value.
+    pub actual: Option<f64>,
+    pub status: RestoreStatus,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RestoreReport {
+    pub device: String,
+    pub dry_run: bool,
+    /// One entry per parameter in the file, in address order.
+    pub params: Vec<ParamOutcome>,
+}
+
+impl RestoreReport {
+    /// Whether every value in the file is now held by the device.
+    pub fn is_success(&self) -> bool {
+        self.params.iter().all(|o| {
+            matches!(
+                o.status,
+                RestoreStatus::Restored | RestoreStatus::Unchanged | RestoreStatus::Skipped
+            )
+        })
+    }
+
+    pub fn with_status(&self, status: RestoreStatus) -> impl Iterator<Item = &ParamOutcome> {
+        self.params.iter().filter(move |o| o.status == status)
+    }
+}
+
+pub(crate) fn backup_rdops(params: &[&str]) -> Vec<RdOp> {
+    params
+        .iter()
+        .map(|p| RdOp::Default { p: p.to_string() })
+        .collect()
+}
+
+/// A config file holding every requested parameter, `null` where the read
+/// returned no value.
+pub(crate) fn backup_file(
+    device: &str,
+    params: &[&str],
+    values: &HashMap<String, Option<f64>>,
+) -> ConfigFile {
+    let values = params
+        .iter()
+        .map(|p| (p.to_string(), values.get(*p).copied().flatten()))
+        .collect();
+    ConfigFile::new(device, &values)
+}
+
+/// Read operations for every parameter in the file.
+pub(crate) fn restore_rdops(file: &ConfigFile) -> Vec<RdOp> {
+    file.params
+        .keys()
+        .map(|p| RdOp::Default { p: p.clone() })
+        .collect()
+}
+
+/// Write operations for every non-null value in the file.
+pub(crate) fn restore_wrops(file: &ConfigFile) -> Vec<WrOp> {
+    file.to_snapshot().to_wrops()
+}
+
+/// Compares the values reported by the device with the file.
+pub(crate) fn restore_report(
+    device: &str,
+    file: &ConfigFile,
+    options: &RestoreOptions,
+    actual: &HashMap<String, Option<f64>>,
+) -> RestoreReport {
+    let params = file
+        .params
+        .iter()
+        .map(|(p, requested)| {
+            let value = actual.get(p).copied().flatten();
+            let matches = |r: f64| value.is_some_and(|v| (v - r).abs() <= options.tolerance);
+            let status = match (requested, options.dry_run) {
+                (None, _) => RestoreStatus::Skipped,
+                (Some(r), true) if matches(*r) => RestoreStatus::Unchanged,
+                (Some(_), true) => RestoreStatus::Pending,
+                (Some(_), false) if value.is_none() => RestoreStatus::Failed,
+                (Some(r), false) if matches(*r) => RestoreStatus::Restored,
+                (Some(_), false) => RestoreStatus::Clamped,
+            };
+            ParamOutcome {
+                param: p.clone(),
+                requested: *requested,
+                actual: value,
+                status,
+            }
+        })
+        .collect();
+
+    RestoreReport {
+        device: device.to_string(),
+        dry_run: options.dry_run,
+        params,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_server::MockServer;
+    use crate::netcom_client_sync::NetcomClientSync;
+
+    fn server() -> MockServer {
+        MockServer::start()
+            .with_device("unit-1", &[("sp1", 20.0), ("sp2", 120.0)])
+            .with_device("unit-2", &[("sp1", 0.0), ("sp2", 0.0)])
+            .with_range("unit-2", "sp2", 0.0, 100.0)
+    }
+
+    #[test]
+    fn should_round_trip_config_file_through_json() {
+        let values = HashMap::from([("sp1".to_string(), Some(20.0)), ("sp9".to_string(), None)]);
+        let file = ConfigFile::new("unit-1", &values);
+        let restored = ConfigFile::from_json(&file.to_json().unwrap()).unwrap();
+        assert_eq!(restored, file);
+
+        let newer = file
+            .to_json()
+            .unwrap()
+            .replace("\"version\": 1", "\"version\": 2");
+        assert!(matches!(
+            ConfigFile::from_json(&newer),
+            Err(NetcomError::InvalidValue(_))
+        ));
+    }
+
+    #[test]
+    fn should_restore_backup_and_report_clamped_params() {
+        let server = server();
+        let mut client = NetcomClientSync::new("127.0.0.1", server.port());
+
+        let file = client.backup("unit-1", &["sp1", "sp2", "sp9"]).unwrap();
+        assert_eq!(file.params.get("sp9"), Some(&None));
+
+        let options = RestoreOptions {
+            verify: true,
+            ..Default::default()
+        };
+        let report = client.restore("unit-2", &file, &options).unwrap();
+        assert!(!report.is_success());
+        let statuses: Vec<_> = report.params.iter().map(|o| o.status).collect();
+        assert_eq!(
+            statuses,
+            [
+                RestoreStatus::Restored,
+                RestoreStatus::Clamped,
+                RestoreStatus::Skipped
+            ]
+        );
+        assert_eq!(report.params[1].actual, Some(100.0));
+        assert_eq!(server.value("unit-2", "sp1"), Some(20.0));
+    }
+
+    #[test]
+    fn should_not_write_on_dry_run() {
+        let server = server();
+        let mut client = NetcomClientSync::new("127.0.0.1", server.port());
+        let file = client.backup("unit-1", &["sp1", "sp2"]).unwrap();
+        server.set_value("unit-2", "sp1", 20.0);
+
+        let options = RestoreOptions {
+            dry_run: true,
+            ..Default::default()
+        };
+        let report = client.restore("unit-2", &file, &options).unwrap();
+        assert_eq!(report.params[0].status, RestoreStatus::Unchanged);
+        assert_eq!(report.params[1].status, RestoreStatus::Pending);
+        assert_eq!(report.params[1].actual, Some(0.0));
+        assert_eq!(server.request_count("write"), 0);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn should_restore_backup_with_async_client() {
+        let server = server();
+        let mut client =
+            crate::netcom_client_async::NetcomClientAsync::new("127.0.0.1", server.port());
+
+        let file = client.backup("unit-1", &["sp1", "sp2"]).await.unwrap();
+        let report = client
+            .restore("unit-2", &file, &RestoreOptions::default())
+            .await
+            .unwrap();
+        let clamped: Vec<_> = report.with_status(RestoreStatus::Clamped).collect();
+        assert_eq!(clamped.len(), 1);
+        assert_eq!(clamped[0].param, "sp2");
+        assert_eq!(clamped[0].actual, Some(100.0));
+    }
+}
@@ -0,0 +1,179 @@
+//! Conditional writes.
+//!
+//! The netcom protocol has no conditional write, so the check is made on
+//! the client: the parameters are read, compared with the expected values
+//! and only then written. Another client can still write between the read
+//! and the write, so this narrows races rather than ruling them out. Should
+//! the protocol gain a server-side compare-and-set, the client methods are
+//! the place to use it.
+use std::collections::HashMap;
+
+use crate::netcom::{RdOp, WrOp};
+
+/// One parameter of a conditional write. The current value is read with
+/// the type of the write.
+#[derive(Clone, Debug)]
+pub struct CasOp {
+    pub op: WrOp,
+    pub expected: f64,
+}
+
+impl CasOp {
+    pub fn new(op: WrOp, expected: f64) -> Self {
+        CasOp { op, expected }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CasOptions {
+    /// Largest difference between the expected and current value that still
+    /// counts as a match.
+    pub tolerance: f64,
+}
+
+/// A parameter whose current value was not the expected one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Conflict {
+    pub param: String,
+    pub expected: f64,
+    /// `None` if the device returned no value.
+    pub actual: Option<f64>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum CasOutcome {
+    /// Every current value matched and the write was made; holds the values
+    /// echoed by the device.
+    Written(HashMap<String, Option<f64>>),
+    /// At least one current value differed and nothing was written.
+    Conflict(Vec<Conflict>),
+}
+
+impl CasOutcome {
+    pub fn is_written(&self) -> bool {
+        matches!(self, CasOutcome::Written(_))
+    }
+}
+
+pub(crate) fn cas_rdops(ops: &[CasOp]) -> Vec<RdOp> {
+    ops.iter().map(|op| op.op.to_rdop()).collect()
+}
+
+pub(crate) fn cas_wrops(ops: &[CasOp]) -> Vec<WrOp> {
+    ops.iter().map(|op| op.op.clone()).collect()
+}
+
+/// The parameters whose current value is further than the tolerance from
+/// the expected one.
+pub(crate) fn conflicts(
+    ops: &[CasOp],
+    current: &HashMap<String, Option<f64>>,
+    options: &CasOptions,
+) -> Vec<Conflict> {
+    ops.iter()
+        .filter_map(|op| {
+            let param = op.op.address();
+            let actual = current.get(param).copied().flatten();
+            let matches = actual.is_some_and(|v| (v - op.expected).abs() <= options.tolerance);
+            (!matches).then(|| Conflict {
+                param: param.to_string(),
+                expected: op.expected,
+                actual,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_server::MockServer;
+    use crate::netcom_client_sync::NetcomClientSync;
+
+    fn mode(v: f64) -> WrOp {
+        WrOp::WithType {
+            p: "mode".to_string(),
+            t: "u16".to_string(),
+            v,
+        }
+    }
+
+    fn speed(v: f64) -> WrOp {
+        WrOp::Default {
+            p: "speed".to_string(),
+            v,
+        }
+    }
+
+    #[test]
+    fn should_write_only_when_current_value_matches() {
+        let server = MockServer::start().with_device("pump-3", &[("mode", 1.0)]);
+        let mut client = NetcomClientSync::new("127.0.0.1", server.port());
+        let exact = CasOptions::default();
+
+        let outcome = client
+            .compare_and_write("pump-3", mode(2.0), 1.0, &exact)
+            .unwrap();
+        assert!(outcome.is_written());
+        assert_eq!(server.value("pump-3", "mode"), Some(2.0));
+
+        let outcome = client
+            .compare_and_write("pump-3", mode(3.0), 1.0, &exact)
+            .unwrap();
+        assert_eq!(
+            outcome,
+            CasOutcome::Conflict(vec![Conflict {
+                param: "mode".to_string(),
+                expected: 1.0,
+                actual: Some(2.0),
+            }])
+        );
+        assert_eq!(server.value("pump-3", "mode"), Some(2.0));
+        assert_eq!(server.request_count("write"), 1);
+    }
+
+    #[test]
+    fn should_match_within_tolerance() {
+        let ops = [CasOp::new(speed(1200.0), 900.0)];
+        let current = HashMap::from([("speed".to_string(), Some(900.4))]);
+
+        let exact = CasOptions::default();
+        assert_eq!(conflicts(&ops, &current, &exact).len(), 1);
+        let loose = CasOptions { tolerance: 0.5 };
+        assert_eq!(conflicts(&ops, &current, &loose), []);
+
+        let missing = HashMap::from([("speed".to_string(), None)]);
+        assert_eq!(conflicts(&ops, &missing, &loose)[0].actual, None);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn should_write_all_or_nothing() {
+        let server = MockServer::start().with_device("pump-3", &[("mode", 1.0), ("speed", 900.0)]);
+        let mut client =
+            crate::netcom_client_async::NetcomClientAsync::new("127.0.0.1", server.port());
+
+        let options = CasOptions::default();
+        let ops = [
+            CasOp::new(mode(2.0), 1.0),
+            CasOp::new(speed(1200.0), 1000.0),
+        ];
+        let outcome = client
+            .compare_and_write_all("pump-3", &ops, &options)
+            .await
+            .unwrap();
+        assert!(matches!(
+            &outcome,
+            CasOutcome::Conflict(c) if c.len() == 1 && c[0].actual == Some(900.0)
+        ));
+        assert_eq!(server.value("pump-3", "mode"), Some(1.0));
+
+        let ops = [CasOp::new(mode(2.0), 1.0), CasOp::new(speed(1200.0), 900.0)];
+        let outcome = client
+            .compare_and_write_all("pump-3", &ops, &options)
+            .await
+            .unwrap();
+        assert!(outcome.is_written());
+        assert_eq!(server.value("pump-3", "speed"), Some(1200.0));
+    }
+}
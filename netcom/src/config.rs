@@ -0,0 +1,145 @@
+//! Pieces shared by the TOML configs of the netcom services: the error type,
+//! parsing and the `[[device]]` tables listing what to poll.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use crate::netcom::{RdOp, WrOp};
+
+/// A device and the parameters to poll from it, as a `[[device]]` table:
+///
+/// ```toml
+/// [[device]]
+/// name = "pump-3"
+/// params = ["speed", "pressure", "mode"]
+/// writes = ["speed", "mode"]
+/// types = { mode = "u16" }
+/// ```
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceConfig {
+    pub name: String,
+    pub params: Vec<String>,
+
+    /// Parameters that may be written, for services that accept writes.
+    /// Devices accept no writes unless they list them here.
+    #[serde(default)]
+    pub writes: Vec<String>,
+
+    /// Types to read and write parameters with, by parameter. Parameters
+    /// not listed use the device's default type.
+    #[serde(default)]
+    pub types: BTreeMap<String, String>,
+}
+
+impl DeviceConfig {
+    /// Read operations for every parameter in `params`.
+    pub fn rdops(&self) -> Vec<RdOp> {
+        self.params
+            .iter()
+            .map(|p| match self.types.get(p) {
+                Some(t) => RdOp::WithType {
+                    p: p.clone(),
+                    t: t.clone(),
+                },
+                None => RdOp::Default { p: p.clone() },
+            })
+            .collect()
+    }
+
+    /// A write of `v` to `param`, with the parameter's configured type.
+    pub fn wrop(&self, param: &str, v: f64) -> WrOp {
+        match self.types.get(param) {
+            Some(t) => WrOp::WithType {
+                p: param.to_string(),
+                t: t.clone(),
+                v,
+            },
+            None => WrOp::Default {
+                p: param.to_string(),
+                v,
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Parse(toml::de::Error),
+    /// The config parsed, but a value in it cannot be used.
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Parse(e) => write!(f, "Invalid config: {}", e),
+            ConfigError::Invalid(s) => write!(f, "Invalid config: {}", s),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Deserializes a config from TOML.
+pub fn parse<T: DeserializeOwned>(s: &str) -> Result<T, ConfigError> {
+    toml::from_str(s).map_err(ConfigError::Parse)
+}
+
+/// Default of `interval_ms`, the time between two polls.
+pub fn default_interval_ms() -> u64 {
+    1000
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[derive(Debug, Deserialize)]
+    struct Config {
+        #[serde(default = "default_interval_ms")]
+        interval_ms: u64,
+        #[serde(rename = "device")]
+        devices: Vec<DeviceConfig>,
+    }
+
+    #[test]
+    fn should_parse_devices() {
+        let config: Config = parse(
+            r#"
+            [[device]]
+            name = "pump-3"
+            params = ["speed", "pressure"]
+            types = { pressure = "i16" }
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            Duration::from_millis(config.interval_ms),
+            Duration::from_secs(1)
+        );
+        assert_eq!(config.devices[0].params, ["speed", "pressure"]);
+        assert!(config.devices[0].writes.is_empty());
+        assert_eq!(
+            config.devices[0].rdops(),
+            [
+                RdOp::Default {
+                    p: "speed".to_string()
+                },
+                RdOp::WithType {
+                    p: "pressure".to_string(),
+                    t: "i16".to_string()
+                },
+            ]
+        );
+        assert!(matches!(
+            parse::<Config>("[[device]]\nname = \"pump-3\""),
+            Err(ConfigError::Parse(_))
+        ));
+    }
+}
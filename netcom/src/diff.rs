@@ -0,0 +1,216 @@
+use std::fmt::Write;
+
+use serde::Serialize;
+
+use crate::netcom::NetcomError;
+use crate::snapshot::ParameterSnapshot;
+
+#[derive(Clone, Debug, Default)]
+pub struct DiffOptions {
+    /// Largest difference between two values that still counts as equal.
+    pub tolerance: f64,
+    /// Parameters left out of the comparison. A `*` matches any run of
+    /// characters, e.g. `alarm.*`.
+    pub ignore: Vec<String>,
+}
+
+/// One difference between two snapshots, from the left to the right one.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "change", rename_all = "lowercase")]
+pub enum DiffEntry {
+    /// Only in the right snapshot.
+    Added { param: String, value: Option<f64> },
+    /// Only in the left snapshot.
+    Removed { param: String, value: Option<f64> },
+    Changed {
+        param: String,
+        left: Option<f64>,
+        right: Option<f64>,
+    },
+}
+
+impl DiffEntry {
+    pub fn param(&self) -> &str {
+        match self {
+            DiffEntry::Added { param, .. } => param,
+            DiffEntry::Removed { param, .. } => param,
+            DiffEntry::Changed { param, .. } => param,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SnapshotDiff {
+    pub left: String,
+    pub right: String,
+    /// Entries in parameter address order.
+    pub entries: Vec<DiffEntry>,
+}
+
+impl SnapshotDiff {
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// A line per entry, prefixed `+`, `-` or `~` like a unified diff.
+    pub fn to_text(&self) -> String {
+        let mut out = format!("--- {}\n+++ {}\n", self.left, self.right);
+        for entry in &self.entries {
+            let _ = match entry {
+                DiffEntry::Added { param, value } => {
+                    writeln!(out, "+ {} = {}", param, value_text(*value))
+                }
+                DiffEntry::Removed { param, value } => {
+                    writeln!(out, "- {} = {}", param, value_text(*value))
+                }
+                DiffEntry::Changed { param, left, right } => writeln!(
+                    out,
+                    "~ {}: {} -> {}",
+                    param,
+                    value_text(*left),
+                    value_text(*right)
+                ),
+            };
+        }
+        out
+    }
+
+    pub fn to_json(&self) -> Result<String, NetcomError> {
+        serde_json::to_string_pretty(self).map_err(NetcomError::JsonError)
+    }
+}
+
+fn value_text(value: Option<f64>) -> String {
+    value.map_or_else(|| "null".to_string(), |v| v.to_string())
+}
+
+/// Compares two snapshots, e.g. of two devices or of one device at two
+/// points in time. A null value only equals another null value.
+pub fn diff(
+    left: &ParameterSnapshot,
+    right: &ParameterSnapshot,
+    options: &DiffOptions,
+) -> SnapshotDiff {
+    let ignored = |p: &str| options.ignore.iter().any(|pattern| glob_match(pattern, p));
+    let equal = |a: Option<f64>, b: Option<f64>| match (a, b) {
+        (Some(a), Some(b)) => (a - b).abs() <= options.tolerance,
+        (a, b) => a.is_none() && b.is_none(),
+    };
+
+    let mut params: Vec<&String> = left.values.keys().chain(right.values.keys()).collect();
+    params.sort();
+    params.dedup();
+
+    let entries = params
+        .into_iter()
+        .filter(|p| !ignored(p))
+        .filter_map(|p| {
+            let param = p.clone();
+            match (left.values.get(p), right.values.get(p)) {
+                (None, Some(value)) => Some(DiffEntry::Added {
+                    param,
+                    value: *value,
+                }),
+                (Some(value), None) => Some(DiffEntry::Removed {
+                    param,
+                    value: *value,
+                }),
+                (Some(l), Some(r)) if !equal(*l, *r) => Some(DiffEntry::Changed {
+                    param,
+                    left: *l,
+                    right: *r,
+                }),
+                _ => None,
+            }
+        })
+        .collect();
+
+    SnapshotDiff {
+        left: left.device.clone(),
+        right: right.device.clone(),
+        entries,
+    }
+}
+
+/// Matches `s` against a pattern in which `*` stands for any run of
+/// characters, including none.
+pub(crate) fn glob_match(pattern: &str, s: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == s,
+        Some((prefix, rest)) => {
+            let Some(s) = s.strip_prefix(prefix) else {
+                return false;
+            };
+            (0..=s.len())
+                .filter(|i| s.is_char_boundary(*i))
+                .any(|i| glob_match(rest, &s[i..]))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn snapshot(device: &str, values: &[(&str, Option<f64>)]) -> ParameterSnapshot {
+        let values: HashMap<String, Option<f64>> =
+            values.iter().map(|(p, v)| (p.to_string(), *v)).collect();
+        ParameterSnapshot::new(device, &values)
+    }
+
+    #[test]
+    fn should_match_glob_patterns() {
+        assert!(glob_match("sp1", "sp1"));
+        assert!(!glob_match("sp1", "sp10"));
+        assert!(glob_match("alarm.*", "alarm.high"));
+        assert!(glob_match("*.min", "temp.min"));
+        assert!(glob_match("a*b*c", "axxbyyc"));
+        assert!(!glob_match("a*b*c", "axxbyy"));
+    }
+
+    #[test]
+    fn should_diff_snapshots() {
+        let left = snapshot(
+            "unit-1",
+            &[
+                ("sp1", Some(20.0)),
+                ("sp2", Some(45.0)),
+                ("sp3", None),
+                ("old", Some(1.0)),
+                ("alarm.high", Some(90.0)),
+            ],
+        );
+        let right = snapshot(
+            "unit-2",
+            &[
+                ("sp1", Some(20.004)),
+                ("sp2", Some(46.0)),
+                ("sp3", Some(0.0)),
+                ("new", None),
+                ("alarm.high", Some(95.0)),
+            ],
+        );
+        let options = DiffOptions {
+            tolerance: 0.01,
+            ignore: vec!["alarm.*".to_string()],
+        };
+
+        let diff = diff(&left, &right, &options);
+        assert_eq!(
+            diff.to_text(),
+            "--- unit-1\n\
+             +++ unit-2\n\
+             + new = null\n\
+             - old = 1\n\
+             ~ sp2: 45 -> 46\n\
+             ~ sp3: null -> 0\n"
+        );
+
+        let json: serde_json::Value = serde_json::from_str(&diff.to_json().unwrap()).unwrap();
+        assert_eq!(
+            json["entries"][2],
+            serde_json::json!({ "change": "changed", "param": "sp2", "left": 45.0, "right": 46.0 })
+        );
+    }
+}
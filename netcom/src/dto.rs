@@ -12,7 +12,7 @@ pub struct DeviceListRequestDto {
     pub r: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct DeviceDto {
     pub id: u32,
     pub network: u32,
@@ -50,7 +50,7 @@ pub struct ReadRequestDto {
     pub p: HashMap<String, RdValueDto>,
 }
 
-#[derive(Serialize)]
+#[derive(Clone, Debug, Serialize)]
 #[serde(untagged)]
 pub enum WrValueDto {
     Simple(f64),
@@ -64,7 +64,7 @@ pub enum RdValueDto {
     Detailed { t: String },
 }
 
-#[derive(Serialize)]
+#[derive(Clone, Debug, Serialize)]
 pub struct WriteRequestDto {
     pub r: String,
     pub device: String,
@@ -96,3 +96,29 @@ pub struct ErrorResponseDto {
     #[allow(dead_code)]
     pub message: Option<String>,
 }
+
+#[derive(Serialize)]
+pub struct SubscribeRequestDto {
+    pub r: String,
+    pub device: String,
+    pub p: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct SubscribeResponseDto {
+    #[serde(rename = "R")]
+    pub r: String,
+}
+
+/// Just the response type of a frame, used to pick out push events.
+#[derive(Deserialize)]
+pub struct FrameTypeDto {
+    #[serde(rename = "R")]
+    pub r: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct PushEventDto {
+    pub device: String,
+    pub result: HashMap<String, Option<f64>>,
+}
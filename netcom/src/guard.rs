@@ -0,0 +1,311 @@
+//! Policy checked by the clients before every write, to keep scripts from
+//! writing to the wrong device or far outside the expected values.
+//!
+//! Each list of a guard is an allowlist of `*` patterns. An empty list
+//! allows everything, so a guard that only enables dry-run stops every
+//! write as a dry run.
+
+use std::fmt;
+use std::sync::Arc;
+
+use crate::diff::glob_match;
+use crate::netcom::NetcomError;
+
+/// The request a dry run stops, handed to the hook and carried by
+/// `NetcomError::DryRun`.
+pub use crate::dto::{WrValueDto, WriteRequestDto};
+
+/// Inclusive range of values allowed for parameters matching `param`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RangeRule {
+    pub param: String,
+    pub min: f64,
+    pub max: f64,
+}
+
+/// The rule a blocked write broke, carried by `NetcomError::WriteBlocked`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum GuardRule {
+    /// The device matches none of the allowed device patterns.
+    Device { device: String },
+    /// The parameter matches none of the allowed parameter patterns.
+    Param { device: String, param: String },
+    /// The value lies outside a range whose pattern matches the parameter.
+    Range {
+        device: String,
+        param: String,
+        value: f64,
+        range: RangeRule,
+    },
+}
+
+impl fmt::Display for GuardRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GuardRule::Device { device } => write!(f, "device {:?} is not allowed", device),
+            GuardRule::Param { device, param } => {
+                write!(f, "parameter {:?} of {:?} is not allowed", param, device)
+            }
+            GuardRule::Range {
+                device,
+                param,
+                value,
+                range,
+            } => write!(
+                f,
+                "value {} for {:?} of {:?} is outside {:?} [{}, {}]",
+                value, param, device, range.param, range.min, range.max
+            ),
+        }
+    }
+}
+
+type DryRunHook = Arc<dyn Fn(&WriteRequestDto) + Send + Sync>;
+
+#[derive(Clone, Default)]
+pub struct WriteGuard {
+    devices: Vec<String>,
+    params: Vec<String>,
+    ranges: Vec<RangeRule>,
+    dry_run: bool,
+    on_dry_run: Option<DryRunHook>,
+}
+
+impl fmt::Debug for WriteGuard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WriteGuard")
+            .field("devices", &self.devices)
+            .field("params", &self.params)
+            .field("ranges", &self.ranges)
+            .field("dry_run", &self.dry_run)
+            .field("on_dry_run", &self.on_dry_run.is_some())
+            .finish()
+    }
+}
+
+impl WriteGuard {
+    pub fn new() -> Self {
+        WriteGuard::default()
+    }
+
+    /// Allows writes to devices matching `pattern`.
+    pub fn allow_device(mut self, pattern: &str) -> Self {
+        self.devices.push(pattern.to_string());
+        self
+    }
+
+    /// Allows writes to parameters matching `pattern`, on any allowed device.
+    pub fn allow_param(mut self, pattern: &str) -> Self {
+        self.params.push(pattern.to_string());
+        self
+    }
+
+    /// Limits values written to parameters matching `pattern`. A value must
+    /// lie within every range that matches its parameter.
+    pub fn with_range(mut self, pattern: &str, min: f64, max: f64) -> Self {
+        self.ranges.push(RangeRule {
+            param: pattern.to_string(),
+            min,
+            max,
+        });
+        self
+    }
+
+    /// Stops allowed writes before they are sent, failing them with
+    /// `NetcomError::DryRun`, which carries the request.
+    pub fn dry_run(mut self, enabled: bool) -> Self {
+        self.dry_run = enabled;
+        self
+    }
+
+    /// Calls `log` with every request stopped by dry-run mode, e.g. to log
+    /// it as JSON.
+    pub fn on_dry_run(mut self, log: impl Fn(&WriteRequestDto) + Send + Sync + 'static) -> Self {
+        self.on_dry_run = Some(Arc::new(log));
+        self
+    }
+
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// Checks a single value, returning the first rule it breaks.
+    pub fn check(&self, device: &str, param: &str, value: f64) -> Result<(), GuardRule> {
+        let allowed = |patterns: &[String], s: &str| {
+            patterns.is_empty() || patterns.iter().any(|pattern| glob_match(pattern, s))
+        };
+
+        if !allowed(&self.devices, device) {
+            return Err(GuardRule::Device {
+                device: device.to_string(),
+            });
+        }
+        if !allowed(&self.params, param) {
+            return Err(GuardRule::Param {
+                device: device.to_string(),
+                param: param.to_string(),
+            });
+        }
+        let broken = self.ranges.iter().find(|range| {
+            glob_match(&range.param, param) && !(range.min..=range.max).contains(&value)
+        });
+        match broken {
+            Some(range) => Err(GuardRule::Range {
+                device: device.to_string(),
+                param: param.to_string(),
+                value,
+                range: range.clone(),
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+fn value(dto: &WrValueDto) -> f64 {
+    match dto {
+        WrValueDto::Simple(v) => *v,
+        WrValueDto::Detailed { v, .. } => *v,
+    }
+}
+
+/// Applies `guard` to a write about to be sent. Blocks the whole request if
+/// any parameter breaks a rule, or hands it to the dry-run hook and returns
+/// it in `NetcomError::DryRun` in dry-run mode. Nothing is echoed for a dry
+/// run, so callers that act on the echo, such as verified writes or
+/// restores, stop instead of trusting values the device never received.
+pub(crate) fn guard_write(
+    guard: Option<&WriteGuard>,
+    req: &WriteRequestDto,
+) -> Result<(), NetcomError> {
+    let Some(guard) = guard else {
+        return Ok(());
+    };
+
+    let mut params: Vec<(&String, &WrValueDto)> = req.p.iter().collect();
+    params.sort_by(|a, b| a.0.cmp(b.0));
+    for (param, v) in params {
+        guard
+            .check(&req.device, param, value(v))
+            .map_err(NetcomError::WriteBlocked)?;
+    }
+
+    if !guard.dry_run {
+        return Ok(());
+    }
+    if let Some(log) = &guard.on_dry_run {
+        log(req);
+    }
+    Err(NetcomError::DryRun(req.clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::mock_server::MockServer;
+    use crate::netcom::WrOp;
+    use crate::netcom_client_sync::NetcomClientSync;
+
+    fn guard() -> WriteGuard {
+        WriteGuard::new()
+            .allow_device("pump-*")
+            .allow_param("speed")
+            .allow_param("setpoint*")
+            .with_range("setpoint*", 0.0, 100.0)
+    }
+
+    fn write(p: &str, v: f64) -> Vec<WrOp> {
+        vec![WrOp::Default {
+            p: p.to_string(),
+            v,
+        }]
+    }
+
+    #[test]
+    fn should_check_allowlists_and_ranges() {
+        let guard = guard();
+        assert_eq!(guard.check("pump-3", "speed", 5000.0), Ok(()));
+        assert_eq!(guard.check("pump-3", "setpoint1", 100.0), Ok(()));
+        assert_eq!(
+            guard.check("boiler-1", "speed", 1.0),
+            Err(GuardRule::Device {
+                device: "boiler-1".to_string()
+            })
+        );
+        assert_eq!(
+            guard.check("pump-3", "mode", 1.0),
+            Err(GuardRule::Param {
+                device: "pump-3".to_string(),
+                param: "mode".to_string()
+            })
+        );
+        assert_eq!(
+            guard.check("pump-3", "setpoint2", 100.5),
+            Err(GuardRule::Range {
+                device: "pump-3".to_string(),
+                param: "setpoint2".to_string(),
+                value: 100.5,
+                range: RangeRule {
+                    param: "setpoint*".to_string(),
+                    min: 0.0,
+                    max: 100.0
+                }
+            })
+        );
+        assert_eq!(WriteGuard::new().check("any", "thing", -1e9), Ok(()));
+    }
+
+    #[test]
+    fn should_block_writes_without_sending() {
+        let server = MockServer::start()
+            .with_device("pump-3", &[("speed", 1200.0), ("setpoint1", 20.0)])
+            .with_device("boiler-1", &[("speed", 0.0)]);
+        let mut client = NetcomClientSync::new("127.0.0.1", server.port());
+        client.set_write_guard(Some(guard()));
+
+        let mut wrops = write("speed", 900.0);
+        wrops.extend(write("setpoint1", 250.0));
+        match client.write_parameters("pump-3", wrops) {
+            Err(NetcomError::WriteBlocked(GuardRule::Range { param, .. })) => {
+                assert_eq!(param, "setpoint1")
+            }
+            other => panic!("Expected a blocked write, got {:?}", other),
+        }
+        assert_eq!(server.value("pump-3", "speed"), Some(1200.0));
+
+        let err = client
+            .write_parameters("boiler-1", write("speed", 1.0))
+            .unwrap_err();
+        assert_eq!(err.kind(), "write_blocked");
+        assert!(!err.needs_reconnect());
+
+        client
+            .write_parameters("pump-3", write("setpoint1", 42.0))
+            .unwrap();
+        assert_eq!(server.value("pump-3", "setpoint1"), Some(42.0));
+    }
+
+    #[test]
+    fn should_not_send_in_dry_run() {
+        let server = MockServer::start().with_device("pump-3", &[("speed", 1200.0)]);
+        let mut client = NetcomClientSync::new("127.0.0.1", server.port());
+        let logged: Arc<Mutex<Vec<Value>>> = Arc::default();
+        let log = logged.clone();
+        let guard = WriteGuard::new()
+            .dry_run(true)
+            .on_dry_run(move |req| log.lock().unwrap().push(json!(req)));
+        client.set_write_guard(Some(guard));
+
+        let expected = json!({ "r": "write", "device": "pump-3", "p": { "speed": 900.0 } });
+        match client.write_parameters("pump-3", write("speed", 900.0)) {
+            Err(NetcomError::DryRun(req)) => assert_eq!(json!(req), expected),
+            other => panic!("Expected a dry run, got {:?}", other),
+        }
+        assert_eq!(*logged.lock().unwrap(), [expected]);
+        assert_eq!(server.value("pump-3", "speed"), Some(1200.0));
+        assert_eq!(server.request_count("write"), 0);
+    }
+}
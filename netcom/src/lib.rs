@@ -1,10 +1,41 @@
 mod dto;
+mod timestamp;
 
+pub mod audit;
+pub mod backup;
+pub mod cas;
+pub mod diff;
+pub mod guard;
 pub mod netcom;
 pub mod netcom_client_sync;
 pub mod netstring;
+pub mod recording;
+pub mod snapshot;
+pub mod transport;
+pub mod verify;
+
+#[cfg(any(test, feature = "mock-server"))]
+pub mod mock_server;
 
 #[cfg(feature = "tokio")]
 pub mod netcom_client_async;
 
-pub use netcom_macros::NetcomMap;
+#[cfg(feature = "tokio")]
+pub mod live;
+
+#[cfg(feature = "tokio")]
+pub mod poller;
+
+#[cfg(feature = "tokio")]
+pub mod recipe;
+
+#[cfg(feature = "tokio")]
+pub mod reconcile;
+
+#[cfg(feature = "config")]
+pub mod config;
+
+#[cfg(feature = "tls")]
+pub mod tls;
+
+pub use netcom_macros::{NetcomEnum, NetcomMap};
@@ -0,0 +1,482 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+use tokio::sync::{watch, Mutex as AsyncMutex};
+use tokio::time::MissedTickBehavior;
+use tokio_stream::wrappers::WatchStream;
+
+use crate::netcom::{NetcomError, RdOp};
+use crate::netcom_client_async::NetcomClientAsync;
+
+/// State of a watched parameter.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LiveValue {
+    /// Not read yet.
+    Pending,
+    /// The value from the last read, `None` if the device has no value.
+    Value(Option<f64>),
+    /// The last read failed.
+    Failed { kind: &'static str, message: String },
+}
+
+impl LiveValue {
+    fn failed(e: &NetcomError) -> Self {
+        LiveValue::Failed {
+            kind: e.kind(),
+            message: e.to_string(),
+        }
+    }
+
+    /// The value from the last read, `None` if there is none or the read
+    /// failed.
+    pub fn value(&self) -> Option<f64> {
+        match self {
+            LiveValue::Value(v) => *v,
+            _ => None,
+        }
+    }
+}
+
+/// The channel a subscriber receives on: the bare value, or the full
+/// `LiveValue` including pending and failed reads.
+enum Sink {
+    Value(watch::Sender<Option<f64>>),
+    Status(watch::Sender<LiveValue>),
+}
+
+struct Subscriber {
+    tx: Sink,
+    deadband: f64,
+}
+
+impl Subscriber {
+    /// Forwards `value` unless it is within the deadband of the last value
+    /// sent. Any other change, such as a value appearing or a read failing,
+    /// is always forwarded. Value receivers see a failed read as `None`.
+    fn publish(&self, value: LiveValue) {
+        match &self.tx {
+            Sink::Value(tx) => {
+                let value = value.value();
+                tx.send_if_modified(|current| {
+                    let changed = match (*current, value) {
+                        (Some(a), Some(b)) => (a - b).abs() > self.deadband,
+                        (a, b) => a != b,
+                    };
+                    if changed {
+                        *current = value;
+                    }
+                    changed
+                });
+            }
+            Sink::Status(tx) => {
+                tx.send_if_modified(|current| {
+                    let changed = match (&*current, &value) {
+                        (LiveValue::Value(Some(a)), LiveValue::Value(Some(b))) => {
+                            (a - b).abs() > self.deadband
+                        }
+                        (a, b) => a != b,
+                    };
+                    if changed {
+                        *current = value;
+                    }
+                    changed
+                });
+            }
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        match &self.tx {
+            Sink::Value(tx) => tx.is_closed(),
+            Sink::Status(tx) => tx.is_closed(),
+        }
+    }
+}
+
+/// Subscribers by device and read operation.
+type Registry = HashMap<String, HashMap<RdOp, Vec<Subscriber>>>;
+
+/// Current values of device parameters, published on `watch` channels.
+///
+/// A single background task polls every subscribed parameter once per
+/// interval, grouped into one read request per device, however many
+/// subscribers there are. Parameters are dropped from the poll once all
+/// their receivers are gone, and the task ends when the `LiveValues` is
+/// dropped. Value receivers start at `None`, and status receivers at
+/// `LiveValue::Pending`.
+pub struct LiveValues {
+    registry: Arc<Mutex<Registry>>,
+}
+
+impl LiveValues {
+    /// Starts the polling task. Must be called within a tokio runtime.
+    pub fn start(client: NetcomClientAsync, interval: Duration) -> Self {
+        Self::start_shared(Arc::new(AsyncMutex::new(client)), interval)
+    }
+
+    /// Like `start`, for a connection that is also used for other requests.
+    /// The lock is held for one read request at a time.
+    pub fn start_shared(client: Arc<AsyncMutex<NetcomClientAsync>>, interval: Duration) -> Self {
+        let registry = Arc::new(Mutex::new(Registry::new()));
+        tokio::spawn(run(client, interval, Arc::downgrade(&registry)));
+        LiveValues { registry }
+    }
+
+    pub fn subscribe(&self, device: &str, param: &str) -> watch::Receiver<Option<f64>> {
+        self.subscribe_with_deadband(device, param, 0.0)
+    }
+
+    /// Like `subscribe`, ignoring changes of at most `deadband`.
+    pub fn subscribe_with_deadband(
+        &self,
+        device: &str,
+        param: &str,
+        deadband: f64,
+    ) -> watch::Receiver<Option<f64>> {
+        let op = RdOp::Default {
+            p: param.to_string(),
+        };
+        self.subscribe_op(device, op, deadband)
+    }
+
+    /// Like `subscribe_with_deadband`, reading the parameter with `op`, so
+    /// that it can be read with a type.
+    pub fn subscribe_op(
+        &self,
+        device: &str,
+        op: RdOp,
+        deadband: f64,
+    ) -> watch::Receiver<Option<f64>> {
+        let (tx, rx) = watch::channel(None);
+        self.register(device, op, Sink::Value(tx), deadband);
+        rx
+    }
+
+    /// Like `subscribe_op`, also telling a parameter not read yet or whose
+    /// last read failed apart from one without a value.
+    pub fn subscribe_status(
+        &self,
+        device: &str,
+        op: RdOp,
+        deadband: f64,
+    ) -> watch::Receiver<LiveValue> {
+        let (tx, rx) = watch::channel(LiveValue::Pending);
+        self.register(device, op, Sink::Status(tx), deadband);
+        rx
+    }
+
+    fn register(&self, device: &str, op: RdOp, tx: Sink, deadband: f64) {
+        self.registry
+            .lock()
+            .unwrap()
+            .entry(device.to_string())
+            .or_default()
+            .entry(op)
+            .or_default()
+            .push(Subscriber { tx, deadband });
+    }
+
+    /// The value of a parameter as a stream, starting with the current value.
+    pub fn stream(&self, device: &str, param: &str) -> WatchStream<Option<f64>> {
+        WatchStream::new(self.subscribe(device, param))
+    }
+
+    pub fn stream_with_deadband(
+        &self,
+        device: &str,
+        param: &str,
+        deadband: f64,
+    ) -> WatchStream<Option<f64>> {
+        WatchStream::new(self.subscribe_with_deadband(device, param, deadband))
+    }
+
+    pub fn stream_op(&self, device: &str, op: RdOp, deadband: f64) -> WatchStream<Option<f64>> {
+        WatchStream::new(self.subscribe_op(device, op, deadband))
+    }
+
+    pub fn stream_status(&self, device: &str, op: RdOp, deadband: f64) -> WatchStream<LiveValue> {
+        WatchStream::new(self.subscribe_status(device, op, deadband))
+    }
+}
+
+/// Removes closed subscribers and returns the read requests needed for the
+/// parameters left. An address watched with several types takes one
+/// request per type, since a read result holds one value per address.
+fn prune(registry: &mut Registry) -> Vec<(String, Vec<RdOp>)> {
+    for params in registry.values_mut() {
+        for subscribers in params.values_mut() {
+            subscribers.retain(|s| !s.is_closed());
+        }
+        params.retain(|_, subscribers| !subscribers.is_empty());
+    }
+    registry.retain(|_, params| !params.is_empty());
+
+    let mut requests = Vec::new();
+    for (device, params) in registry.iter() {
+        let mut batches: Vec<Vec<RdOp>> = Vec::new();
+        for op in params.keys() {
+            let batch = batches
+                .iter_mut()
+                .find(|batch| batch.iter().all(|o| o.address() != op.address()));
+            match batch {
+                Some(batch) => batch.push(op.clone()),
+                None => batches.push(vec![op.clone()]),
+            }
+        }
+        requests.extend(batches.into_iter().map(|batch| (device.clone(), batch)));
+    }
+    requests
+}
+
+async fn run(
+    client: Arc<AsyncMutex<NetcomClientAsync>>,
+    interval: Duration,
+    registry: Weak<Mutex<Registry>>,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+    loop {
+        ticker.tick().await;
+
+        let requests = match registry.upgrade() {
+            Some(registry) => prune(&mut registry.lock().unwrap()),
+            None => return,
+        };
+
+        for (device, rdops) in requests {
+            let result = client
+                .lock()
+                .await
+                .read_parameters(&device, rdops.clone())
+                .await;
+
+            let registry = match registry.upgrade() {
+                Some(registry) => registry,
+                None => return,
+            };
+            let registry = registry.lock().unwrap();
+            let Some(subscribed) = registry.get(&device) else {
+                continue;
+            };
+            for op in &rdops {
+                let value = match &result {
+                    Ok(result) => LiveValue::Value(result.get(op.address()).copied().flatten()),
+                    Err(e) => LiveValue::failed(e),
+                };
+                for s in subscribed.get(op).into_iter().flatten() {
+                    s.publish(value.clone());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_server::MockServer;
+    use crate::netstring::{split_netstring, ToNetstring};
+    use serde_json::{json, Value};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+    use tokio_stream::StreamExt;
+
+    const INTERVAL: Duration = Duration::from_millis(10);
+
+    #[test]
+    fn should_publish_changes_beyond_deadband() {
+        let (tx, rx) = watch::channel(LiveValue::Pending);
+        let s = Subscriber {
+            tx: Sink::Status(tx),
+            deadband: 0.5,
+        };
+
+        s.publish(LiveValue::Value(Some(10.0)));
+        assert_eq!(*rx.borrow(), LiveValue::Value(Some(10.0)));
+        s.publish(LiveValue::Value(Some(10.5)));
+        assert_eq!(*rx.borrow(), LiveValue::Value(Some(10.0)));
+        s.publish(LiveValue::Value(Some(9.4)));
+        assert_eq!(*rx.borrow(), LiveValue::Value(Some(9.4)));
+        s.publish(LiveValue::Value(None));
+        assert_eq!(*rx.borrow(), LiveValue::Value(None));
+        s.publish(LiveValue::failed(&NetcomError::NotConnected));
+        assert_eq!(rx.borrow().value(), None);
+        assert!(matches!(
+            *rx.borrow(),
+            LiveValue::Failed {
+                kind: "not_connected",
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn should_publish_failed_reads_as_no_value() {
+        let (tx, mut rx) = watch::channel(None);
+        let s = Subscriber {
+            tx: Sink::Value(tx),
+            deadband: 0.5,
+        };
+
+        s.publish(LiveValue::Value(Some(10.0)));
+        assert_eq!(*rx.borrow_and_update(), Some(10.0));
+        s.publish(LiveValue::Value(Some(10.5)));
+        assert!(!rx.has_changed().unwrap());
+        s.publish(LiveValue::failed(&NetcomError::NotConnected));
+        assert_eq!(*rx.borrow_and_update(), None);
+        s.publish(LiveValue::Value(None));
+        assert!(!rx.has_changed().unwrap());
+    }
+
+    #[test]
+    fn should_read_each_type_of_an_address_separately() {
+        let (tx, _rx) = watch::channel(LiveValue::Pending);
+        let subscriber = || Subscriber {
+            tx: Sink::Status(tx.clone()),
+            deadband: 0.0,
+        };
+        let speed = RdOp::Default {
+            p: "speed".to_string(),
+        };
+        let speed_u16 = RdOp::WithType {
+            p: "speed".to_string(),
+            t: "u16".to_string(),
+        };
+        let temp = RdOp::Default {
+            p: "temp".to_string(),
+        };
+
+        let mut registry = Registry::new();
+        let params = registry.entry("pump-3".to_string()).or_default();
+        for op in [&speed, &speed_u16, &temp] {
+            params.insert(op.clone(), vec![subscriber()]);
+        }
+
+        let requests = prune(&mut registry);
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests.iter().map(|(_, ops)| ops.len()).sum::<usize>(), 3);
+        for (device, ops) in requests {
+            assert_eq!(device, "pump-3");
+            assert!(ops.contains(&speed) != ops.contains(&speed_u16));
+        }
+    }
+
+    /// Answers read requests on `stream` from `values`, counting them in
+    /// `reads`. Unlike `MockServer`, it runs on the test's runtime, so the
+    /// paused clock cannot move while a request is in flight.
+    async fn serve(
+        mut stream: DuplexStream,
+        values: Arc<Mutex<HashMap<String, f64>>>,
+        reads: Arc<AtomicUsize>,
+    ) {
+        let mut upgrade = [0; 8];
+        stream.read_exact(&mut upgrade).await.unwrap();
+        let version = json!({ "version": "mock-1.0" }).to_string();
+        stream.write_all(&version.to_netstring()).await.unwrap();
+
+        let mut msg = Vec::new();
+        let mut buf = [0; 128];
+        loop {
+            let (request, consumed) = match split_netstring(&msg) {
+                Ok((s, n)) => (serde_json::from_slice::<Value>(s).unwrap(), n),
+                Err(_) => match stream.read(&mut buf).await {
+                    Ok(0) | Err(_) => return,
+                    Ok(n) => {
+                        msg.extend_from_slice(&buf[..n]);
+                        continue;
+                    }
+                },
+            };
+            msg.drain(..consumed);
+
+            reads.fetch_add(1, Ordering::SeqCst);
+            let result: serde_json::Map<String, Value> = {
+                let values = values.lock().unwrap();
+                let params = request["p"].as_object().unwrap();
+                params
+                    .keys()
+                    .map(|p| (p.clone(), json!(values.get(p))))
+                    .collect()
+            };
+            let response = json!({ "R": "read", "result": result }).to_string();
+            stream.write_all(&response.to_netstring()).await.unwrap();
+        }
+    }
+
+    // Time is paused, so the clock only moves when every task waits for a
+    // timer, and jumps straight to the earliest one.
+    #[tokio::test(start_paused = true)]
+    async fn should_share_reads_between_subscribers() {
+        let values = Arc::new(Mutex::new(HashMap::from([
+            ("speed".to_string(), 1200.0),
+            ("temp".to_string(), 40.0),
+        ])));
+        let reads = Arc::new(AtomicUsize::new(0));
+        let (client, remote) = NetcomClientAsync::duplex(1024);
+        tokio::spawn(serve(remote, values.clone(), reads.clone()));
+        let live = LiveValues::start(client, INTERVAL);
+
+        let mut a = live.subscribe("pump-3", "speed");
+        let mut b = live.stream("pump-3", "speed");
+        let mut c = live.subscribe("pump-3", "temp");
+        assert_eq!(b.next().await, Some(None));
+
+        // The first tick is immediate and reads both parameters at once.
+        a.changed().await.unwrap();
+        assert_eq!(*a.borrow_and_update(), Some(1200.0));
+        assert_eq!(b.next().await, Some(Some(1200.0)));
+        c.changed().await.unwrap();
+        assert_eq!(*c.borrow_and_update(), Some(40.0));
+        assert_eq!(reads.load(Ordering::SeqCst), 1);
+
+        values.lock().unwrap().insert("speed".to_string(), 900.0);
+        a.changed().await.unwrap();
+        assert_eq!(*a.borrow_and_update(), Some(900.0));
+        assert_eq!(reads.load(Ordering::SeqCst), 2);
+
+        // One read request per tick for all three subscriptions, with ten
+        // ticks in the next ten and a half intervals.
+        tokio::time::sleep(INTERVAL * 10 + INTERVAL / 2).await;
+        assert_eq!(reads.load(Ordering::SeqCst), 12);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn should_suppress_changes_within_deadband() {
+        let server = MockServer::start().with_device("tank", &[("level", 50.0)]);
+        let live = LiveValues::start(NetcomClientAsync::new("127.0.0.1", server.port()), INTERVAL);
+
+        let mut level = live.subscribe_with_deadband("tank", "level", 1.0);
+        level.changed().await.unwrap();
+        assert_eq!(*level.borrow_and_update(), Some(50.0));
+
+        server.set_value("tank", "level", 50.8);
+        tokio::time::sleep(INTERVAL * 5).await;
+        assert!(!level.has_changed().unwrap());
+
+        server.set_value("tank", "level", 51.5);
+        level.changed().await.unwrap();
+        assert_eq!(*level.borrow_and_update(), Some(51.5));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn should_publish_failed_reads() {
+        let server = MockServer::start();
+        let live = LiveValues::start(NetcomClientAsync::new("127.0.0.1", server.port()), INTERVAL);
+
+        let op = RdOp::Default {
+            p: "speed".to_string(),
+        };
+        let mut speed = live.subscribe_status("missing", op, 0.0);
+        assert_eq!(*speed.borrow_and_update(), LiveValue::Pending);
+        speed.changed().await.unwrap();
+        assert_eq!(
+            *speed.borrow_and_update(),
+            LiveValue::Failed {
+                kind: "device_not_found",
+                message: "Device not found".to_string()
+            }
+        );
+    }
+}
@@ -0,0 +1,302 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpListener,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use serde_json::{json, Value};
+
+use crate::netstring::{split_netstring, NetstringError, ToNetstring};
+
+type Devices = HashMap<String, HashMap<String, f64>>;
+
+struct Connection {
+    writer: Box<dyn Write + Send>,
+    subscriptions: HashSet<(String, String)>,
+}
+
+#[derive(Default)]
+struct State {
+    devices: Devices,
+    ranges: HashMap<(String, String), (f64, f64)>,
+    read_only: HashSet<(String, String)>,
+    write_only: HashSet<(String, String)>,
+    requests: HashMap<String, usize>,
+    connections: HashMap<usize, Connection>,
+    next_connection: usize,
+}
+
+impl State {
+    /// Sends a push event to every connection subscribed to the parameter.
+    fn notify(&mut self, device: &str, param: &str) {
+        let value = self
+            .devices
+            .get(device)
+            .and_then(|params| params.get(param));
+        let event = json!({ "R": "event", "device": device, "result": { param: value } });
+        let key = (device.to_string(), param.to_string());
+
+        for connection in self.connections.values_mut() {
+            if connection.subscriptions.contains(&key) {
+                send(&mut connection.writer, &event);
+            }
+        }
+    }
+}
+
+/// In-process netcom server used by the unit tests. It answers the
+/// `PROTO30` upgrade and the device-list, client-info, read and write
+/// requests from an in-memory parameter table, and pushes change events for
+/// parameters subscribed to with `subscribe`.
+pub struct MockServer {
+    port: u16,
+    state: Arc<Mutex<State>>,
+}
+
+impl MockServer {
+    pub fn start() -> MockServer {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let state = Arc::new(Mutex::new(State::default()));
+
+        let shared = state.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let state = shared.clone();
+                let reader = stream.try_clone().unwrap();
+                thread::spawn(move || serve(reader, Box::new(stream), state));
+            }
+        });
+
+        MockServer { port, state }
+    }
+
+    pub fn with_device(self, name: &str, params: &[(&str, f64)]) -> Self {
+        let params = params.iter().map(|(p, v)| (p.to_string(), *v)).collect();
+        self.state
+            .lock()
+            .unwrap()
+            .devices
+            .insert(name.to_string(), params);
+        self
+    }
+
+    /// Clamps values written to the parameter to `min..=max`, like a device
+    /// enforcing its limits.
+    pub fn with_range(self, device: &str, param: &str, min: f64, max: f64) -> Self {
+        self.state
+            .lock()
+            .unwrap()
+            .ranges
+            .insert((device.to_string(), param.to_string()), (min, max));
+        self
+    }
+
+    /// Rejects writes to the parameter, answering them with `null`.
+    pub fn with_read_only(self, device: &str, param: &str) -> Self {
+        self.state
+            .lock()
+            .unwrap()
+            .read_only
+            .insert((device.to_string(), param.to_string()));
+        self
+    }
+
+    /// Fails reads that include the parameter with an error response.
+    pub fn with_write_only(self, device: &str, param: &str) -> Self {
+        self.state
+            .lock()
+            .unwrap()
+            .write_only
+            .insert((device.to_string(), param.to_string()));
+        self
+    }
+
+    /// Also serves the parameter table on a Unix domain socket at `path`.
+    #[cfg(unix)]
+    pub fn with_unix_socket(self, path: &std::path::Path) -> Self {
+        let listener = std::os::unix::net::UnixListener::bind(path).unwrap();
+        let shared = self.state.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let state = shared.clone();
+                let reader = stream.try_clone().unwrap();
+                thread::spawn(move || serve(reader, Box::new(stream), state));
+            }
+        });
+        self
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn value(&self, device: &str, param: &str) -> Option<f64> {
+        self.state
+            .lock()
+            .unwrap()
+            .devices
+            .get(device)
+            .and_then(|params| params.get(param).copied())
+    }
+
+    pub fn set_value(&self, device: &str, param: &str, value: f64) {
+        let mut state = self.state.lock().unwrap();
+        state
+            .devices
+            .entry(device.to_string())
+            .or_default()
+            .insert(param.to_string(), value);
+        state.notify(device, param);
+    }
+
+    /// Number of requests of type `r` (e.g. `"read"`) received so far.
+    pub fn request_count(&self, r: &str) -> usize {
+        self.state
+            .lock()
+            .unwrap()
+            .requests
+            .get(r)
+            .copied()
+            .unwrap_or(0)
+    }
+}
+
+fn serve(reader: impl Read, mut writer: Box<dyn Write + Send>, state: Arc<Mutex<State>>) {
+    let mut reader = BufReader::new(reader);
+
+    let mut line = String::new();
+    if reader.read_line(&mut line).is_err() || line.trim() != "PROTO30" {
+        return;
+    }
+    send(&mut writer, &json!({ "version": "mock-1.0" }));
+
+    // The writer moves into the state, where push events can reach it too.
+    let id = {
+        let mut state = state.lock().unwrap();
+        let id = state.next_connection;
+        state.next_connection += 1;
+        state.connections.insert(
+            id,
+            Connection {
+                writer,
+                subscriptions: HashSet::new(),
+            },
+        );
+        id
+    };
+
+    let mut msg: Vec<u8> = Vec::new();
+    let mut buf = [0; 128];
+
+    loop {
+        let (request, consumed) = match split_netstring(&msg) {
+            Ok((s, n)) => (serde_json::from_slice::<Value>(s).unwrap(), n),
+            Err(NetstringError::Incomplete) => match reader.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => {
+                    msg.extend_from_slice(&buf[..n]);
+                    continue;
+                }
+            },
+            Err(_) => break,
+        };
+        msg.drain(..consumed);
+
+        let mut state = state.lock().unwrap();
+        let r = request["r"].as_str().unwrap_or_default().to_string();
+        *state.requests.entry(r).or_default() += 1;
+        let response = handle(&request, &mut state, id);
+        if let Some(connection) = state.connections.get_mut(&id) {
+            send(&mut connection.writer, &response);
+        }
+    }
+
+    state.lock().unwrap().connections.remove(&id);
+}
+
+fn send(writer: &mut dyn Write, response: &Value) {
+    let _ = writer.write_all(&response.to_string().to_netstring());
+}
+
+fn handle(request: &Value, state: &mut State, connection: usize) -> Value {
+    match request["r"].as_str() {
+        Some("device-list") => {
+            let mut names: Vec<&String> = state.devices.keys().collect();
+            names.sort();
+            let list: Vec<Value> = names
+                .iter()
+                .enumerate()
+                .map(|(i, name)| {
+                    json!({
+                        "id": i,
+                        "network": 0,
+                        "name": name,
+                        "description": "",
+                        "type": "mock"
+                    })
+                })
+                .collect();
+            json!({ "R": "device-list", "devices": list })
+        }
+        Some("client-info") => json!({ "R": "client-info" }),
+        Some(r @ ("read" | "write")) => {
+            let device = request["device"].as_str().unwrap_or_default();
+            let params = match state.devices.get_mut(device) {
+                Some(params) => params,
+                None => return json!({ "error": "notfound", "message": device }),
+            };
+
+            let mut result = serde_json::Map::new();
+            let mut changed = vec![];
+            for (p, v) in request["p"].as_object().unwrap() {
+                if r == "read" && state.write_only.contains(&(device.to_string(), p.clone())) {
+                    return json!({ "error": "notreadable", "message": p });
+                }
+                if r == "write" {
+                    let key = (device.to_string(), p.clone());
+                    if state.read_only.contains(&key) {
+                        result.insert(p.clone(), Value::Null);
+                        continue;
+                    }
+                    let mut value = v.as_f64().or_else(|| v["v"].as_f64()).unwrap();
+                    if let Some((min, max)) = state.ranges.get(&key) {
+                        value = value.clamp(*min, *max);
+                    }
+                    params.insert(p.clone(), value);
+                    changed.push(p.clone());
+                }
+                result.insert(p.clone(), json!(params.get(p)));
+            }
+
+            for p in changed {
+                state.notify(device, &p);
+            }
+            json!({ "R": r, "result": result })
+        }
+        Some(r @ ("subscribe" | "unsubscribe")) => {
+            let device = request["device"].as_str().unwrap_or_default();
+            if !state.devices.contains_key(device) {
+                return json!({ "error": "notfound", "message": device });
+            }
+
+            let subscriptions = &mut state
+                .connections
+                .get_mut(&connection)
+                .unwrap()
+                .subscriptions;
+            for p in request["p"].as_array().unwrap() {
+                let key = (device.to_string(), p.as_str().unwrap().to_string());
+                if r == "subscribe" {
+                    subscriptions.insert(key);
+                } else {
+                    subscriptions.remove(&key);
+                }
+            }
+            json!({ "R": r })
+        }
+        _ => json!({ "error": "badrequest", "message": request.to_string() }),
+    }
+}
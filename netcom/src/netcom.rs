@@ -1,13 +1,22 @@
 use std::collections::HashMap;
+use std::convert::Infallible;
 use std::fmt;
-use std::io::Write;
+#[cfg(feature = "tokio")]
+use std::future::Future;
 use std::str::Utf8Error;
 
-pub use netcom_macros::NetcomMap;
+pub use netcom_macros::{NetcomEnum, NetcomMap};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 
-use crate::dto::{ErrorResponseDto, RdValueDto, ReadRequestDto};
+use crate::dto::{
+    ErrorResponseDto, FrameTypeDto, PushEventDto, RdValueDto, ReadRequestDto, WrValueDto,
+    WriteRequestDto,
+};
+use crate::guard::GuardRule;
+#[cfg(feature = "tokio")]
+use crate::netcom_client_async::NetcomClientAsync;
+use crate::netcom_client_sync::NetcomClientSync;
 use crate::netstring::NetstringError;
 
 pub const DEFAULT_PORT: u16 = 7878;
@@ -21,12 +30,25 @@ pub enum NetcomError {
     Utf8Error(Utf8Error),
     ResponseError(String),
     DeviceNotFound,
+    InvalidValue(String),
+    WriteBlocked(GuardRule),
+    /// A dry-run `WriteGuard` stopped a write that passed its rules. Carries
+    /// the request that would have been sent.
+    DryRun(WriteRequestDto),
+    /// The audit log failed to record a write. Carries the result of the
+    /// write, which was sent.
+    AuditFailed {
+        error: std::io::Error,
+        result: Box<Result<HashMap<String, Option<f64>>, NetcomError>>,
+    },
+    /// Invalid TLS certificates, keys or settings.
+    Tls(String),
 }
 
 impl fmt::Display for NetcomError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            NetcomError::NotConnected => write!(f, "{}", "Not Connected"),
+            NetcomError::NotConnected => write!(f, "Not Connected"),
             NetcomError::StreamError(err) => write!(f, "Stream error: {}", err),
             NetcomError::NetstringError(err) => {
                 write!(f, "Netstring error: {}", err)
@@ -35,30 +57,300 @@ impl fmt::Display for NetcomError {
             NetcomError::Utf8Error(err) => write!(f, "UTF8 error: {}", err),
             NetcomError::ResponseError(err) => write!(f, "Response error: {}", err),
             NetcomError::DeviceNotFound => write!(f, "Device not found"),
+            NetcomError::InvalidValue(err) => write!(f, "Invalid value: {}", err),
+            NetcomError::WriteBlocked(rule) => write!(f, "Write blocked: {}", rule),
+            NetcomError::DryRun(req) => match serde_json::to_string(req) {
+                Ok(json) => write!(f, "Dry run: would send {}", json),
+                Err(_) => write!(f, "Dry run: would write to {:?}", req.device),
+            },
+            NetcomError::AuditFailed { error, .. } => write!(f, "Audit failed: {}", error),
+            NetcomError::Tls(err) => write!(f, "TLS error: {}", err),
+        }
+    }
+}
+
+impl NetcomError {
+    /// Short machine-readable name of the error variant.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            NetcomError::NotConnected => "not_connected",
+            NetcomError::StreamError(_) => "stream",
+            NetcomError::NetstringError(_) => "netstring",
+            NetcomError::JsonError(_) => "json",
+            NetcomError::Utf8Error(_) => "utf8",
+            NetcomError::ResponseError(_) => "response",
+            NetcomError::DeviceNotFound => "device_not_found",
+            NetcomError::InvalidValue(_) => "invalid_value",
+            NetcomError::WriteBlocked(_) => "write_blocked",
+            NetcomError::DryRun(_) => "dry_run",
+            NetcomError::AuditFailed { .. } => "audit_failed",
+            NetcomError::Tls(_) => "tls",
         }
     }
+
+    /// Whether the connection may be out of step with the server after this
+    /// error, so that the client should disconnect and start over.
+    pub fn needs_reconnect(&self) -> bool {
+        matches!(
+            self,
+            NetcomError::StreamError(_)
+                | NetcomError::NetstringError(_)
+                | NetcomError::Utf8Error(_)
+        )
+    }
 }
 
 impl std::error::Error for NetcomError {}
 
-#[derive(Debug, Serialize)]
+impl From<Infallible> for NetcomError {
+    fn from(e: Infallible) -> Self {
+        match e {}
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
 #[serde(untagged)]
 pub enum WrOp {
     Default { p: String, v: f64 },
     WithType { p: String, t: String, v: f64 },
 }
 
-#[derive(Debug, Serialize)]
+impl WrOp {
+    /// The parameter address, which keys the value in a write echo.
+    pub fn address(&self) -> &str {
+        match self {
+            WrOp::Default { p, .. } => p,
+            WrOp::WithType { p, .. } => p,
+        }
+    }
+
+    /// A read of the written parameter, with the same type.
+    pub fn to_rdop(&self) -> RdOp {
+        match self {
+            WrOp::Default { p, .. } => RdOp::Default { p: p.clone() },
+            WrOp::WithType { p, t, .. } => RdOp::WithType {
+                p: p.clone(),
+                t: t.clone(),
+            },
+        }
+    }
+
+    pub fn with_prefix(self, prefix: &str) -> Self {
+        match self {
+            WrOp::Default { p, v } => WrOp::Default {
+                p: format!("{}{}", prefix, p),
+                v,
+            },
+            WrOp::WithType { p, t, v } => WrOp::WithType {
+                p: format!("{}{}", prefix, p),
+                t,
+                v,
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize)]
 #[serde(untagged)]
 pub enum RdOp {
     Default { p: String },
     WithType { p: String, t: String },
 }
 
+impl RdOp {
+    /// The parameter address, which keys the value in a read result.
+    pub fn address(&self) -> &str {
+        match self {
+            RdOp::Default { p } => p,
+            RdOp::WithType { p, .. } => p,
+        }
+    }
+
+    pub fn with_prefix(self, prefix: &str) -> Self {
+        match self {
+            RdOp::Default { p } => RdOp::Default {
+                p: format!("{}{}", prefix, p),
+            },
+            RdOp::WithType { p, t } => RdOp::WithType {
+                p: format!("{}{}", prefix, p),
+                t,
+            },
+        }
+    }
+}
+
 pub trait NetcomSync {
     fn to_wrops(&self) -> Vec<WrOp>;
     fn to_rdops(&self) -> Vec<RdOp>;
+    /// Applies the values in `result`. The `NetcomMap` derive applies
+    /// nothing if a value fails to convert; `try_apply_result` reports why.
     fn apply_result(&mut self, result: &HashMap<String, Option<f64>>);
+
+    /// Checks that every value in `result` converts to the type of its
+    /// field, without applying any of them.
+    fn check_result(&self, result: &HashMap<String, Option<f64>>) -> Result<(), NetcomError> {
+        let _ = result;
+        Ok(())
+    }
+
+    /// Applies `result` if every value converts to the type of its field,
+    /// and leaves the struct unchanged otherwise.
+    fn try_apply_result(
+        &mut self,
+        result: &HashMap<String, Option<f64>>,
+    ) -> Result<(), NetcomError> {
+        self.check_result(result)?;
+        self.apply_result(result);
+        Ok(())
+    }
+
+    /// Write operations with every parameter address prefixed, used when
+    /// the struct is nested inside another `NetcomMap` struct.
+    fn to_wrops_prefixed(&self, prefix: &str) -> Vec<WrOp> {
+        self.to_wrops()
+            .into_iter()
+            .map(|op| op.with_prefix(prefix))
+            .collect()
+    }
+
+    fn to_rdops_prefixed(&self, prefix: &str) -> Vec<RdOp> {
+        self.to_rdops()
+            .into_iter()
+            .map(|op| op.with_prefix(prefix))
+            .collect()
+    }
+
+    fn check_result_prefixed(
+        &self,
+        prefix: &str,
+        result: &HashMap<String, Option<f64>>,
+    ) -> Result<(), NetcomError> {
+        self.check_result(&strip_prefix(prefix, result))
+    }
+
+    /// Applies the entries of `result` whose address starts with `prefix`,
+    /// with the prefix stripped.
+    fn try_apply_result_prefixed(
+        &mut self,
+        prefix: &str,
+        result: &HashMap<String, Option<f64>>,
+    ) -> Result<(), NetcomError> {
+        self.try_apply_result(&strip_prefix(prefix, result))
+    }
+}
+
+fn strip_prefix(
+    prefix: &str,
+    result: &HashMap<String, Option<f64>>,
+) -> HashMap<String, Option<f64>> {
+    result
+        .iter()
+        .filter_map(|(k, v)| k.strip_prefix(prefix).map(|k| (k.to_string(), *v)))
+        .collect()
+}
+
+/// A partial update of a `NetcomMap` struct, generated by the derive as
+/// `<Struct>Patch`, with a `with_<field>` builder method per field. Only the
+/// fields that have been set produce write operations.
+pub trait NetcomPatch {
+    fn to_wrops(&self) -> Vec<WrOp>;
+
+    fn to_wrops_prefixed(&self, prefix: &str) -> Vec<WrOp> {
+        self.to_wrops()
+            .into_iter()
+            .map(|op| op.with_prefix(prefix))
+            .collect()
+    }
+}
+
+/// A `NetcomSync` struct bound to a device name, implemented by the
+/// `NetcomMap` derive for structs marked `#[netcom(device = "...")]`.
+///
+/// The `_from` and `_to` variants take the device name as an argument, for
+/// structs that describe many devices of the same type.
+pub trait NetcomDevice: NetcomSync + Default {
+    const DEVICE: &'static str;
+
+    fn read(client: &mut NetcomClientSync) -> Result<Self, NetcomError> {
+        Self::read_from(client, Self::DEVICE)
+    }
+
+    fn read_from(client: &mut NetcomClientSync, device: &str) -> Result<Self, NetcomError> {
+        let mut params = Self::default();
+        client.read_struct(device, &mut params)?;
+        Ok(params)
+    }
+
+    fn write(
+        &self,
+        client: &mut NetcomClientSync,
+    ) -> Result<HashMap<String, Option<f64>>, NetcomError> {
+        self.write_to(client, Self::DEVICE)
+    }
+
+    fn write_to(
+        &self,
+        client: &mut NetcomClientSync,
+        device: &str,
+    ) -> Result<HashMap<String, Option<f64>>, NetcomError> {
+        client.write_struct(device, self)
+    }
+
+    #[cfg(feature = "tokio")]
+    fn read_async(
+        client: &mut NetcomClientAsync,
+    ) -> impl Future<Output = Result<Self, NetcomError>> + Send
+    where
+        Self: Send,
+    {
+        Self::read_from_async(client, Self::DEVICE)
+    }
+
+    #[cfg(feature = "tokio")]
+    fn read_from_async(
+        client: &mut NetcomClientAsync,
+        device: &str,
+    ) -> impl Future<Output = Result<Self, NetcomError>> + Send
+    where
+        Self: Send,
+    {
+        async move {
+            let mut params = Self::default();
+            client.read_struct(device, &mut params).await?;
+            Ok(params)
+        }
+    }
+
+    #[cfg(feature = "tokio")]
+    fn write_async(
+        &self,
+        client: &mut NetcomClientAsync,
+    ) -> impl Future<Output = Result<HashMap<String, Option<f64>>, NetcomError>> + Send
+    where
+        Self: Sync,
+    {
+        self.write_to_async(client, Self::DEVICE)
+    }
+
+    #[cfg(feature = "tokio")]
+    fn write_to_async(
+        &self,
+        client: &mut NetcomClientAsync,
+        device: &str,
+    ) -> impl Future<Output = Result<HashMap<String, Option<f64>>, NetcomError>> + Send
+    where
+        Self: Sync,
+    {
+        client.write_struct(device, self)
+    }
+}
+
+/// Unsolicited notification of changed parameter values, sent by servers
+/// for parameters the client has subscribed to.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PushEvent {
+    pub device: String,
+    pub values: HashMap<String, Option<f64>>,
 }
 
 #[derive(Eq, Hash, PartialEq)]
@@ -72,9 +364,9 @@ where
     T: DeserializeOwned,
 {
     match std::str::from_utf8(data) {
-        Ok(s) => match serde_json::from_str(&s) {
+        Ok(s) => match serde_json::from_str(s) {
             Ok(r) => Ok(r),
-            Err(e) => match serde_json::from_str::<ErrorResponseDto>(&s) {
+            Err(e) => match serde_json::from_str::<ErrorResponseDto>(s) {
                 Ok(error_response) => match error_response.error.as_str() {
                     "notfound" => Err(NetcomError::DeviceNotFound),
                     _ => Err(NetcomError::JsonError(e)),
@@ -86,6 +378,21 @@ where
     }
 }
 
+/// Returns the push event carried by a frame, or `None` for any other frame.
+pub(super) fn parse_push_event(data: &[u8]) -> Option<PushEvent> {
+    match serde_json::from_slice::<FrameTypeDto>(data) {
+        Ok(FrameTypeDto { r: Some(r) }) if r == "event" => {
+            serde_json::from_slice::<PushEventDto>(data)
+                .ok()
+                .map(|e| PushEvent {
+                    device: e.device,
+                    values: e.result,
+                })
+        }
+        _ => None,
+    }
+}
+
 pub(super) fn build_read_request(device: &str, parameters: Vec<RdOp>) -> ReadRequestDto {
     let mut p = HashMap::<String, RdValueDto>::new();
 
@@ -107,13 +414,129 @@ pub(super) fn build_read_request(device: &str, parameters: Vec<RdOp>) -> ReadReq
     }
 }
 
-#[cfg(test)]
+pub(super) fn build_write_request(device: &str, parameters: Vec<WrOp>) -> WriteRequestDto {
+    let mut p = HashMap::<String, WrValueDto>::new();
+
+    for op in parameters {
+        match op {
+            WrOp::Default { p: pp, v } => p.insert(pp, WrValueDto::Simple(v)),
+            WrOp::WithType { p: pp, t, v } => p.insert(pp, WrValueDto::Detailed { v, t }),
+        };
+    }
+
+    WriteRequestDto {
+        r: "write".to_string(),
+        device: device.to_string(),
+        p,
+    }
+}
+
+// The tests use the async client, so they only build with tokio.
+#[cfg(all(test, feature = "tokio"))]
+#[allow(clippy::approx_constant)]
 mod tests {
     use serde_json::{json, Value};
 
     use crate::dto::{WrValueDto, WriteRequestDto};
+    use crate::mock_server::MockServer;
+    use crate::netcom::{
+        NetcomDevice, NetcomEnum, NetcomError, NetcomMap, NetcomPatch, NetcomSync, RdOp, WrOp,
+    };
+    use crate::netcom_client_async::NetcomClientAsync;
+    use crate::netcom_client_sync::NetcomClientSync;
     use std::collections::HashMap;
 
+    #[derive(Default, NetcomMap)]
+    #[netcom(crate = "crate")]
+    struct Channel {
+        #[param(p = "temp")]
+        temp: f64,
+    }
+
+    #[derive(Default, NetcomMap)]
+    #[netcom(crate = "crate")]
+    struct Unit {
+        #[param(p = "mode")]
+        mode: f64,
+
+        #[param(p = "setpoint{}", start = 1)]
+        setpoints: [f64; 2],
+
+        #[param(prefix = "ch{}.", start = 1)]
+        channels: [Channel; 3],
+
+        #[param(prefix = "aux.")]
+        aux: Channel,
+    }
+
+    #[derive(Clone, Copy, Debug, Default, PartialEq, NetcomEnum)]
+    #[netcom(crate = "crate")]
+    enum Mode {
+        #[default]
+        Off = 0,
+        Auto = 1,
+        Manual = 2,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, NetcomEnum)]
+    #[netcom(crate = "crate")]
+    #[repr(i64)]
+    enum Fan {
+        Stopped = 0,
+        Running = 1,
+        Unknown(i64),
+    }
+
+    #[derive(Default, NetcomMap)]
+    #[netcom(crate = "crate")]
+    struct Controller {
+        #[param(p = "mode")]
+        mode: Mode,
+
+        #[param(p = "speed")]
+        speed: f64,
+    }
+
+    #[derive(Default, NetcomMap)]
+    #[netcom(crate = "crate", device = "boiler-1")]
+    struct BoilerStatus {
+        #[param(p = "temp")]
+        temp: f64,
+
+        #[param(p = "mode")]
+        mode: Mode,
+    }
+
+    fn boiler_server() -> MockServer {
+        MockServer::start()
+            .with_device("boiler-1", &[("temp", 61.0), ("mode", 1.0)])
+            .with_device("boiler-2", &[("temp", 55.0), ("mode", 0.0)])
+    }
+
+    fn write_values(ops: Vec<WrOp>) -> Vec<(String, f64)> {
+        let mut values: Vec<(String, f64)> = ops
+            .into_iter()
+            .map(|op| match op {
+                WrOp::Default { p, v } => (p, v),
+                WrOp::WithType { p, v, .. } => (p, v),
+            })
+            .collect();
+        values.sort_by(|a, b| a.0.cmp(&b.0));
+        values
+    }
+
+    fn addresses(ops: Vec<RdOp>) -> Vec<String> {
+        let mut addresses: Vec<String> = ops
+            .into_iter()
+            .map(|op| match op {
+                RdOp::Default { p } => p,
+                RdOp::WithType { p, .. } => p,
+            })
+            .collect();
+        addresses.sort();
+        addresses
+    }
+
     #[tokio::test]
     async fn should_serialize_write_request_dto() {
         let mut p = HashMap::<String, WrValueDto>::new();
@@ -153,4 +576,181 @@ mod tests {
             Err(e) => panic!("JSON serialization failed: {:?}", e),
         }
     }
+
+    #[test]
+    fn should_flatten_nested_and_array_params() {
+        let unit = Unit::default();
+        assert_eq!(
+            addresses(unit.to_rdops()),
+            vec![
+                "aux.temp",
+                "ch1.temp",
+                "ch2.temp",
+                "ch3.temp",
+                "mode",
+                "setpoint1",
+                "setpoint2"
+            ]
+        );
+        assert_eq!(unit.to_wrops().len(), 7);
+        assert_eq!(
+            Unit::metadata().get("channels[2].temp"),
+            Some(&"ch3.temp".to_string())
+        );
+    }
+
+    #[test]
+    fn should_apply_nested_and_array_results() {
+        let mut unit = Unit::default();
+        let mut result = HashMap::new();
+        result.insert("mode".to_string(), Some(2.0));
+        result.insert("setpoint2".to_string(), Some(21.5));
+        result.insert("ch2.temp".to_string(), Some(40.0));
+        result.insert("ch3.temp".to_string(), None);
+        result.insert("aux.temp".to_string(), Some(-5.0));
+        unit.try_apply_result(&result).unwrap();
+
+        assert_eq!(unit.mode, 2.0);
+        assert_eq!(unit.setpoints, [0.0, 21.5]);
+        assert_eq!(unit.channels[1].temp, 40.0);
+        assert_eq!(unit.channels[2].temp, 0.0);
+        assert_eq!(unit.aux.temp, -5.0);
+
+        match unit.to_wrops().into_iter().find(|op| match op {
+            WrOp::Default { p, .. } => p == "ch2.temp",
+            _ => false,
+        }) {
+            Some(WrOp::Default { v, .. }) => assert_eq!(v, 40.0),
+            _ => panic!("Missing write operation for ch2.temp"),
+        }
+    }
+
+    #[test]
+    fn should_convert_enum_params() {
+        assert_eq!(f64::from(Mode::Manual), 2.0);
+        assert_eq!(Mode::try_from(1.0).unwrap(), Mode::Auto);
+        assert!(matches!(
+            Mode::try_from(7.0),
+            Err(NetcomError::InvalidValue(_))
+        ));
+        assert!(matches!(
+            Mode::try_from(1.5),
+            Err(NetcomError::InvalidValue(_))
+        ));
+
+        assert_eq!(Fan::try_from(1.0).unwrap(), Fan::Running);
+        assert_eq!(Fan::try_from(9.0).unwrap(), Fan::Unknown(9));
+        assert_eq!(f64::from(Fan::Unknown(9)), 9.0);
+        for value in [0.5, 1e19, -1e19, f64::NAN, f64::INFINITY] {
+            assert!(matches!(
+                Fan::try_from(value),
+                Err(NetcomError::InvalidValue(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn should_apply_enum_results() {
+        let mut controller = Controller::default();
+        let mut result = HashMap::new();
+        result.insert("mode".to_string(), Some(1.0));
+        result.insert("speed".to_string(), Some(80.0));
+        controller.try_apply_result(&result).unwrap();
+        assert_eq!(controller.mode, Mode::Auto);
+        assert_eq!(controller.speed, 80.0);
+
+        result.insert("mode".to_string(), Some(5.0));
+        result.insert("speed".to_string(), Some(90.0));
+        assert!(matches!(
+            controller.try_apply_result(&result),
+            Err(NetcomError::InvalidValue(_))
+        ));
+        // Nothing is applied when any value fails to convert.
+        assert_eq!(controller.mode, Mode::Auto);
+        assert_eq!(controller.speed, 80.0);
+    }
+
+    #[test]
+    fn should_read_and_write_bound_device() {
+        let server = boiler_server();
+        let mut client = NetcomClientSync::new("127.0.0.1", server.port());
+
+        let mut status = BoilerStatus::read(&mut client).unwrap();
+        assert_eq!(status.temp, 61.0);
+        assert_eq!(status.mode, Mode::Auto);
+
+        status.mode = Mode::Manual;
+        status.write(&mut client).unwrap();
+        assert_eq!(server.value("boiler-1", "mode"), Some(2.0));
+
+        let other = BoilerStatus::read_from(&mut client, "boiler-2").unwrap();
+        assert_eq!(other.temp, 55.0);
+        assert_eq!(other.mode, Mode::Off);
+    }
+
+    #[tokio::test]
+    async fn should_read_and_write_bound_device_async() {
+        let server = boiler_server();
+        let mut client = NetcomClientAsync::new("127.0.0.1", server.port());
+
+        let mut status = BoilerStatus::read_async(&mut client).await.unwrap();
+        assert_eq!(status.temp, 61.0);
+
+        status.temp = 65.0;
+        status
+            .write_to_async(&mut client, "boiler-2")
+            .await
+            .unwrap();
+        assert_eq!(server.value("boiler-2", "temp"), Some(65.0));
+        assert_eq!(server.value("boiler-1", "temp"), Some(61.0));
+    }
+
+    #[test]
+    fn should_only_write_patched_fields() {
+        assert!(UnitPatch::new().to_wrops().is_empty());
+
+        let patch = UnitPatch::new()
+            .with_mode(1.0)
+            .with_setpoints(1, 22.5)
+            .with_channels(0, ChannelPatch::new().with_temp(30.0))
+            .with_aux(ChannelPatch::new().with_temp(4.0));
+        assert_eq!(
+            write_values(patch.to_wrops()),
+            vec![
+                ("aux.temp".to_string(), 4.0),
+                ("ch1.temp".to_string(), 30.0),
+                ("mode".to_string(), 1.0),
+                ("setpoint2".to_string(), 22.5),
+            ]
+        );
+    }
+
+    #[derive(Default, NetcomMap)]
+    #[netcom(crate = "crate")]
+    struct Batch {
+        #[param(p = "new")]
+        new: f64,
+    }
+
+    #[test]
+    fn should_build_patches_for_any_field_name() {
+        let patch = BatchPatch::new().with_new(1.0);
+        assert_eq!(
+            write_values(patch.to_wrops()),
+            vec![("new".to_string(), 1.0)]
+        );
+        assert_eq!(Batch::default().new, 0.0);
+    }
+
+    #[test]
+    fn should_write_patch() {
+        let server = boiler_server();
+        let mut client = NetcomClientSync::new("127.0.0.1", server.port());
+
+        let patch = BoilerStatusPatch::new().with_mode(Mode::Manual);
+        let result = client.write_patch("boiler-1", &patch).unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(server.value("boiler-1", "mode"), Some(2.0));
+        assert_eq!(server.value("boiler-1", "temp"), Some(61.0));
+    }
 }
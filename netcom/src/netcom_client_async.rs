@@ -1,89 +1,202 @@
 use std::collections::HashMap;
+use std::collections::VecDeque;
+#[cfg(unix)]
+use std::path::Path;
 
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 use tokio::io::AsyncReadExt;
 use tokio::io::AsyncWriteExt;
-
+use tokio::io::DuplexStream;
+use tokio::sync::mpsc;
+
+use crate::audit::previous_rdops;
+use crate::audit::AuditLog;
+use crate::backup::backup_file;
+use crate::backup::backup_rdops;
+use crate::backup::restore_rdops;
+use crate::backup::restore_report;
+use crate::backup::restore_wrops;
+use crate::backup::ConfigFile;
+use crate::backup::RestoreOptions;
+use crate::backup::RestoreReport;
+use crate::cas::cas_rdops;
+use crate::cas::cas_wrops;
+use crate::cas::conflicts;
+use crate::cas::CasOp;
+use crate::cas::CasOptions;
+use crate::cas::CasOutcome;
 use crate::dto::ClientInfoRequestDto;
 use crate::dto::ClientInfoResponseDto;
 use crate::dto::DeviceDto;
 use crate::dto::DeviceListRequestDto;
 use crate::dto::DeviceListResponseDto;
 use crate::dto::ReadResponseDto;
+use crate::dto::SubscribeRequestDto;
+use crate::dto::SubscribeResponseDto;
 use crate::dto::UpgradeResponseDto;
-use crate::dto::WrValueDto;
 use crate::dto::WriteRequestDto;
 use crate::dto::WriteResponseDto;
+use crate::guard::guard_write;
+use crate::guard::WriteGuard;
 use crate::netcom::build_read_request;
+use crate::netcom::build_write_request;
 use crate::netcom::parse_json;
+use crate::netcom::parse_push_event;
 use crate::netcom::NetcomError;
+use crate::netcom::NetcomPatch;
 use crate::netcom::NetcomSync;
+use crate::netcom::PushEvent;
 use crate::netcom::RdOp;
 use crate::netcom::WrOp;
-use crate::netstring::parse_netstring;
+use crate::netstring::split_netstring;
 use crate::netstring::NetstringError;
 use crate::netstring::ToNetstring;
+#[cfg(feature = "tokio-tls")]
+use crate::tls::TlsOptions;
+use crate::transport::AsyncTransport;
+use crate::transport::Endpoint;
+use crate::verify::Verification;
+use crate::verify::VerifyOptions;
+use crate::verify::WriteReport;
 
 pub struct NetcomClientAsync {
-    hostname: String,
-    port: u16,
-    stream: Option<tokio::net::TcpStream>,
+    endpoint: Endpoint<dyn AsyncTransport>,
+    stream: Option<Box<dyn AsyncTransport>>,
     auto_connect: bool,
     version: Option<String>,
+    connects: u64,
+    buffer: Vec<u8>,
+    events: VecDeque<PushEvent>,
+    event_sender: Option<mpsc::UnboundedSender<PushEvent>>,
+    write_guard: Option<WriteGuard>,
+    audit_log: Option<AuditLog>,
+    client_name: Option<String>,
 }
 
 impl NetcomClientAsync {
     pub fn new(hostname: &str, port: u16) -> Self {
+        NetcomClientAsync::with_endpoint(Endpoint::tcp(hostname, port))
+    }
+
+    /// A client connecting to the Unix domain socket at `path`.
+    #[cfg(unix)]
+    pub fn new_unix(path: impl AsRef<Path>) -> Self {
+        NetcomClientAsync::with_endpoint(Endpoint::Unix(path.as_ref().to_path_buf()))
+    }
+
+    /// A client connecting to `hostname:port` over TLS.
+    #[cfg(feature = "tokio-tls")]
+    pub fn new_tls(hostname: &str, port: u16, options: &TlsOptions) -> Result<Self, NetcomError> {
+        Ok(NetcomClientAsync::with_endpoint(Endpoint::tls(
+            hostname, port, options,
+        )?))
+    }
+
+    /// A client running over `transport`, which must not have been
+    /// upgraded yet. It cannot reconnect once disconnected.
+    pub fn from_transport(transport: impl AsyncTransport + 'static) -> Self {
+        NetcomClientAsync::with_endpoint(Endpoint::Supplied(Some(Box::new(transport))))
+    }
+
+    /// A client over an in-memory pipe, returned with the server's end of
+    /// it. `max_buf_size` bounds the bytes buffered in each direction.
+    pub fn duplex(max_buf_size: usize) -> (Self, DuplexStream) {
+        let (client, server) = tokio::io::duplex(max_buf_size);
+        (NetcomClientAsync::from_transport(client), server)
+    }
+
+    fn with_endpoint(endpoint: Endpoint<dyn AsyncTransport>) -> Self {
         NetcomClientAsync {
-            hostname: hostname.to_string(),
-            port,
+            endpoint,
             stream: None,
             auto_connect: true,
             version: None,
+            connects: 0,
+            buffer: Vec::new(),
+            events: VecDeque::new(),
+            event_sender: None,
+            write_guard: None,
+            audit_log: None,
+            client_name: None,
         }
     }
 
+    /// Checks every later write against `guard`, or stops checking if `None`.
+    pub fn set_write_guard(&mut self, guard: Option<WriteGuard>) {
+        self.write_guard = guard;
+    }
+
+    pub fn write_guard(&self) -> Option<&WriteGuard> {
+        self.write_guard.as_ref()
+    }
+
+    /// Records every later write in `log`, or stops recording if `None`.
+    pub fn set_audit_log(&mut self, log: Option<AuditLog>) {
+        self.audit_log = log;
+    }
+
     pub async fn connect(&mut self) -> Result<(), NetcomError> {
-        let host = format!("{}:{}", self.hostname, self.port);
-        let mut stream = tokio::net::TcpStream::connect(host)
-            .await
-            .map_err(|e| NetcomError::StreamError(e))?;
+        let mut stream = self.endpoint.open().await?;
 
         stream
             .write_all(b"PROTO30\n")
             .await
-            .map_err(|e| NetcomError::StreamError(e))?;
+            .map_err(NetcomError::StreamError)?;
 
         self.stream = Some(stream);
+        self.buffer.clear();
 
-        let json = match self.read_netstring().await {
-            Ok(s) => s,
-            Err(e) => return Err(e),
-        };
+        let json = self.read_netstring().await?;
 
-        let response: UpgradeResponseDto = parse_json(&json)?;
+        let response = parse_json::<UpgradeResponseDto>(&json);
+        let response = self.disconnect_on_desync(response)?;
         self.version = Some(response.version);
+        self.connects += 1;
         Ok(())
     }
 
+    /// Reads the next response frame. Push events read on the way are
+    /// handed to `dispatch_event`.
     async fn read_netstring(&mut self) -> Result<Vec<u8>, NetcomError> {
+        loop {
+            let frame = self.read_frame().await?;
+            match parse_push_event(&frame) {
+                Some(event) => self.dispatch_event(event),
+                None => return Ok(frame),
+            }
+        }
+    }
+
+    async fn read_frame(&mut self) -> Result<Vec<u8>, NetcomError> {
+        let result = self.read_buffered_frame().await;
+        self.disconnect_on_desync(result)
+    }
+
+    async fn read_buffered_frame(&mut self) -> Result<Vec<u8>, NetcomError> {
         // TODO: Handle timeout
         match &mut self.stream {
             Some(stream) => {
-                let mut msg: Vec<u8> = Vec::new();
                 let mut buf = [0; 128];
 
                 loop {
+                    match split_netstring(&self.buffer) {
+                        Ok((s, n)) => {
+                            let frame = s.to_vec();
+                            self.buffer.drain(..n);
+                            return Ok(frame);
+                        }
+                        Err(NetstringError::Incomplete) => {}
+                        Err(e) => return Err(NetcomError::NetstringError(e)),
+                    };
+
                     match stream.read(&mut buf).await {
-                        Ok(n) => {
-                            msg.extend_from_slice(&buf[..n]);
-                            match parse_netstring(&msg) {
-                                Ok(s) => return Ok(s.to_vec()),
-                                Err(NetstringError::Incomplete) => {}
-                                Err(e) => return Err(NetcomError::NetstringError(e)),
-                            };
+                        Ok(0) => {
+                            return Err(NetcomError::StreamError(
+                                std::io::ErrorKind::UnexpectedEof.into(),
+                            ))
                         }
+                        Ok(n) => self.buffer.extend_from_slice(&buf[..n]),
                         Err(e) => return Err(NetcomError::StreamError(e)),
                     }
                 }
@@ -92,6 +205,15 @@ impl NetcomClientAsync {
         }
     }
 
+    fn dispatch_event(&mut self, event: PushEvent) {
+        match &self.event_sender {
+            Some(sender) => {
+                let _ = sender.send(event);
+            }
+            None => self.events.push_back(event),
+        }
+    }
+
     async fn prepare(&mut self) -> Result<(), NetcomError> {
         if self.auto_connect && self.stream.is_none() {
             self.connect().await?;
@@ -103,21 +225,44 @@ impl NetcomClientAsync {
         self.stream.is_some()
     }
 
+    /// Number of successful connects, including those made implicitly by
+    /// requests after a disconnect.
+    pub fn connect_count(&self) -> u64 {
+        self.connects
+    }
+
+    /// Server version reported in the reply to the protocol upgrade.
+    pub fn version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+
     pub fn disconnect(&mut self) {
         self.stream = None;
+        self.buffer.clear();
+    }
+
+    /// Drops the connection after an error that may have left it out of
+    /// step with the server, so that the next request starts over.
+    fn disconnect_on_desync<T>(
+        &mut self,
+        result: Result<T, NetcomError>,
+    ) -> Result<T, NetcomError> {
+        if let Err(e) = &result {
+            if e.needs_reconnect() {
+                self.disconnect();
+            }
+        }
+        result
     }
 
     async fn send_buf(&mut self, buf: &[u8]) -> Result<(), NetcomError> {
         self.prepare().await?;
 
-        if let Some(s) = &mut self.stream {
-            s.write(buf)
-                .await
-                .map_err(|e| NetcomError::StreamError(e))?;
-            return Ok(());
-        }
-
-        Err(NetcomError::NotConnected)
+        let result = match &mut self.stream {
+            Some(s) => s.write_all(buf).await.map_err(NetcomError::StreamError),
+            None => Err(NetcomError::NotConnected),
+        };
+        self.disconnect_on_desync(result)
     }
 
     async fn send_request<T>(&mut self, req: &T) -> Result<(), NetcomError>
@@ -134,10 +279,11 @@ impl NetcomClientAsync {
     where
         T: DeserializeOwned,
     {
-        match self.read_netstring().await {
+        let result = match self.read_netstring().await {
             Ok(s) => parse_json(&s),
             Err(e) => Err(e),
-        }
+        };
+        self.disconnect_on_desync(result)
     }
 
     pub async fn get_device_list(&mut self) -> Result<Vec<DeviceDto>, NetcomError> {
@@ -170,7 +316,10 @@ impl NetcomClientAsync {
 
         match self.wait_for_response::<ClientInfoResponseDto>().await {
             Ok(r) => match r.r.as_str() {
-                "client-info" => Ok(()),
+                "client-info" => {
+                    self.client_name = Some(name.to_string());
+                    Ok(())
+                }
                 _ => Err(NetcomError::ResponseError(format!(
                     "Expected response type device-list, got {:?}",
                     r.r
@@ -205,22 +354,33 @@ impl NetcomClientAsync {
         device: &str,
         parameters: Vec<WrOp>,
     ) -> Result<HashMap<String, Option<f64>>, NetcomError> {
-        let mut p = HashMap::<String, WrValueDto>::new();
-
-        for op in parameters {
-            match op {
-                WrOp::Default { p: pp, v } => p.insert(pp, WrValueDto::Simple(v)),
-                WrOp::WithType { p: pp, t, v } => p.insert(pp, WrValueDto::Detailed { v, t }),
-            };
-        }
-
-        let req = WriteRequestDto {
-            r: "write".to_string(),
-            device: device.to_string(),
-            p,
+        let req = build_write_request(device, parameters);
+        guard_write(self.write_guard.as_ref(), &req)?;
+
+        // The log is not `Sync`, so it must not be borrowed across the read.
+        let read_previous = self
+            .audit_log
+            .as_ref()
+            .is_some_and(|log| log.reads_previous());
+        let previous = if read_previous {
+            self.read_parameters(device, previous_rdops(&req))
+                .await
+                .ok()
+        } else {
+            None
         };
+        let result = self.send_write(&req).await;
+        match &mut self.audit_log {
+            Some(log) => log.record(self.client_name.as_deref(), &req, previous.as_ref(), result),
+            None => result,
+        }
+    }
 
-        self.send_request(&req).await?;
+    async fn send_write(
+        &mut self,
+        req: &WriteRequestDto,
+    ) -> Result<HashMap<String, Option<f64>>, NetcomError> {
+        self.send_request(req).await?;
 
         match self.wait_for_response::<WriteResponseDto>().await {
             Ok(res) => match res.r.as_str() {
@@ -235,6 +395,78 @@ impl NetcomClientAsync {
         }
     }
 
+    pub async fn subscribe(
+        &mut self,
+        device: &str,
+        parameters: &[&str],
+    ) -> Result<(), NetcomError> {
+        self.send_subscription("subscribe", device, parameters)
+            .await
+    }
+
+    pub async fn unsubscribe(
+        &mut self,
+        device: &str,
+        parameters: &[&str],
+    ) -> Result<(), NetcomError> {
+        self.send_subscription("unsubscribe", device, parameters)
+            .await
+    }
+
+    async fn send_subscription(
+        &mut self,
+        r: &str,
+        device: &str,
+        parameters: &[&str],
+    ) -> Result<(), NetcomError> {
+        let req = SubscribeRequestDto {
+            r: r.to_string(),
+            device: device.to_string(),
+            p: parameters.iter().map(|p| p.to_string()).collect(),
+        };
+
+        self.send_request(&req).await?;
+
+        match self.wait_for_response::<SubscribeResponseDto>().await {
+            Ok(res) if res.r == r => Ok(()),
+            Ok(res) => Err(NetcomError::ResponseError(format!(
+                "Expected response type {:?}, got {:?}",
+                r, res.r
+            ))),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Delivers push events on a channel from now on, including any that
+    /// were queued before. Events only arrive while the client is reading
+    /// from the server, i.e. during requests or `next_event`.
+    pub fn events(&mut self) -> mpsc::UnboundedReceiver<PushEvent> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        for event in self.events.drain(..) {
+            let _ = sender.send(event);
+        }
+        self.event_sender = Some(sender);
+        receiver
+    }
+
+    /// Waits for the next push event. Events already queued, or received on
+    /// the channel from `events`, are not returned again.
+    pub async fn next_event(&mut self) -> Result<PushEvent, NetcomError> {
+        if let Some(event) = self.events.pop_front() {
+            return Ok(event);
+        }
+
+        self.prepare().await?;
+        let frame = self.read_frame().await?;
+        match parse_push_event(&frame) {
+            Some(event) => Ok(event),
+            None => Err(NetcomError::ResponseError(format!(
+                "Expected push event, got {:?}",
+                String::from_utf8_lossy(&frame)
+            ))),
+        }
+    }
+
     pub async fn read_struct<T: NetcomSync>(
         &mut self,
         device: &str,
@@ -243,7 +475,7 @@ impl NetcomClientAsync {
         let rdops = params.to_rdops();
         match self.read_parameters(device, rdops).await {
             Ok(res) => {
-                params.apply_result(&res);
+                params.try_apply_result(&res)?;
                 Ok(res)
             }
             Err(err) => Err(err),
@@ -261,6 +493,113 @@ impl NetcomClientAsync {
             Err(e) => Err(e),
         }
     }
+
+    pub async fn write_patch<T: NetcomPatch>(
+        &mut self,
+        device: &str,
+        patch: &T,
+    ) -> Result<HashMap<String, Option<f64>>, NetcomError> {
+        let wrops = patch.to_wrops();
+        if wrops.is_empty() {
+            return Ok(HashMap::new());
+        }
+        self.write_parameters(device, wrops).await
+    }
+
+    /// Makes the write `op` only if the current value of its parameter is
+    /// `expected`, within the tolerance of `options`. See the `cas` module
+    /// for the guarantees this gives.
+    pub async fn compare_and_write(
+        &mut self,
+        device: &str,
+        op: WrOp,
+        expected: f64,
+        options: &CasOptions,
+    ) -> Result<CasOutcome, NetcomError> {
+        self.compare_and_write_all(device, &[CasOp::new(op, expected)], options)
+            .await
+    }
+
+    /// Writes every parameter in `ops` if all of them hold their expected
+    /// values, and none of them otherwise.
+    pub async fn compare_and_write_all(
+        &mut self,
+        device: &str,
+        ops: &[CasOp],
+        options: &CasOptions,
+    ) -> Result<CasOutcome, NetcomError> {
+        let current = self.read_parameters(device, cas_rdops(ops)).await?;
+        let conflicts = conflicts(ops, &current, options);
+        if !conflicts.is_empty() {
+            return Ok(CasOutcome::Conflict(conflicts));
+        }
+        let echo = self.write_parameters(device, cas_wrops(ops)).await?;
+        Ok(CasOutcome::Written(echo))
+    }
+
+    /// Writes `parameters` and checks the values the device reports against
+    /// those requested, retrying rejected writes if `options` allows.
+    pub async fn write_verified(
+        &mut self,
+        device: &str,
+        parameters: Vec<WrOp>,
+        options: &VerifyOptions,
+    ) -> Result<WriteReport, NetcomError> {
+        let mut verification = Verification::new(parameters);
+        loop {
+            let pending = verification.pending();
+            let echo = self.write_parameters(device, pending.clone()).await?;
+            let read = if options.read_back {
+                Some(
+                    self.read_parameters(device, Verification::read_back(&pending))
+                        .await?,
+                )
+            } else {
+                None
+            };
+
+            if !verification.record(&pending, &echo, read.as_ref(), options) {
+                return Ok(verification.report(device));
+            }
+            tokio::time::sleep(options.retry_delay).await;
+        }
+    }
+
+    /// Reads `params` from `device` into a config file.
+    pub async fn backup(
+        &mut self,
+        device: &str,
+        params: &[&str],
+    ) -> Result<ConfigFile, NetcomError> {
+        let values = self.read_parameters(device, backup_rdops(params)).await?;
+        Ok(backup_file(device, params, &values))
+    }
+
+    /// Writes the values saved in `file` to `device`, which need not be the
+    /// device the file was taken from, and reports what the device accepted.
+    pub async fn restore(
+        &mut self,
+        device: &str,
+        file: &ConfigFile,
+        options: &RestoreOptions,
+    ) -> Result<RestoreReport, NetcomError> {
+        let actual = if options.dry_run {
+            self.read_parameters(device, restore_rdops(file)).await?
+        } else {
+            let wrops = restore_wrops(file);
+            let echo = if wrops.is_empty() {
+                HashMap::new()
+            } else {
+                self.write_parameters(device, wrops).await?
+            };
+            if options.verify {
+                self.read_parameters(device, restore_rdops(file)).await?
+            } else {
+                echo
+            }
+        };
+        Ok(restore_report(device, file, options, &actual))
+    }
 }
 impl Drop for NetcomClientAsync {
     fn drop(&mut self) {
@@ -271,6 +610,7 @@ impl Drop for NetcomClientAsync {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::mock_server::MockServer;
     use crate::netcom::DEFAULT_PORT;
 
     #[tokio::test]
@@ -281,4 +621,35 @@ mod tests {
             Err(e) => panic!("Failed with error: {:?}", e),
         }
     }
+
+    #[tokio::test]
+    async fn should_deliver_push_events_on_channel() {
+        let server = MockServer::start().with_device("pump-3", &[("speed", 1200.0)]);
+        let mut c = NetcomClientAsync::new("127.0.0.1", server.port());
+        let mut events = c.events();
+        c.subscribe("pump-3", &["speed"]).await.unwrap();
+
+        let wrops = vec![WrOp::Default {
+            p: "speed".to_string(),
+            v: 900.0,
+        }];
+        c.write_parameters("pump-3", wrops).await.unwrap();
+        let event = events.try_recv().unwrap();
+        assert_eq!(event.values.get("speed"), Some(&Some(900.0)));
+
+        server.set_value("pump-3", "speed", 1000.0);
+        let event = c.next_event().await.unwrap();
+        assert_eq!(event.values.get("speed"), Some(&Some(1000.0)));
+        assert!(events.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn should_fail_to_subscribe_to_unknown_device() {
+        let server = MockServer::start();
+        let mut c = NetcomClientAsync::new("127.0.0.1", server.port());
+        assert!(matches!(
+            c.subscribe("missing", &["speed"]).await,
+            Err(NetcomError::DeviceNotFound)
+        ));
+    }
 }
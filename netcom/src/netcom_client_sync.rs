@@ -1,77 +1,168 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
     io::{Read, Write},
+    sync::mpsc,
 };
 
+#[cfg(unix)]
+use std::path::Path;
+
+#[cfg(feature = "tls")]
+use crate::tls::TlsOptions;
+
 use serde::{de::DeserializeOwned, Serialize};
 
 use crate::{
+    audit::{previous_rdops, AuditLog},
+    backup::{
+        backup_file, backup_rdops, restore_rdops, restore_report, restore_wrops, ConfigFile,
+        RestoreOptions, RestoreReport,
+    },
+    cas::{cas_rdops, cas_wrops, conflicts, CasOp, CasOptions, CasOutcome},
     dto::{
         ClientInfoRequestDto, ClientInfoResponseDto, DeviceDto, DeviceListRequestDto,
-        DeviceListResponseDto, ReadResponseDto, UpgradeResponseDto, WrValueDto, WriteRequestDto,
-        WriteResponseDto,
+        DeviceListResponseDto, ReadResponseDto, SubscribeRequestDto, SubscribeResponseDto,
+        UpgradeResponseDto, WriteRequestDto, WriteResponseDto,
     },
-    netcom::{build_read_request, parse_json, NetcomError, NetcomSync, RdOp, WrOp},
-    netstring::{parse_netstring, NetstringError, ToNetstring},
+    guard::{guard_write, WriteGuard},
+    netcom::{
+        build_read_request, build_write_request, parse_json, parse_push_event, NetcomError,
+        NetcomPatch, NetcomSync, PushEvent, RdOp, WrOp,
+    },
+    netstring::{split_netstring, NetstringError, ToNetstring},
+    transport::{Endpoint, Transport},
+    verify::{Verification, VerifyOptions, WriteReport},
 };
 
 pub struct NetcomClientSync {
-    hostname: String,
-    port: u16,
-    stream: Option<std::net::TcpStream>,
+    endpoint: Endpoint<dyn Transport>,
+    stream: Option<Box<dyn Transport>>,
     auto_connect: bool,
     version: Option<String>,
+    connects: u64,
+    buffer: Vec<u8>,
+    events: VecDeque<PushEvent>,
+    event_sender: Option<mpsc::Sender<PushEvent>>,
+    write_guard: Option<WriteGuard>,
+    audit_log: Option<AuditLog>,
+    client_name: Option<String>,
 }
 
 impl NetcomClientSync {
     pub fn new(hostname: &str, port: u16) -> Self {
+        NetcomClientSync::with_endpoint(Endpoint::tcp(hostname, port))
+    }
+
+    /// A client connecting to the Unix domain socket at `path`.
+    #[cfg(unix)]
+    pub fn new_unix(path: impl AsRef<Path>) -> Self {
+        NetcomClientSync::with_endpoint(Endpoint::Unix(path.as_ref().to_path_buf()))
+    }
+
+    /// A client connecting to `hostname:port` over TLS.
+    #[cfg(feature = "tls")]
+    pub fn new_tls(hostname: &str, port: u16, options: &TlsOptions) -> Result<Self, NetcomError> {
+        Ok(NetcomClientSync::with_endpoint(Endpoint::tls(
+            hostname, port, options,
+        )?))
+    }
+
+    /// A client running over `transport`, which must not have been
+    /// upgraded yet. It cannot reconnect once disconnected.
+    pub fn from_transport(transport: impl Transport + 'static) -> Self {
+        NetcomClientSync::with_endpoint(Endpoint::Supplied(Some(Box::new(transport))))
+    }
+
+    fn with_endpoint(endpoint: Endpoint<dyn Transport>) -> Self {
         NetcomClientSync {
-            hostname: hostname.to_string(),
-            port,
+            endpoint,
             stream: None,
             auto_connect: true,
             version: None,
+            connects: 0,
+            buffer: Vec::new(),
+            events: VecDeque::new(),
+            event_sender: None,
+            write_guard: None,
+            audit_log: None,
+            client_name: None,
         }
     }
 
+    /// Checks every later write against `guard`, or stops checking if `None`.
+    pub fn set_write_guard(&mut self, guard: Option<WriteGuard>) {
+        self.write_guard = guard;
+    }
+
+    pub fn write_guard(&self) -> Option<&WriteGuard> {
+        self.write_guard.as_ref()
+    }
+
+    /// Records every later write in `log`, or stops recording if `None`.
+    pub fn set_audit_log(&mut self, log: Option<AuditLog>) {
+        self.audit_log = log;
+    }
+
     pub fn connect(&mut self) -> Result<(), NetcomError> {
-        let host = format!("{}:{}", self.hostname, self.port);
-        let mut stream =
-            std::net::TcpStream::connect(host).map_err(|e| NetcomError::StreamError(e))?;
+        let mut stream = self.endpoint.open()?;
 
         stream
             .write_all(b"PROTO30\n")
-            .map_err(|e| NetcomError::StreamError(e))?;
+            .map_err(NetcomError::StreamError)?;
 
         self.stream = Some(stream);
+        self.buffer.clear();
 
-        let json = match self.read_netstring() {
-            Ok(s) => s,
-            Err(e) => return Err(e),
-        };
+        let json = self.read_netstring()?;
 
-        let response: UpgradeResponseDto = parse_json(&json)?;
+        let response = parse_json::<UpgradeResponseDto>(&json);
+        let response = self.disconnect_on_desync(response)?;
         self.version = Some(response.version);
+        self.connects += 1;
         Ok(())
     }
 
+    /// Reads the next response frame. Push events read on the way are
+    /// handed to `dispatch_event`.
     fn read_netstring(&mut self) -> Result<Vec<u8>, NetcomError> {
+        loop {
+            let frame = self.read_frame()?;
+            match parse_push_event(&frame) {
+                Some(event) => self.dispatch_event(event),
+                None => return Ok(frame),
+            }
+        }
+    }
+
+    fn read_frame(&mut self) -> Result<Vec<u8>, NetcomError> {
+        let result = self.read_buffered_frame();
+        self.disconnect_on_desync(result)
+    }
+
+    fn read_buffered_frame(&mut self) -> Result<Vec<u8>, NetcomError> {
         // TODO: Handle timeout
         match &mut self.stream {
             Some(stream) => {
-                let mut msg: Vec<u8> = Vec::new();
                 let mut buf = [0; 128];
 
                 loop {
+                    match split_netstring(&self.buffer) {
+                        Ok((s, n)) => {
+                            let frame = s.to_vec();
+                            self.buffer.drain(..n);
+                            return Ok(frame);
+                        }
+                        Err(NetstringError::Incomplete) => {}
+                        Err(e) => return Err(NetcomError::NetstringError(e)),
+                    };
+
                     match stream.read(&mut buf) {
-                        Ok(n) => {
-                            msg.extend_from_slice(&buf[..n]);
-                            match parse_netstring(&msg) {
-                                Ok(s) => return Ok(s.to_vec()),
-                                Err(NetstringError::Incomplete) => {}
-                                Err(e) => return Err(NetcomError::NetstringError(e)),
-                            };
+                        Ok(0) => {
+                            return Err(NetcomError::StreamError(
+                                std::io::ErrorKind::UnexpectedEof.into(),
+                            ))
                         }
+                        Ok(n) => self.buffer.extend_from_slice(&buf[..n]),
                         Err(e) => return Err(NetcomError::StreamError(e)),
                     }
                 }
@@ -80,6 +171,15 @@ impl NetcomClientSync {
         }
     }
 
+    fn dispatch_event(&mut self, event: PushEvent) {
+        match &self.event_sender {
+            Some(sender) => {
+                let _ = sender.send(event);
+            }
+            None => self.events.push_back(event),
+        }
+    }
+
     pub fn prepare(&mut self) -> Result<(), NetcomError> {
         if self.auto_connect && self.stream.is_none() {
             self.connect()?;
@@ -91,19 +191,44 @@ impl NetcomClientSync {
         self.stream.is_some()
     }
 
+    /// Number of successful connects, including those made implicitly by
+    /// requests after a disconnect.
+    pub fn connect_count(&self) -> u64 {
+        self.connects
+    }
+
+    /// Server version reported in the reply to the protocol upgrade.
+    pub fn version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+
     pub fn disconnect(&mut self) {
         self.stream = None;
+        self.buffer.clear();
+    }
+
+    /// Drops the connection after an error that may have left it out of
+    /// step with the server, so that the next request starts over.
+    fn disconnect_on_desync<T>(
+        &mut self,
+        result: Result<T, NetcomError>,
+    ) -> Result<T, NetcomError> {
+        if let Err(e) = &result {
+            if e.needs_reconnect() {
+                self.disconnect();
+            }
+        }
+        result
     }
 
     fn send_buf(&mut self, buf: &[u8]) -> Result<(), NetcomError> {
         self.prepare()?;
 
-        if let Some(s) = &mut self.stream {
-            s.write(buf).map_err(|e| NetcomError::StreamError(e))?;
-            return Ok(());
-        }
-
-        Err(NetcomError::NotConnected)
+        let result = match &mut self.stream {
+            Some(s) => s.write_all(buf).map_err(NetcomError::StreamError),
+            None => Err(NetcomError::NotConnected),
+        };
+        self.disconnect_on_desync(result)
     }
 
     fn send_request<T>(&mut self, req: &T) -> Result<(), NetcomError>
@@ -120,10 +245,11 @@ impl NetcomClientSync {
     where
         T: DeserializeOwned,
     {
-        match self.read_netstring() {
+        let result = match self.read_netstring() {
             Ok(s) => parse_json(&s),
             Err(e) => Err(e),
-        }
+        };
+        self.disconnect_on_desync(result)
     }
 
     pub fn get_device_list(&mut self) -> Result<Vec<DeviceDto>, NetcomError> {
@@ -156,7 +282,10 @@ impl NetcomClientSync {
 
         match self.wait_for_response::<ClientInfoResponseDto>() {
             Ok(r) => match r.r.as_str() {
-                "client-info" => Ok(()),
+                "client-info" => {
+                    self.client_name = Some(name.to_string());
+                    Ok(())
+                }
                 _ => Err(NetcomError::ResponseError(format!(
                     "Expected response type device-list, got {:?}",
                     r.r
@@ -191,22 +320,27 @@ impl NetcomClientSync {
         device: &str,
         parameters: Vec<WrOp>,
     ) -> Result<HashMap<String, Option<f64>>, NetcomError> {
-        let mut p = HashMap::<String, WrValueDto>::new();
+        let req = build_write_request(device, parameters);
+        guard_write(self.write_guard.as_ref(), &req)?;
 
-        for op in parameters {
-            match op {
-                WrOp::Default { p: pp, v } => p.insert(pp, WrValueDto::Simple(v)),
-                WrOp::WithType { p: pp, t, v } => p.insert(pp, WrValueDto::Detailed { v, t }),
-            };
-        }
-
-        let req = WriteRequestDto {
-            r: "write".to_string(),
-            device: device.to_string(),
-            p,
+        let previous = match &self.audit_log {
+            Some(log) if log.reads_previous() => {
+                self.read_parameters(device, previous_rdops(&req)).ok()
+            }
+            _ => None,
         };
+        let result = self.send_write(&req);
+        match &mut self.audit_log {
+            Some(log) => log.record(self.client_name.as_deref(), &req, previous.as_ref(), result),
+            None => result,
+        }
+    }
 
-        self.send_request(&req)?;
+    fn send_write(
+        &mut self,
+        req: &WriteRequestDto,
+    ) -> Result<HashMap<String, Option<f64>>, NetcomError> {
+        self.send_request(req)?;
 
         match self.wait_for_response::<WriteResponseDto>() {
             Ok(res) => match res.r.as_str() {
@@ -221,6 +355,68 @@ impl NetcomClientSync {
         }
     }
 
+    pub fn subscribe(&mut self, device: &str, parameters: &[&str]) -> Result<(), NetcomError> {
+        self.send_subscription("subscribe", device, parameters)
+    }
+
+    pub fn unsubscribe(&mut self, device: &str, parameters: &[&str]) -> Result<(), NetcomError> {
+        self.send_subscription("unsubscribe", device, parameters)
+    }
+
+    fn send_subscription(
+        &mut self,
+        r: &str,
+        device: &str,
+        parameters: &[&str],
+    ) -> Result<(), NetcomError> {
+        let req = SubscribeRequestDto {
+            r: r.to_string(),
+            device: device.to_string(),
+            p: parameters.iter().map(|p| p.to_string()).collect(),
+        };
+
+        self.send_request(&req)?;
+
+        match self.wait_for_response::<SubscribeResponseDto>() {
+            Ok(res) if res.r == r => Ok(()),
+            Ok(res) => Err(NetcomError::ResponseError(format!(
+                "Expected response type {:?}, got {:?}",
+                r, res.r
+            ))),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Delivers push events on a channel from now on, including any that
+    /// were queued before. Events only arrive while the client is reading
+    /// from the server, i.e. during requests or `next_event`.
+    pub fn events(&mut self) -> mpsc::Receiver<PushEvent> {
+        let (sender, receiver) = mpsc::channel();
+        for event in self.events.drain(..) {
+            let _ = sender.send(event);
+        }
+        self.event_sender = Some(sender);
+        receiver
+    }
+
+    /// Waits for the next push event. Events already queued, or received on
+    /// the channel from `events`, are not returned again.
+    pub fn next_event(&mut self) -> Result<PushEvent, NetcomError> {
+        if let Some(event) = self.events.pop_front() {
+            return Ok(event);
+        }
+
+        self.prepare()?;
+        let frame = self.read_frame()?;
+        match parse_push_event(&frame) {
+            Some(event) => Ok(event),
+            None => Err(NetcomError::ResponseError(format!(
+                "Expected push event, got {:?}",
+                String::from_utf8_lossy(&frame)
+            ))),
+        }
+    }
+
     pub fn read_struct<T: NetcomSync>(
         &mut self,
         device: &str,
@@ -229,7 +425,7 @@ impl NetcomClientSync {
         let rdops = params.to_rdops();
         match self.read_parameters(device, rdops) {
             Ok(res) => {
-                params.apply_result(&res);
+                params.try_apply_result(&res)?;
                 Ok(res)
             }
             Err(err) => Err(err),
@@ -247,6 +443,105 @@ impl NetcomClientSync {
             Err(e) => Err(e),
         }
     }
+
+    pub fn write_patch<T: NetcomPatch>(
+        &mut self,
+        device: &str,
+        patch: &T,
+    ) -> Result<HashMap<String, Option<f64>>, NetcomError> {
+        let wrops = patch.to_wrops();
+        if wrops.is_empty() {
+            return Ok(HashMap::new());
+        }
+        self.write_parameters(device, wrops)
+    }
+
+    /// Makes the write `op` only if the current value of its parameter is
+    /// `expected`, within the tolerance of `options`. See the `cas` module
+    /// for the guarantees this gives.
+    pub fn compare_and_write(
+        &mut self,
+        device: &str,
+        op: WrOp,
+        expected: f64,
+        options: &CasOptions,
+    ) -> Result<CasOutcome, NetcomError> {
+        self.compare_and_write_all(device, &[CasOp::new(op, expected)], options)
+    }
+
+    /// Writes every parameter in `ops` if all of them hold their expected
+    /// values, and none of them otherwise.
+    pub fn compare_and_write_all(
+        &mut self,
+        device: &str,
+        ops: &[CasOp],
+        options: &CasOptions,
+    ) -> Result<CasOutcome, NetcomError> {
+        let current = self.read_parameters(device, cas_rdops(ops))?;
+        let conflicts = conflicts(ops, &current, options);
+        if !conflicts.is_empty() {
+            return Ok(CasOutcome::Conflict(conflicts));
+        }
+        let echo = self.write_parameters(device, cas_wrops(ops))?;
+        Ok(CasOutcome::Written(echo))
+    }
+
+    /// Writes `parameters` and checks the values the device reports against
+    /// those requested, retrying rejected writes if `options` allows.
+    pub fn write_verified(
+        &mut self,
+        device: &str,
+        parameters: Vec<WrOp>,
+        options: &VerifyOptions,
+    ) -> Result<WriteReport, NetcomError> {
+        let mut verification = Verification::new(parameters);
+        loop {
+            let pending = verification.pending();
+            let echo = self.write_parameters(device, pending.clone())?;
+            let read = if options.read_back {
+                Some(self.read_parameters(device, Verification::read_back(&pending))?)
+            } else {
+                None
+            };
+
+            if !verification.record(&pending, &echo, read.as_ref(), options) {
+                return Ok(verification.report(device));
+            }
+            std::thread::sleep(options.retry_delay);
+        }
+    }
+
+    /// Reads `params` from `device` into a config file.
+    pub fn backup(&mut self, device: &str, params: &[&str]) -> Result<ConfigFile, NetcomError> {
+        let values = self.read_parameters(device, backup_rdops(params))?;
+        Ok(backup_file(device, params, &values))
+    }
+
+    /// Writes the values saved in `file` to `device`, which need not be the
+    /// device the file was taken from, and reports what the device accepted.
+    pub fn restore(
+        &mut self,
+        device: &str,
+        file: &ConfigFile,
+        options: &RestoreOptions,
+    ) -> Result<RestoreReport, NetcomError> {
+        let actual = if options.dry_run {
+            self.read_parameters(device, restore_rdops(file))?
+        } else {
+            let wrops = restore_wrops(file);
+            let echo = if wrops.is_empty() {
+                HashMap::new()
+            } else {
+                self.write_parameters(device, wrops)?
+            };
+            if options.verify {
+                self.read_parameters(device, restore_rdops(file))?
+            } else {
+                echo
+            }
+        };
+        Ok(restore_report(device, file, options, &actual))
+    }
 }
 impl Drop for NetcomClientSync {
     fn drop(&mut self) {
@@ -257,6 +552,7 @@ impl Drop for NetcomClientSync {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::mock_server::MockServer;
     use crate::netcom::DEFAULT_PORT;
 
     #[test]
@@ -267,4 +563,108 @@ mod tests {
             Err(e) => panic!("Failed with error: {:?}", e),
         }
     }
+
+    #[test]
+    fn should_demultiplex_push_events() {
+        let server = MockServer::start().with_device("pump-3", &[("speed", 1200.0)]);
+        let mut c = NetcomClientSync::new("127.0.0.1", server.port());
+        c.subscribe("pump-3", &["speed"]).unwrap();
+
+        // The event for our own write arrives ahead of the write response.
+        let wrops = vec![WrOp::Default {
+            p: "speed".to_string(),
+            v: 900.0,
+        }];
+        let result = c.write_parameters("pump-3", wrops).unwrap();
+        assert_eq!(result.get("speed"), Some(&Some(900.0)));
+
+        let event = c.next_event().unwrap();
+        assert_eq!(event.device, "pump-3");
+        assert_eq!(event.values.get("speed"), Some(&Some(900.0)));
+
+        server.set_value("pump-3", "speed", 1000.0);
+        let event = c.next_event().unwrap();
+        assert_eq!(event.values.get("speed"), Some(&Some(1000.0)));
+
+        let events = c.events();
+        c.unsubscribe("pump-3", &["speed"]).unwrap();
+        server.set_value("pump-3", "speed", 1.0);
+        let rdops = vec![RdOp::Default {
+            p: "speed".to_string(),
+        }];
+        c.read_parameters("pump-3", rdops).unwrap();
+        assert!(events.try_recv().is_err());
+    }
+
+    /// Replies with `input` to whatever is written to it.
+    struct Scripted(std::io::Cursor<Vec<u8>>);
+
+    impl Read for Scripted {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    impl Write for Scripted {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// A client whose server answers the upgrade, then sends `replies`.
+    fn scripted(replies: &[u8]) -> NetcomClientSync {
+        let mut input = r#"{"version":"mock-1.0"}"#.to_string().to_netstring();
+        input.extend(replies);
+        NetcomClientSync::from_transport(Scripted(std::io::Cursor::new(input)))
+    }
+
+    #[test]
+    fn should_survive_malformed_json_body() {
+        let mut replies = r#"{"R":"read","result":"#.to_string().to_netstring();
+        replies.extend(r#"{"R":"read","result":{"speed":1200.0}}"#.to_string().to_netstring());
+        let mut c = scripted(&replies);
+
+        c.connect().unwrap();
+        let rdops = vec![RdOp::Default {
+            p: "speed".to_string(),
+        }];
+        let err = c.read_parameters("pump-3", rdops.clone()).unwrap_err();
+        assert!(matches!(err, NetcomError::JsonError(_)));
+        assert!(c.is_connected());
+
+        // The framing is intact, so the next reply is read from the same
+        // connection.
+        let result = c.read_parameters("pump-3", rdops).unwrap();
+        assert_eq!(result.get("speed"), Some(&Some(1200.0)));
+        assert_eq!(c.connect_count(), 1);
+    }
+
+    #[test]
+    fn should_disconnect_after_desync() {
+        let mut c = scripted(b"2:{}x");
+
+        c.connect().unwrap();
+        let rdops = vec![RdOp::Default {
+            p: "speed".to_string(),
+        }];
+        let err = c.read_parameters("pump-3", rdops).unwrap_err();
+        assert!(matches!(err, NetcomError::NetstringError(_)));
+        assert!(!c.is_connected());
+
+        // Errors reported by the server leave the connection usable.
+        let server = MockServer::start().with_device("pump-3", &[("speed", 1200.0)]);
+        let mut c = NetcomClientSync::new("127.0.0.1", server.port());
+        let rdops = vec![RdOp::Default {
+            p: "speed".to_string(),
+        }];
+        assert!(matches!(
+            c.read_parameters("missing", rdops),
+            Err(NetcomError::DeviceNotFound)
+        ));
+        assert!(c.is_connected());
+    }
 }
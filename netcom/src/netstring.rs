@@ -18,6 +18,12 @@ impl fmt::Display for NetstringError {
 impl std::error::Error for NetstringError {}
 
 pub fn parse_netstring(buf: &[u8]) -> Result<&[u8], NetstringError> {
+    split_netstring(buf).map(|(payload, _)| payload)
+}
+
+/// Like `parse_netstring`, also returning the number of bytes the netstring
+/// occupies in `buf`, so that any following data can be kept.
+pub fn split_netstring(buf: &[u8]) -> Result<(&[u8], usize), NetstringError> {
     if buf.len() < 3 {
         return Err(NetstringError::Incomplete);
     }
@@ -47,7 +53,7 @@ pub fn parse_netstring(buf: &[u8]) -> Result<&[u8], NetstringError> {
         return Err(NetstringError::Malformed);
     }
 
-    return Ok(&buf[i..(i + string_length)]);
+    Ok((&buf[i..(i + string_length)], i + string_length + 1))
 }
 
 pub trait ToNetstring {
@@ -131,4 +137,13 @@ mod tests {
             assert_eq!(parse_netstring(case.0.as_bytes()), Ok(case.1.as_bytes()));
         }
     }
+
+    #[test]
+    fn should_return_length_of_first_netstring() {
+        assert_eq!(
+            split_netstring("5:abcde,3:xyz,".as_bytes()),
+            Ok(("abcde".as_bytes(), 8))
+        );
+        assert_eq!(split_netstring("0:,".as_bytes()), Ok(("".as_bytes(), 3)));
+    }
 }
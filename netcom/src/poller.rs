@@ -0,0 +1,301 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+use tokio::time::{sleep_until, Instant};
+
+use crate::netcom::{NetcomError, NetcomSync, RdOp};
+use crate::netcom_client_async::NetcomClientAsync;
+
+pub const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+trait PollJob: Send {
+    fn to_rdops(&self) -> Vec<RdOp>;
+    fn update(&mut self, result: &HashMap<String, Option<f64>>) -> Result<(), NetcomError>;
+}
+
+struct StructJob<T, F> {
+    params: T,
+    on_update: F,
+}
+
+impl<T, F> PollJob for StructJob<T, F>
+where
+    T: NetcomSync + Send,
+    F: FnMut(&T) + Send,
+{
+    fn to_rdops(&self) -> Vec<RdOp> {
+        self.params.to_rdops()
+    }
+
+    fn update(&mut self, result: &HashMap<String, Option<f64>>) -> Result<(), NetcomError> {
+        self.params.try_apply_result(result)?;
+        (self.on_update)(&self.params);
+        Ok(())
+    }
+}
+
+/// Jobs sharing a device and interval, polled with a single read request.
+struct Group {
+    device: String,
+    interval: Duration,
+    jobs: Vec<Box<dyn PollJob>>,
+    next: Instant,
+    failures: u32,
+}
+
+type ErrorHandler = Box<dyn FnMut(&str, &NetcomError) + Send>;
+
+/// Periodically reads registered `NetcomSync` structs over one
+/// `NetcomClientAsync` connection.
+///
+/// Missed ticks are skipped rather than bunched up. After a failed read the
+/// group is retried with an exponential backoff, starting at its interval
+/// and capped at the maximum backoff.
+pub struct Poller {
+    client: NetcomClientAsync,
+    groups: Vec<Group>,
+    max_backoff: Duration,
+    on_error: Option<ErrorHandler>,
+}
+
+impl Poller {
+    pub fn new(client: NetcomClientAsync) -> Self {
+        Poller {
+            client,
+            groups: Vec::new(),
+            max_backoff: DEFAULT_MAX_BACKOFF,
+            on_error: None,
+        }
+    }
+
+    pub fn with_max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    /// Called with the device name whenever a read or an update fails.
+    pub fn on_error<F>(&mut self, f: F)
+    where
+        F: FnMut(&str, &NetcomError) + Send + 'static,
+    {
+        self.on_error = Some(Box::new(f));
+    }
+
+    /// Polls `params` from `device` every `interval` and passes the updated
+    /// struct to `on_update`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `interval` is zero.
+    pub fn register<T, F>(&mut self, device: &str, params: T, interval: Duration, on_update: F)
+    where
+        T: NetcomSync + Send + 'static,
+        F: FnMut(&T) + Send + 'static,
+    {
+        assert!(!interval.is_zero(), "poll interval must be non-zero");
+        let job = Box::new(StructJob { params, on_update });
+
+        match self
+            .groups
+            .iter_mut()
+            .find(|g| g.device == device && g.interval == interval)
+        {
+            Some(group) => group.jobs.push(job),
+            None => self.groups.push(Group {
+                device: device.to_string(),
+                interval,
+                jobs: vec![job],
+                next: Instant::now(),
+                failures: 0,
+            }),
+        }
+    }
+
+    /// Like `register`, delivering a copy of each updated struct on a channel.
+    pub fn subscribe<T>(
+        &mut self,
+        device: &str,
+        params: T,
+        interval: Duration,
+    ) -> mpsc::UnboundedReceiver<T>
+    where
+        T: NetcomSync + Clone + Send + 'static,
+    {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.register(device, params, interval, move |params: &T| {
+            let _ = tx.send(params.clone());
+        });
+        rx
+    }
+
+    /// Runs the polling loop. It only returns when no jobs are registered,
+    /// so it is usually spawned as a task.
+    pub async fn run(&mut self) {
+        while let Some(i) = self.next_group() {
+            sleep_until(self.groups[i].next).await;
+            self.poll_group(i).await;
+        }
+    }
+
+    fn next_group(&self) -> Option<usize> {
+        self.groups
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, g)| g.next)
+            .map(|(i, _)| i)
+    }
+
+    async fn poll_group(&mut self, i: usize) {
+        let group = &mut self.groups[i];
+        let rdops = group.jobs.iter().flat_map(|job| job.to_rdops()).collect();
+
+        let mut errors = Vec::new();
+        match self.client.read_parameters(&group.device, rdops).await {
+            Ok(result) => {
+                for job in group.jobs.iter_mut() {
+                    if let Err(e) = job.update(&result) {
+                        errors.push(e);
+                    }
+                }
+                group.failures = 0;
+                group.next = next_tick(group.next, group.interval, Instant::now());
+            }
+            Err(e) => {
+                errors.push(e);
+                group.next =
+                    Instant::now() + backoff(group.interval, group.failures, self.max_backoff);
+                group.failures += 1;
+            }
+        }
+
+        if let Some(on_error) = &mut self.on_error {
+            for e in &errors {
+                on_error(&group.device, e);
+            }
+        }
+    }
+}
+
+/// The first tick after `now` on the schedule `previous + n * interval`.
+fn next_tick(previous: Instant, interval: Duration, now: Instant) -> Instant {
+    let mut next = previous + interval;
+    if next <= now {
+        let period = interval.as_nanos();
+        let skipped = ((now - next).as_nanos() / period + 1) * period;
+        next += Duration::new(
+            (skipped / 1_000_000_000) as u64,
+            (skipped % 1_000_000_000) as u32,
+        );
+    }
+    next
+}
+
+fn backoff(interval: Duration, failures: u32, max_backoff: Duration) -> Duration {
+    interval
+        .saturating_mul(2u32.saturating_pow(failures))
+        .min(max_backoff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_server::MockServer;
+    use crate::netcom::NetcomMap;
+
+    #[derive(Clone, Default, NetcomMap)]
+    #[netcom(crate = "crate")]
+    struct Speed {
+        #[param(p = "speed")]
+        speed: f64,
+    }
+
+    #[derive(Clone, Default, NetcomMap)]
+    #[netcom(crate = "crate")]
+    struct Pressure {
+        #[param(p = "pressure")]
+        pressure: f64,
+    }
+
+    #[test]
+    fn should_skip_missed_ticks() {
+        let start = Instant::now();
+        let interval = Duration::from_millis(100);
+        assert_eq!(next_tick(start, interval, start), start + interval);
+        assert_eq!(
+            next_tick(start, interval, start + Duration::from_millis(350)),
+            start + Duration::from_millis(400)
+        );
+
+        // More missed ticks than fit in a u32.
+        let interval = Duration::from_nanos(1);
+        let now = start + Duration::from_secs(10);
+        assert_eq!(next_tick(start, interval, now), now + interval);
+    }
+
+    #[test]
+    #[should_panic(expected = "non-zero")]
+    fn should_reject_zero_interval() {
+        let mut poller = Poller::new(NetcomClientAsync::new("127.0.0.1", 1));
+        poller.register("pump-3", Speed::default(), Duration::ZERO, |_| {});
+    }
+
+    #[test]
+    fn should_back_off_exponentially() {
+        let interval = Duration::from_secs(1);
+        let max = Duration::from_secs(10);
+        assert_eq!(backoff(interval, 0, max), Duration::from_secs(1));
+        assert_eq!(backoff(interval, 3, max), Duration::from_secs(8));
+        assert_eq!(backoff(interval, 40, max), max);
+    }
+
+    // Time is paused, so the clock only moves when every task waits for a
+    // timer, and jumps straight to the earliest one.
+    #[tokio::test(start_paused = true)]
+    async fn should_coalesce_jobs_into_one_read() {
+        let server =
+            MockServer::start().with_device("pump-3", &[("speed", 1200.0), ("pressure", 2.5)]);
+        let mut poller = Poller::new(NetcomClientAsync::new("127.0.0.1", server.port()));
+
+        let interval = Duration::from_millis(20);
+        let mut speeds = poller.subscribe("pump-3", Speed::default(), interval);
+        let mut pressures = poller.subscribe("pump-3", Pressure::default(), interval);
+
+        let start = Instant::now();
+        let task = tokio::spawn(async move { poller.run().await });
+        for i in 0..3 {
+            assert_eq!(speeds.recv().await.unwrap().speed, 1200.0);
+            assert_eq!(pressures.recv().await.unwrap().pressure, 2.5);
+            assert_eq!(start.elapsed(), interval * i);
+        }
+        task.abort();
+
+        assert_eq!(server.request_count("read"), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn should_report_errors() {
+        let server = MockServer::start();
+        let mut poller = Poller::new(NetcomClientAsync::new("127.0.0.1", server.port()));
+
+        let (tx, mut errors) = mpsc::unbounded_channel();
+        poller.on_error(move |device, e| {
+            let _ = tx.send((Instant::now(), format!("{}: {}", device, e)));
+        });
+        let mut speeds = poller.subscribe("missing", Speed::default(), Duration::from_millis(10));
+
+        let start = Instant::now();
+        let task = tokio::spawn(async move { poller.run().await });
+        let mut attempts = Vec::new();
+        for _ in 0..4 {
+            let (at, error) = errors.recv().await.unwrap();
+            assert_eq!(error, "missing: Device not found");
+            attempts.push((at - start).as_millis());
+        }
+        task.abort();
+
+        assert!(speeds.try_recv().is_err());
+        // Retried after backoffs of 10, 20 and 40 ms.
+        assert_eq!(attempts, [0, 10, 30, 70]);
+    }
+}
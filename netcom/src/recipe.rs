@@ -0,0 +1,582 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, watch};
+use tokio::time::Instant;
+
+use crate::netcom::{NetcomError, RdOp, WrOp};
+use crate::netcom_client_async::NetcomClientAsync;
+
+/// A failed step and its location, e.g. `steps[3].on_failure[0]`.
+type Failure = (String, StepError);
+
+/// Shortest interval between two reads of a `wait_for` step, so that a
+/// recipe cannot flood the server.
+const MIN_POLL_MS: u64 = 10;
+
+fn default_poll_ms() -> u64 {
+    100
+}
+
+/// A test on a parameter value, e.g. `{"gt": 50}` or `{"between": [10, 20]}`.
+/// A parameter without a value fails every condition.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Condition {
+    Eq(f64),
+    Ne(f64),
+    Gt(f64),
+    Ge(f64),
+    Lt(f64),
+    Le(f64),
+    /// Inclusive range.
+    Between(f64, f64),
+}
+
+impl Condition {
+    pub fn holds(&self, value: f64) -> bool {
+        match *self {
+            Condition::Eq(x) => value == x,
+            Condition::Ne(x) => value != x,
+            Condition::Gt(x) => value > x,
+            Condition::Ge(x) => value >= x,
+            Condition::Lt(x) => value < x,
+            Condition::Le(x) => value <= x,
+            Condition::Between(min, max) => (min..=max).contains(&value),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "step", rename_all = "snake_case")]
+pub enum Step {
+    Write {
+        device: String,
+        params: BTreeMap<String, f64>,
+    },
+    /// Polls a parameter until the condition holds, failing after the
+    /// timeout. `poll_ms` is raised to at least 10 ms.
+    WaitFor {
+        device: String,
+        param: String,
+        condition: Condition,
+        timeout_ms: u64,
+        #[serde(default = "default_poll_ms")]
+        poll_ms: u64,
+    },
+    Delay {
+        ms: u64,
+    },
+    /// Reads a parameter once and fails unless the condition holds.
+    Assert {
+        device: String,
+        param: String,
+        condition: Condition,
+    },
+    /// Runs `steps`, and if one of them fails runs `on_failure` in place of
+    /// the rest. The step fails only if `on_failure` does.
+    Try {
+        steps: Vec<Step>,
+        on_failure: Vec<Step>,
+    },
+}
+
+/// A sequence of steps, usually loaded from JSON:
+///
+/// ```json
+/// {
+///   "name": "start-up",
+///   "steps": [
+///     { "step": "write", "device": "pump-3", "params": { "enable": 1 } },
+///     { "step": "wait_for", "device": "pump-3", "param": "pressure",
+///       "condition": { "gt": 50 }, "timeout_ms": 30000 },
+///     { "step": "delay", "ms": 10000 }
+///   ],
+///   "rollback": [
+///     { "step": "write", "device": "pump-3", "params": { "enable": 0 } }
+///   ]
+/// }
+/// ```
+///
+/// The rollback steps run when the recipe fails or is aborted.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Recipe {
+    pub name: String,
+    pub steps: Vec<Step>,
+    #[serde(default)]
+    pub rollback: Vec<Step>,
+}
+
+impl Recipe {
+    pub fn from_json(s: &str) -> Result<Self, NetcomError> {
+        serde_json::from_str(s).map_err(NetcomError::JsonError)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum RecipeOutcome {
+    Completed,
+    Failed { location: String, error: String },
+    Aborted,
+}
+
+/// Progress of a recipe. Steps are located by their path in the recipe,
+/// e.g. `steps[2]` or `steps[3].on_failure[0]`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum RecipeEvent {
+    StepStarted { location: String },
+    StepCompleted { location: String, detail: String },
+    StepFailed { location: String, error: String },
+    RollbackStarted,
+    Finished { outcome: RecipeOutcome },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct LogEntry {
+    #[serde(with = "crate::timestamp")]
+    pub timestamp: SystemTime,
+    #[serde(flatten)]
+    pub event: RecipeEvent,
+}
+
+/// The outcome of a run, with every event in the order it happened.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RecipeReport {
+    pub recipe: String,
+    pub outcome: RecipeOutcome,
+    pub log: Vec<LogEntry>,
+}
+
+/// Aborts a running recipe from another task. The current step is
+/// interrupted at its next read, write or wait, and the rollback runs.
+#[derive(Clone)]
+pub struct AbortHandle {
+    tx: Arc<watch::Sender<bool>>,
+}
+
+impl AbortHandle {
+    pub fn abort(&self) {
+        self.tx.send_replace(true);
+    }
+}
+
+enum StepError {
+    Netcom(NetcomError),
+    NoValue(String),
+    ConditionFailed { param: String, value: f64 },
+    Timeout(String),
+    Aborted,
+}
+
+impl fmt::Display for StepError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StepError::Netcom(e) => write!(f, "{}", e),
+            StepError::NoValue(p) => write!(f, "No value for {}", p),
+            StepError::ConditionFailed { param, value } => {
+                write!(f, "Condition failed for {} = {}", param, value)
+            }
+            StepError::Timeout(p) => write!(f, "Timed out waiting for {}", p),
+            StepError::Aborted => write!(f, "Aborted"),
+        }
+    }
+}
+
+impl From<NetcomError> for StepError {
+    fn from(e: NetcomError) -> Self {
+        StepError::Netcom(e)
+    }
+}
+
+/// Runs recipes against a client, one at a time.
+pub struct RecipeRunner<'a> {
+    client: &'a mut NetcomClientAsync,
+    abort_tx: Arc<watch::Sender<bool>>,
+    abort_rx: watch::Receiver<bool>,
+    event_sender: Option<mpsc::UnboundedSender<RecipeEvent>>,
+    log: Vec<LogEntry>,
+}
+
+impl<'a> RecipeRunner<'a> {
+    pub fn new(client: &'a mut NetcomClientAsync) -> Self {
+        let (abort_tx, abort_rx) = watch::channel(false);
+        RecipeRunner {
+            client,
+            abort_tx: Arc::new(abort_tx),
+            abort_rx,
+            event_sender: None,
+            log: Vec::new(),
+        }
+    }
+
+    pub fn abort_handle(&self) -> AbortHandle {
+        AbortHandle {
+            tx: self.abort_tx.clone(),
+        }
+    }
+
+    /// Progress events, as they happen. They are also kept in the report.
+    pub fn events(&mut self) -> mpsc::UnboundedReceiver<RecipeEvent> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.event_sender = Some(tx);
+        rx
+    }
+
+    pub async fn run(&mut self, recipe: &Recipe) -> RecipeReport {
+        self.log.clear();
+
+        let outcome = match self.run_steps(&recipe.steps, "steps").await {
+            Ok(()) => RecipeOutcome::Completed,
+            Err((_, StepError::Aborted)) => RecipeOutcome::Aborted,
+            Err((location, e)) => RecipeOutcome::Failed {
+                location,
+                error: e.to_string(),
+            },
+        };
+
+        if outcome != RecipeOutcome::Completed && !recipe.rollback.is_empty() {
+            self.emit(RecipeEvent::RollbackStarted);
+            // Rollback steps run to the end whatever fails, and cannot be
+            // aborted.
+            self.abort_tx.send_replace(false);
+            for (i, step) in recipe.rollback.iter().enumerate() {
+                let _ = self.run_step(step, format!("rollback[{}]", i)).await;
+            }
+        }
+
+        self.emit(RecipeEvent::Finished {
+            outcome: outcome.clone(),
+        });
+        // An abort requested before the run started stops it at the first
+        // step, but does not carry over to the next run.
+        self.abort_tx.send_replace(false);
+        RecipeReport {
+            recipe: recipe.name.clone(),
+            outcome,
+            log: std::mem::take(&mut self.log),
+        }
+    }
+
+    /// Runs steps in order, stopping at the first failure, which is
+    /// returned with the location of the failed step.
+    fn run_steps<'b>(
+        &'b mut self,
+        steps: &'b [Step],
+        prefix: &'b str,
+    ) -> Pin<Box<dyn Future<Output = Result<(), Failure>> + Send + 'b>> {
+        Box::pin(async move {
+            for (i, step) in steps.iter().enumerate() {
+                let location = format!("{}[{}]", prefix, i);
+                self.run_step(step, location.clone())
+                    .await
+                    .map_err(|e| (location, e))?;
+            }
+            Ok(())
+        })
+    }
+
+    async fn run_step(&mut self, step: &Step, location: String) -> Result<(), StepError> {
+        self.emit(RecipeEvent::StepStarted {
+            location: location.clone(),
+        });
+
+        let result = match step {
+            Step::Try { steps, on_failure } => {
+                match self.run_steps(steps, &format!("{}.steps", location)).await {
+                    Ok(()) => Ok("completed".to_string()),
+                    Err((_, StepError::Aborted)) => Err(StepError::Aborted),
+                    Err(_) => self
+                        .run_steps(on_failure, &format!("{}.on_failure", location))
+                        .await
+                        .map(|()| "recovered".to_string())
+                        .map_err(|(_, e)| e),
+                }
+            }
+            _ => self.execute(step).await,
+        };
+
+        match &result {
+            Ok(detail) => self.emit(RecipeEvent::StepCompleted {
+                location,
+                detail: detail.clone(),
+            }),
+            Err(e) => self.emit(RecipeEvent::StepFailed {
+                location,
+                error: e.to_string(),
+            }),
+        }
+        result.map(|_| ())
+    }
+
+    /// Runs a step other than `Try`, returning a description of the result.
+    async fn execute(&mut self, step: &Step) -> Result<String, StepError> {
+        match step {
+            Step::Write { device, params } => {
+                let wrops = params
+                    .iter()
+                    .map(|(p, v)| WrOp::Default {
+                        p: p.clone(),
+                        v: *v,
+                    })
+                    .collect();
+                self.check_abort()?;
+                self.client.write_parameters(device, wrops).await?;
+                Ok("written".to_string())
+            }
+            Step::WaitFor {
+                device,
+                param,
+                condition,
+                timeout_ms,
+                poll_ms,
+            } => {
+                let deadline = Instant::now() + Duration::from_millis(*timeout_ms);
+                loop {
+                    if let Some(v) = self.read(device, param).await? {
+                        if condition.holds(v) {
+                            return Ok(format!("{} = {}", param, v));
+                        }
+                    }
+                    if Instant::now() >= deadline {
+                        return Err(StepError::Timeout(param.clone()));
+                    }
+                    let wait = Duration::from_millis((*poll_ms).max(MIN_POLL_MS))
+                        .min(deadline.saturating_duration_since(Instant::now()));
+                    self.sleep(wait).await?;
+                }
+            }
+            Step::Delay { ms } => {
+                self.sleep(Duration::from_millis(*ms)).await?;
+                Ok("done".to_string())
+            }
+            Step::Assert {
+                device,
+                param,
+                condition,
+            } => match self.read(device, param).await? {
+                Some(v) if condition.holds(v) => Ok(format!("{} = {}", param, v)),
+                Some(v) => Err(StepError::ConditionFailed {
+                    param: param.clone(),
+                    value: v,
+                }),
+                None => Err(StepError::NoValue(param.clone())),
+            },
+            Step::Try { .. } => unreachable!("handled by run_step"),
+        }
+    }
+
+    async fn read(&mut self, device: &str, param: &str) -> Result<Option<f64>, StepError> {
+        self.check_abort()?;
+        let rdops = vec![RdOp::Default {
+            p: param.to_string(),
+        }];
+        let values = self.client.read_parameters(device, rdops).await?;
+        Ok(values.get(param).copied().flatten())
+    }
+
+    async fn sleep(&mut self, duration: Duration) -> Result<(), StepError> {
+        let mut abort = self.abort_rx.clone();
+        tokio::select! {
+            _ = tokio::time::sleep(duration) => Ok(()),
+            _ = abort.wait_for(|aborted| *aborted) => Err(StepError::Aborted),
+        }
+    }
+
+    fn check_abort(&self) -> Result<(), StepError> {
+        match *self.abort_rx.borrow() {
+            true => Err(StepError::Aborted),
+            false => Ok(()),
+        }
+    }
+
+    fn emit(&mut self, event: RecipeEvent) {
+        if let Some(tx) = &self.event_sender {
+            let _ = tx.send(event.clone());
+        }
+        self.log.push(LogEntry {
+            timestamp: SystemTime::now(),
+            event,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_server::MockServer;
+
+    const START_UP: &str = r#"{
+        "name": "start-up",
+        "steps": [
+            { "step": "write", "device": "pump-3", "params": { "enable": 1 } },
+            { "step": "wait_for", "device": "pump-3", "param": "pressure",
+              "condition": { "gt": 50 }, "timeout_ms": 2000, "poll_ms": 5 },
+            { "step": "delay", "ms": 10 },
+            { "step": "try",
+              "steps": [
+                { "step": "assert", "device": "pump-3", "param": "temp",
+                  "condition": { "between": [10, 40] } }
+              ],
+              "on_failure": [
+                { "step": "write", "device": "pump-3", "params": { "cooling": 1 } }
+              ] },
+            { "step": "assert", "device": "pump-3", "param": "enable", "condition": { "eq": 1 } }
+        ],
+        "rollback": [
+            { "step": "write", "device": "pump-3", "params": { "enable": 0 } }
+        ]
+    }"#;
+
+    fn server() -> MockServer {
+        MockServer::start().with_device(
+            "pump-3",
+            &[
+                ("enable", 0.0),
+                ("pressure", 0.0),
+                ("temp", 45.0),
+                ("cooling", 0.0),
+            ],
+        )
+    }
+
+    fn locations(report: &RecipeReport) -> Vec<String> {
+        report
+            .log
+            .iter()
+            .filter_map(|entry| match &entry.event {
+                RecipeEvent::StepFailed { location, .. } => Some(format!("failed {}", location)),
+                RecipeEvent::StepCompleted { location, .. } => Some(location.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn should_evaluate_conditions() {
+        assert!(Condition::Gt(50.0).holds(50.5));
+        assert!(!Condition::Gt(50.0).holds(50.0));
+        assert!(Condition::Between(10.0, 40.0).holds(40.0));
+        assert!(!Condition::Ne(1.0).holds(1.0));
+    }
+
+    #[tokio::test]
+    async fn should_run_recipe_and_branch_on_failure() {
+        let server = server();
+        let mut client = NetcomClientAsync::new("127.0.0.1", server.port());
+        let recipe = Recipe::from_json(START_UP).unwrap();
+        let mut runner = RecipeRunner::new(&mut client);
+        let mut events = runner.events();
+
+        // Another client raises the pressure while the recipe waits for it.
+        let port = server.port();
+        let writer = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(30));
+            let mut other = crate::netcom_client_sync::NetcomClientSync::new("127.0.0.1", port);
+            let wrops = vec![WrOp::Default {
+                p: "pressure".to_string(),
+                v: 60.0,
+            }];
+            other.write_parameters("pump-3", wrops).unwrap();
+        });
+
+        let report = runner.run(&recipe).await;
+        assert_eq!(report.outcome, RecipeOutcome::Completed);
+        assert_eq!(
+            locations(&report),
+            [
+                "steps[0]",
+                "steps[1]",
+                "steps[2]",
+                "failed steps[3].steps[0]",
+                "steps[3].on_failure[0]",
+                "steps[3]",
+                "steps[4]",
+            ]
+        );
+        assert_eq!(server.value("pump-3", "cooling"), Some(1.0));
+        assert_eq!(server.value("pump-3", "enable"), Some(1.0));
+        assert_eq!(
+            events.recv().await.unwrap(),
+            RecipeEvent::StepStarted {
+                location: "steps[0]".to_string()
+            }
+        );
+        writer.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn should_roll_back_after_failure() {
+        let server = server();
+        let mut client = NetcomClientAsync::new("127.0.0.1", server.port());
+        let mut recipe = Recipe::from_json(START_UP).unwrap();
+        recipe.steps[1] = Step::WaitFor {
+            device: "pump-3".to_string(),
+            param: "pressure".to_string(),
+            condition: Condition::Gt(50.0),
+            timeout_ms: 20,
+            poll_ms: 5,
+        };
+
+        let report = RecipeRunner::new(&mut client).run(&recipe).await;
+        assert_eq!(
+            report.outcome,
+            RecipeOutcome::Failed {
+                location: "steps[1]".to_string(),
+                error: "Timed out waiting for pressure".to_string(),
+            }
+        );
+        assert_eq!(locations(&report)[2], "rollback[0]");
+        assert_eq!(server.value("pump-3", "enable"), Some(0.0));
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["outcome"]["outcome"], "failed");
+        assert_eq!(json["log"][0]["event"], "step_started");
+    }
+
+    #[tokio::test]
+    async fn should_roll_back_after_abort() {
+        let server = server();
+        let mut client = NetcomClientAsync::new("127.0.0.1", server.port());
+        let recipe = Recipe::from_json(START_UP).unwrap();
+        let mut runner = RecipeRunner::new(&mut client);
+
+        let abort = runner.abort_handle();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(30)).await;
+            abort.abort();
+        });
+
+        let report = runner.run(&recipe).await;
+        assert_eq!(report.outcome, RecipeOutcome::Aborted);
+        assert_eq!(
+            locations(&report),
+            ["steps[0]", "failed steps[1]", "rollback[0]"]
+        );
+        assert_eq!(server.value("pump-3", "enable"), Some(0.0));
+    }
+
+    #[tokio::test]
+    async fn should_abort_before_the_first_step() {
+        let server = server();
+        let mut client = NetcomClientAsync::new("127.0.0.1", server.port());
+        let recipe = Recipe::from_json(START_UP).unwrap();
+        let mut runner = RecipeRunner::new(&mut client);
+
+        runner.abort_handle().abort();
+        let report = runner.run(&recipe).await;
+        assert_eq!(report.outcome, RecipeOutcome::Aborted);
+        assert_eq!(locations(&report), ["failed steps[0]", "rollback[0]"]);
+        assert_eq!(server.request_count("write"), 1);
+
+        server.set_value("pump-3", "pressure", 60.0);
+        let report = runner.run(&recipe).await;
+        assert_eq!(report.outcome, RecipeOutcome::Completed);
+    }
+}
@@ -0,0 +1,349 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tokio::time::MissedTickBehavior;
+
+use crate::netcom::{NetcomError, RdOp, WrOp};
+use crate::netcom_client_async::NetcomClientAsync;
+
+type Params = BTreeMap<String, f64>;
+
+/// Desired parameter values, by device name and by device type:
+///
+/// ```json
+/// {
+///   "tolerance": 0.01,
+///   "device_types": { "pump": { "speed": 1200 } },
+///   "devices": { "pump-3": { "speed": 900 }, "boiler-1": { "sp": 65 } }
+/// }
+/// ```
+///
+/// Values given for a device name override those for its type.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DesiredState {
+    #[serde(default)]
+    pub devices: BTreeMap<String, Params>,
+    #[serde(default)]
+    pub device_types: BTreeMap<String, Params>,
+    /// Largest difference from the desired value that is not drift.
+    #[serde(default)]
+    pub tolerance: f64,
+}
+
+impl DesiredState {
+    pub fn from_json(s: &str) -> Result<Self, NetcomError> {
+        serde_json::from_str(s).map_err(NetcomError::JsonError)
+    }
+
+    /// Desired values per device, given the type of every known device.
+    pub fn resolve(&self, device_types: &HashMap<String, String>) -> BTreeMap<String, Params> {
+        let mut resolved: BTreeMap<String, Params> = BTreeMap::new();
+        for (device, device_type) in device_types {
+            if let Some(params) = self.device_types.get(device_type) {
+                resolved.insert(device.clone(), params.clone());
+            }
+        }
+        for (device, params) in &self.devices {
+            resolved
+                .entry(device.clone())
+                .or_default()
+                .extend(params.iter().map(|(p, v)| (p.clone(), *v)));
+        }
+        resolved
+    }
+}
+
+/// A parameter whose current value differs from the desired one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Drift {
+    pub device: String,
+    pub param: String,
+    pub desired: f64,
+    /// `None` if the device returned no value.
+    pub actual: Option<f64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    /// Only report drift.
+    Report,
+    /// Write the desired value of every drifted parameter.
+    Apply,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ReconcileEvent {
+    Drift(Drift),
+    /// A drifted parameter was written, with the value echoed by the device.
+    Applied {
+        drift: Drift,
+        value: Option<f64>,
+    },
+    /// Reading or writing a device failed.
+    Failed {
+        device: String,
+        kind: &'static str,
+        message: String,
+    },
+}
+
+/// The outcome of one reconciliation pass.
+#[derive(Debug, Default)]
+pub struct ReconcileReport {
+    pub drift: Vec<Drift>,
+    /// Writes made in apply mode, with the values echoed by the devices.
+    pub applied: Vec<(Drift, Option<f64>)>,
+    pub errors: Vec<(String, NetcomError)>,
+}
+
+impl ReconcileReport {
+    /// Whether every device was read and none had drifted.
+    pub fn is_in_sync(&self) -> bool {
+        self.drift.is_empty() && self.errors.is_empty()
+    }
+}
+
+/// The parameters of `device` that are not within `tolerance` of their
+/// desired values.
+pub fn compute_drift(
+    device: &str,
+    desired: &Params,
+    actual: &HashMap<String, Option<f64>>,
+    tolerance: f64,
+) -> Vec<Drift> {
+    desired
+        .iter()
+        .filter_map(|(p, desired)| {
+            let actual = actual.get(p).copied().flatten();
+            let in_sync = actual.is_some_and(|v| (v - desired).abs() <= tolerance);
+            (!in_sync).then(|| Drift {
+                device: device.to_string(),
+                param: p.clone(),
+                desired: *desired,
+                actual,
+            })
+        })
+        .collect()
+}
+
+/// Compares devices with a `DesiredState`, and in apply mode writes the
+/// parameters that have drifted.
+///
+/// Each pass reads every device with desired values once. A device that
+/// cannot be read or written is reported and skipped, so one offline unit
+/// does not hold up the others.
+pub struct Reconciler {
+    client: NetcomClientAsync,
+    desired: DesiredState,
+    mode: Mode,
+    event_sender: Option<mpsc::UnboundedSender<ReconcileEvent>>,
+}
+
+impl Reconciler {
+    pub fn new(client: NetcomClientAsync, desired: DesiredState, mode: Mode) -> Self {
+        Reconciler {
+            client,
+            desired,
+            mode,
+            event_sender: None,
+        }
+    }
+
+    /// Events for drift, writes and failures, as they happen.
+    pub fn events(&mut self) -> mpsc::UnboundedReceiver<ReconcileEvent> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.event_sender = Some(tx);
+        rx
+    }
+
+    pub async fn run_once(&mut self) -> ReconcileReport {
+        let mut report = ReconcileReport::default();
+
+        let device_types = if self.desired.device_types.is_empty() {
+            HashMap::new()
+        } else {
+            match self.client.get_device_list().await {
+                Ok(devices) => devices
+                    .into_iter()
+                    .map(|d| (d.name, d.device_type))
+                    .collect(),
+                Err(e) => {
+                    self.fail(&mut report, "", e);
+                    HashMap::new()
+                }
+            }
+        };
+
+        for (device, desired) in self.desired.resolve(&device_types) {
+            let rdops = desired
+                .keys()
+                .map(|p| RdOp::Default { p: p.clone() })
+                .collect();
+            let actual = match self.client.read_parameters(&device, rdops).await {
+                Ok(actual) => actual,
+                Err(e) => {
+                    self.fail(&mut report, &device, e);
+                    continue;
+                }
+            };
+
+            let drift = compute_drift(&device, &desired, &actual, self.desired.tolerance);
+            for d in &drift {
+                self.emit(ReconcileEvent::Drift(d.clone()));
+            }
+            if self.mode == Mode::Apply && !drift.is_empty() {
+                self.apply(&mut report, &device, &drift).await;
+            }
+            report.drift.extend(drift);
+        }
+
+        report
+    }
+
+    /// Runs a pass every `interval`. Results are only delivered as events,
+    /// so call `events` first.
+    pub async fn run(&mut self, interval: Duration) {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        loop {
+            ticker.tick().await;
+            self.run_once().await;
+        }
+    }
+
+    async fn apply(&mut self, report: &mut ReconcileReport, device: &str, drift: &[Drift]) {
+        let wrops = drift
+            .iter()
+            .map(|d| WrOp::Default {
+                p: d.param.clone(),
+                v: d.desired,
+            })
+            .collect();
+
+        match self.client.write_parameters(device, wrops).await {
+            Ok(echo) => {
+                for d in drift {
+                    let value = echo.get(&d.param).copied().flatten();
+                    self.emit(ReconcileEvent::Applied {
+                        drift: d.clone(),
+                        value,
+                    });
+                    report.applied.push((d.clone(), value));
+                }
+            }
+            Err(e) => self.fail(report, device, e),
+        }
+    }
+
+    fn fail(&self, report: &mut ReconcileReport, device: &str, e: NetcomError) {
+        self.emit(ReconcileEvent::Failed {
+            device: device.to_string(),
+            kind: e.kind(),
+            message: e.to_string(),
+        });
+        report.errors.push((device.to_string(), e));
+    }
+
+    fn emit(&self, event: ReconcileEvent) {
+        if let Some(tx) = &self.event_sender {
+            let _ = tx.send(event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_server::MockServer;
+
+    const DESIRED: &str = r#"{
+        "tolerance": 0.5,
+        "device_types": { "mock": { "speed": 1200 } },
+        "devices": { "pump-4": { "speed": 900, "mode": 2 }, "missing": { "sp": 1 } }
+    }"#;
+
+    #[test]
+    fn should_resolve_device_types_and_overrides() {
+        let desired = DesiredState::from_json(DESIRED).unwrap();
+        let types = HashMap::from([
+            ("pump-3".to_string(), "mock".to_string()),
+            ("pump-4".to_string(), "mock".to_string()),
+        ]);
+
+        let resolved = desired.resolve(&types);
+        assert_eq!(
+            resolved["pump-3"],
+            Params::from([("speed".to_string(), 1200.0)])
+        );
+        assert_eq!(
+            resolved["pump-4"],
+            Params::from([("speed".to_string(), 900.0), ("mode".to_string(), 2.0)])
+        );
+        assert!(resolved.contains_key("missing"));
+    }
+
+    #[test]
+    fn should_compute_drift_within_tolerance() {
+        let desired = Params::from([
+            ("a".to_string(), 10.0),
+            ("b".to_string(), 20.0),
+            ("c".to_string(), 30.0),
+        ]);
+        let actual = HashMap::from([("a".to_string(), Some(10.4)), ("b".to_string(), Some(21.0))]);
+
+        let drift = compute_drift("unit", &desired, &actual, 0.5);
+        let params: Vec<_> = drift.iter().map(|d| (d.param.as_str(), d.actual)).collect();
+        assert_eq!(params, [("b", Some(21.0)), ("c", None)]);
+    }
+
+    #[tokio::test]
+    async fn should_report_without_writing() {
+        let server = MockServer::start()
+            .with_device("pump-3", &[("speed", 1000.0)])
+            .with_device("pump-4", &[("speed", 900.0), ("mode", 1.0)]);
+        let client = NetcomClientAsync::new("127.0.0.1", server.port());
+        let desired = DesiredState::from_json(DESIRED).unwrap();
+        let mut reconciler = Reconciler::new(client, desired, Mode::Report);
+        let mut events = reconciler.events();
+
+        let report = reconciler.run_once().await;
+        assert!(!report.is_in_sync());
+        assert_eq!(report.drift.len(), 2);
+        assert_eq!(report.errors[0].0, "missing");
+        assert_eq!(server.request_count("write"), 0);
+
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            ReconcileEvent::Failed { device, kind: "device_not_found", .. } if device == "missing"
+        ));
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            ReconcileEvent::Drift(Drift { device, actual: Some(1000.0), .. }) if device == "pump-3"
+        ));
+    }
+
+    #[tokio::test]
+    async fn should_apply_desired_values() {
+        let server = MockServer::start()
+            .with_device("pump-3", &[("speed", 1000.0)])
+            .with_device("pump-4", &[("speed", 900.0), ("mode", 1.0)])
+            .with_range("pump-4", "mode", 0.0, 1.0);
+        let client = NetcomClientAsync::new("127.0.0.1", server.port());
+        let mut desired = DesiredState::from_json(DESIRED).unwrap();
+        desired.devices.remove("missing");
+        let mut reconciler = Reconciler::new(client, desired, Mode::Apply);
+
+        let report = reconciler.run_once().await;
+        assert_eq!(report.applied.len(), 2);
+        assert_eq!(server.value("pump-3", "speed"), Some(1200.0));
+
+        // The clamped parameter keeps drifting; the other one has converged.
+        let report = reconciler.run_once().await;
+        assert_eq!(report.drift.len(), 1);
+        assert_eq!(report.drift[0].param, "mode");
+        assert_eq!(report.applied[0].1, Some(1.0));
+    }
+}
@@ -0,0 +1,915 @@
+//! Recording of netcom traffic and deterministic replay of it.
+//!
+//! Every netstring passing between a client and the server is appended to
+//! a JSON-lines file as a `Frame`. The traffic is recorded either by
+//! wrapping the client's transport with `Recording::wrap`, or by a
+//! `Recorder`, a proxy in front of the server that clients connect to
+//! instead. A `Replay` feeds such a session back to a client, answering
+//! each request with the responses recorded after it, through a
+//! `ReplayTransport` or a `ReplayServer`, so that a session captured on site
+//! can be replayed against either client in a test.
+
+use std::{
+    collections::VecDeque,
+    fs::OpenOptions,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    path::Path,
+    sync::{Arc, Mutex},
+    thread,
+    time::SystemTime,
+};
+
+#[cfg(feature = "tokio")]
+use std::{
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+#[cfg(feature = "tokio")]
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::netcom::NetcomError;
+use crate::netstring::{split_netstring, NetstringError, ToNetstring};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    /// From the client to the server.
+    Request,
+    /// From the server to the client, including push events.
+    Response,
+}
+
+/// One netstring passed between a client and the server. `connection`
+/// numbers the connections made through the recorder, from 0.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Frame {
+    pub connection: usize,
+    #[serde(with = "crate::timestamp")]
+    pub timestamp: SystemTime,
+    pub direction: Direction,
+    pub data: String,
+}
+
+/// The frames of a recording, in the order the recorder saw them.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Session {
+    pub frames: Vec<Frame>,
+}
+
+impl Session {
+    pub fn from_json_lines(text: &str) -> Result<Self, NetcomError> {
+        let frames = text
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| serde_json::from_str(line).map_err(NetcomError::JsonError))
+            .collect::<Result<_, _>>()?;
+        Ok(Session { frames })
+    }
+
+    pub fn to_json_lines(&self) -> String {
+        self.frames
+            .iter()
+            .map(|frame| serde_json::to_string(frame).unwrap() + "\n")
+            .collect()
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, NetcomError> {
+        let text = std::fs::read_to_string(path).map_err(NetcomError::StreamError)?;
+        Session::from_json_lines(&text)
+    }
+
+    fn connection(&self, connection: usize) -> Vec<Frame> {
+        self.frames
+            .iter()
+            .filter(|frame| frame.connection == connection)
+            .cloned()
+            .collect()
+    }
+}
+
+struct RecorderLog {
+    writer: Box<dyn Write + Send>,
+    frames: Vec<Frame>,
+    next_connection: usize,
+    /// The first error writing to the file, kept until taken.
+    error: Option<io::Error>,
+}
+
+impl RecorderLog {
+    fn record(&mut self, frame: Frame) {
+        let result = serde_json::to_vec(&frame)
+            .map_err(io::Error::from)
+            .and_then(|mut line| {
+                line.push(b'\n');
+                self.writer.write_all(&line)?;
+                self.writer.flush()
+            });
+        if let Err(e) = result {
+            self.error.get_or_insert(e);
+        }
+        self.frames.push(frame);
+    }
+}
+
+/// Splits the bytes sent in one direction of a connection into netstrings.
+/// Requests start with the `PROTO30` line, which is skipped.
+struct FrameSplitter {
+    pending: Vec<u8>,
+    upgraded: bool,
+}
+
+impl FrameSplitter {
+    fn new(direction: Direction) -> Self {
+        FrameSplitter {
+            pending: Vec::new(),
+            upgraded: direction == Direction::Response,
+        }
+    }
+
+    /// The netstrings completed by `bytes`.
+    fn feed(&mut self, bytes: &[u8]) -> Vec<String> {
+        self.pending.extend_from_slice(bytes);
+        if !self.upgraded {
+            match self.pending.iter().position(|b| *b == b'\n') {
+                Some(i) => {
+                    self.upgraded = true;
+                    self.pending.drain(..=i);
+                }
+                None => self.pending.clear(),
+            }
+        }
+
+        let mut frames = Vec::new();
+        while self.upgraded && !self.pending.is_empty() {
+            match split_netstring(&self.pending) {
+                Ok((s, n)) => {
+                    frames.push(String::from_utf8_lossy(s).into_owned());
+                    self.pending.drain(..n);
+                }
+                Err(NetstringError::Incomplete) => break,
+                // Not netcom traffic after all, passed on without recording.
+                Err(NetstringError::Malformed) => self.pending.clear(),
+            }
+        }
+        frames
+    }
+}
+
+/// Frames recorded from any number of connections, appended to a writer as
+/// JSON lines. Clones share the same recording.
+#[derive(Clone)]
+pub struct Recording {
+    log: Arc<Mutex<RecorderLog>>,
+}
+
+impl Recording {
+    pub fn new(writer: impl Write + Send + 'static) -> Self {
+        Recording {
+            log: Arc::new(Mutex::new(RecorderLog {
+                writer: Box::new(writer),
+                frames: Vec::new(),
+                next_connection: 0,
+                error: None,
+            })),
+        }
+    }
+
+    /// Records to the file at `path`, appending if it exists.
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Recording::new(file))
+    }
+
+    /// Records the traffic over `transport` as the next connection. The
+    /// transport is any stream a client can run over, see `transport`.
+    pub fn wrap<T>(&self, transport: T) -> RecordingTransport<T> {
+        RecordingTransport {
+            inner: transport,
+            connection: self.next_connection(),
+            recording: self.clone(),
+            requests: FrameSplitter::new(Direction::Request),
+            responses: FrameSplitter::new(Direction::Response),
+        }
+    }
+
+    /// The frames recorded so far.
+    pub fn session(&self) -> Session {
+        Session {
+            frames: self.log.lock().unwrap().frames.clone(),
+        }
+    }
+
+    /// The first error writing a frame since the last call. Recording goes
+    /// on after an error, and the frame is still kept in `session`, but the
+    /// file is missing frames.
+    pub fn take_error(&self) -> Option<io::Error> {
+        self.log.lock().unwrap().error.take()
+    }
+
+    fn next_connection(&self) -> usize {
+        let mut log = self.log.lock().unwrap();
+        log.next_connection += 1;
+        log.next_connection - 1
+    }
+
+    fn record(&self, connection: usize, direction: Direction, frames: Vec<String>) {
+        let mut log = self.log.lock().unwrap();
+        for data in frames {
+            log.record(Frame {
+                connection,
+                timestamp: SystemTime::now(),
+                direction,
+                data,
+            });
+        }
+    }
+}
+
+/// A transport recording the netstrings written to and read from it. A
+/// request is recorded once written in full, before its response can be
+/// read.
+pub struct RecordingTransport<T> {
+    inner: T,
+    connection: usize,
+    recording: Recording,
+    requests: FrameSplitter,
+    responses: FrameSplitter,
+}
+
+impl<T> RecordingTransport<T> {
+    fn record(&mut self, direction: Direction, bytes: &[u8]) {
+        let splitter = match direction {
+            Direction::Request => &mut self.requests,
+            Direction::Response => &mut self.responses,
+        };
+        let frames = splitter.feed(bytes);
+        if !frames.is_empty() {
+            self.recording.record(self.connection, direction, frames);
+        }
+    }
+}
+
+impl<T: Read> Read for RecordingTransport<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.record(Direction::Response, &buf[..n]);
+        Ok(n)
+    }
+}
+
+impl<T: Write> Write for RecordingTransport<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.record(Direction::Request, &buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<T: AsyncRead + Unpin> AsyncRead for RecordingTransport<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let start = buf.filled().len();
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        this.record(Direction::Response, &buf.filled()[start..]);
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<T: AsyncWrite + Unpin> AsyncWrite for RecordingTransport<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let n = ready!(Pin::new(&mut this.inner).poll_write(cx, buf))?;
+        this.record(Direction::Request, &buf[..n]);
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+/// Proxy recording the traffic between its clients and a netcom server.
+pub struct Recorder {
+    port: u16,
+    recording: Recording,
+}
+
+impl Recorder {
+    /// Listens on a free local port, forwarding connections to
+    /// `hostname:port` and appending their frames to the file at `path`.
+    pub fn start(hostname: &str, port: u16, path: impl AsRef<Path>) -> io::Result<Recorder> {
+        let recording = Recording::create(path)?;
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let local_port = listener.local_addr()?.port();
+
+        let upstream = format!("{}:{}", hostname, port);
+        let shared = recording.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let upstream = upstream.clone();
+                let recording = shared.clone();
+                thread::spawn(move || forward(stream, &upstream, recording));
+            }
+        });
+
+        Ok(Recorder {
+            port: local_port,
+            recording,
+        })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// The frames recorded so far.
+    pub fn session(&self) -> Session {
+        self.recording.session()
+    }
+
+    /// See `Recording::take_error`.
+    pub fn take_error(&self) -> Option<io::Error> {
+        self.recording.take_error()
+    }
+}
+
+fn forward(client: TcpStream, upstream: &str, recording: Recording) {
+    let Ok(server) = TcpStream::connect(upstream) else {
+        return;
+    };
+    let connection = recording.next_connection();
+
+    let (Ok(client_reader), Ok(server_writer)) = (client.try_clone(), server.try_clone()) else {
+        return;
+    };
+    let requests_recording = recording.clone();
+    let requests = thread::spawn(move || {
+        pipe(
+            client_reader,
+            server_writer,
+            Direction::Request,
+            connection,
+            requests_recording,
+        )
+    });
+    pipe(server, client, Direction::Response, connection, recording);
+    let _ = requests.join();
+}
+
+/// Copies bytes from `from` to `to` until either side closes, recording
+/// each complete netstring before forwarding its last bytes, so that a
+/// request is always recorded before its response.
+fn pipe(
+    mut from: TcpStream,
+    mut to: TcpStream,
+    direction: Direction,
+    connection: usize,
+    recording: Recording,
+) {
+    let mut splitter = FrameSplitter::new(direction);
+    let mut buf = [0; 1024];
+
+    loop {
+        let n = match from.read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => n,
+        };
+        recording.record(connection, direction, splitter.feed(&buf[..n]));
+        if to.write_all(&buf[..n]).is_err() {
+            break;
+        }
+    }
+
+    let _ = to.shutdown(Shutdown::Both);
+    let _ = from.shutdown(Shutdown::Both);
+}
+
+/// A request received during a replay that does not match the session.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mismatch {
+    pub connection: usize,
+    /// The request recorded at this point, `None` past the end of the
+    /// recording.
+    pub expected: Option<String>,
+    pub actual: String,
+}
+
+#[derive(Default)]
+struct ReplayState {
+    next_connection: usize,
+    replayed: usize,
+    mismatches: Vec<Mismatch>,
+}
+
+/// A recorded session being replayed. The n-th connection made to it, by
+/// `transport` or through a `ReplayServer`, is answered with the frames of
+/// connection n of the session. Requests are compared with the recording
+/// as JSON, so the order of keys does not matter. The first mismatch closes
+/// the connection. Responses are sent as soon as their request arrives,
+/// regardless of the recorded timestamps.
+#[derive(Clone)]
+pub struct Replay {
+    session: Arc<Session>,
+    state: Arc<Mutex<ReplayState>>,
+}
+
+impl Replay {
+    pub fn new(session: Session) -> Self {
+        Replay {
+            session: Arc::new(session),
+            state: Arc::default(),
+        }
+    }
+
+    /// An in-memory transport replaying the next connection of the session.
+    pub fn transport(&self) -> ReplayTransport {
+        ReplayTransport {
+            replayer: self.next_connection(),
+            requests: FrameSplitter::new(Direction::Request),
+            output: VecDeque::new(),
+            closed: false,
+        }
+    }
+
+    pub fn mismatches(&self) -> Vec<Mismatch> {
+        self.state.lock().unwrap().mismatches.clone()
+    }
+
+    /// Whether every frame of the session has been replayed.
+    pub fn is_complete(&self) -> bool {
+        self.state.lock().unwrap().replayed == self.session.frames.len()
+    }
+
+    fn next_connection(&self) -> Replayer {
+        let connection = {
+            let mut state = self.state.lock().unwrap();
+            state.next_connection += 1;
+            state.next_connection - 1
+        };
+        Replayer {
+            connection,
+            frames: self.session.connection(connection).into(),
+            state: self.state.clone(),
+        }
+    }
+}
+
+/// The frames of one connection left to replay.
+struct Replayer {
+    connection: usize,
+    frames: VecDeque<Frame>,
+    state: Arc<Mutex<ReplayState>>,
+}
+
+impl Replayer {
+    /// The responses due before the next request.
+    fn responses(&mut self) -> Vec<String> {
+        let mut responses = Vec::new();
+        while let Some(frame) = self.frames.front() {
+            if frame.direction != Direction::Response {
+                break;
+            }
+            responses.push(frame.data.clone());
+            self.frames.pop_front();
+            self.state.lock().unwrap().replayed += 1;
+        }
+        responses
+    }
+
+    /// Checks `request` against the next recorded frame, recording a
+    /// mismatch if it differs.
+    fn request(&mut self, request: String) -> bool {
+        let expected = self.frames.pop_front();
+        let mut state = self.state.lock().unwrap();
+        match expected {
+            Some(frame) if same_json(&frame.data, &request) => {
+                state.replayed += 1;
+                true
+            }
+            other => {
+                state.mismatches.push(Mismatch {
+                    connection: self.connection,
+                    expected: other.map(|frame| frame.data),
+                    actual: request,
+                });
+                false
+            }
+        }
+    }
+}
+
+/// A transport answering requests from a recorded session, without a
+/// server. It reports end of stream once the recorded responses are
+/// read, and after a mismatch.
+pub struct ReplayTransport {
+    replayer: Replayer,
+    requests: FrameSplitter,
+    output: VecDeque<u8>,
+    closed: bool,
+}
+
+impl Read for ReplayTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.output.read(buf)
+    }
+}
+
+impl Write for ReplayTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.closed {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        let requests = self.requests.feed(buf);
+        if !self.requests.upgraded {
+            return Ok(buf.len());
+        }
+
+        let mut responses = self.replayer.responses();
+        for request in requests {
+            if !self.replayer.request(request) {
+                self.closed = true;
+                return Ok(buf.len());
+            }
+            responses.extend(self.replayer.responses());
+        }
+        for response in responses {
+            self.output.extend(response.to_netstring());
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncRead for ReplayTransport {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let n = buf.remaining().min(this.output.len());
+        let bytes: Vec<u8> = this.output.drain(..n).collect();
+        buf.put_slice(&bytes);
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncWrite for ReplayTransport {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Poll::Ready(self.get_mut().write(buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Fake server replaying a session to the clients connecting to it, see
+/// `Replay`.
+pub struct ReplayServer {
+    port: u16,
+    replay: Replay,
+}
+
+impl ReplayServer {
+    pub fn start(session: Session) -> io::Result<ReplayServer> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let port = listener.local_addr()?.port();
+        let replay = Replay::new(session);
+
+        let shared = replay.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let replayer = shared.next_connection();
+                thread::spawn(move || serve(stream, replayer));
+            }
+        });
+
+        Ok(ReplayServer { port, replay })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn mismatches(&self) -> Vec<Mismatch> {
+        self.replay.mismatches()
+    }
+
+    /// Whether every frame of the session has been replayed.
+    pub fn is_complete(&self) -> bool {
+        self.replay.is_complete()
+    }
+}
+
+fn serve(stream: TcpStream, mut replayer: Replayer) {
+    let Ok(writer) = stream.try_clone() else {
+        return;
+    };
+    let mut writer = writer;
+    let mut reader = BufReader::new(stream);
+
+    let mut line = String::new();
+    if reader.read_line(&mut line).is_err() || line.trim() != "PROTO30" {
+        return;
+    }
+
+    let mut msg: Vec<u8> = Vec::new();
+    let mut buf = [0; 128];
+    loop {
+        for response in replayer.responses() {
+            if writer.write_all(&response.to_netstring()).is_err() {
+                return;
+            }
+        }
+
+        let (request, consumed) = match split_netstring(&msg) {
+            Ok((s, n)) => (String::from_utf8_lossy(s).into_owned(), n),
+            Err(NetstringError::Incomplete) => match reader.read(&mut buf) {
+                Ok(0) | Err(_) => return,
+                Ok(n) => {
+                    msg.extend_from_slice(&buf[..n]);
+                    continue;
+                }
+            },
+            Err(_) => return,
+        };
+        msg.drain(..consumed);
+
+        if !replayer.request(request) {
+            let _ = writer.shutdown(Shutdown::Both);
+            return;
+        }
+    }
+}
+
+fn same_json(a: &str, b: &str) -> bool {
+    match (
+        serde_json::from_str::<Value>(a),
+        serde_json::from_str::<Value>(b),
+    ) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::mock_server::MockServer;
+    use crate::netcom::{RdOp, WrOp};
+    use crate::netcom_client_sync::NetcomClientSync;
+
+    type Values = HashMap<String, Option<f64>>;
+
+    fn exchange(client: &mut NetcomClientSync) -> (Vec<String>, Values, Values) {
+        let devices = client
+            .get_device_list()
+            .unwrap()
+            .into_iter()
+            .map(|d| d.name)
+            .collect();
+        let rdops = vec![
+            RdOp::Default {
+                p: "speed".to_string(),
+            },
+            RdOp::Default {
+                p: "mode".to_string(),
+            },
+        ];
+        let read = client.read_parameters("pump-3", rdops).unwrap();
+        let wrops = vec![WrOp::Default {
+            p: "speed".to_string(),
+            v: 900.0,
+        }];
+        let written = client.write_parameters("pump-3", wrops).unwrap();
+        (devices, read, written)
+    }
+
+    fn record() -> (Session, (Vec<String>, Values, Values)) {
+        let server = MockServer::start().with_device("pump-3", &[("speed", 1200.0), ("mode", 1.0)]);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session.jsonl");
+        let recorder = Recorder::start("127.0.0.1", server.port(), &path).unwrap();
+
+        let mut client = NetcomClientSync::new("127.0.0.1", recorder.port());
+        let results = exchange(&mut client);
+        assert_eq!(server.value("pump-3", "speed"), Some(900.0));
+
+        let session = Session::load(&path).unwrap();
+        assert_eq!(session, recorder.session());
+        assert!(recorder.take_error().is_none());
+        (session, results)
+    }
+
+    #[test]
+    fn should_record_requests_and_responses() {
+        let (session, _) = record();
+        let directions: Vec<Direction> = session.frames.iter().map(|f| f.direction).collect();
+        use Direction::*;
+        assert_eq!(
+            directions,
+            [Response, Request, Response, Request, Response, Request, Response]
+        );
+        assert!(session.frames.iter().all(|f| f.connection == 0));
+        assert!(session.frames[0].data.contains("mock-1.0"));
+        assert!(session.frames[5].data.contains(r#""r":"write""#));
+
+        let text = session.to_json_lines();
+        assert_eq!(text.lines().count(), 7);
+        assert_eq!(Session::from_json_lines(&text).unwrap(), session);
+    }
+
+    /// A file system with no space left.
+    struct FullWriter;
+
+    impl Write for FullWriter {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::StorageFull.into())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn server() -> MockServer {
+        MockServer::start().with_device("pump-3", &[("speed", 1200.0), ("mode", 1.0)])
+    }
+
+    #[test]
+    fn should_keep_the_first_write_error() {
+        let server = server();
+        let recording = Recording::new(FullWriter);
+        let stream = TcpStream::connect(("127.0.0.1", server.port())).unwrap();
+
+        let mut client = NetcomClientSync::from_transport(recording.wrap(stream));
+        exchange(&mut client);
+        assert_eq!(recording.session().frames.len(), 7);
+        assert_eq!(
+            recording.take_error().unwrap().kind(),
+            io::ErrorKind::StorageFull
+        );
+        assert!(recording.take_error().is_none());
+    }
+
+    #[test]
+    fn should_record_and_replay_through_transports() {
+        let server = server();
+        let recording = Recording::new(io::sink());
+        let stream = TcpStream::connect(("127.0.0.1", server.port())).unwrap();
+        let mut client = NetcomClientSync::from_transport(recording.wrap(stream));
+        let recorded = exchange(&mut client);
+        drop(server);
+
+        // The same frames as recorded by the proxy.
+        let session = recording.session();
+        let (proxied, _) = record();
+        assert_eq!(session.frames.len(), proxied.frames.len());
+        for (a, b) in session.frames.iter().zip(&proxied.frames) {
+            assert_eq!(a.direction, b.direction);
+            assert!(same_json(&a.data, &b.data));
+        }
+
+        let replay = Replay::new(session.clone());
+        let mut client = NetcomClientSync::from_transport(replay.transport());
+        assert_eq!(exchange(&mut client), recorded);
+        assert!(replay.is_complete());
+
+        let replay = Replay::new(session);
+        let mut client = NetcomClientSync::from_transport(replay.transport());
+        let err = client.push_client_info("tests").unwrap_err();
+        assert!(err.needs_reconnect());
+        assert_eq!(replay.mismatches().len(), 1);
+        assert!(replay.mismatches()[0].actual.contains("client-info"));
+    }
+
+    #[test]
+    fn should_replay_session_without_server() {
+        let (session, recorded) = record();
+        let replay = ReplayServer::start(session).unwrap();
+
+        let mut client = NetcomClientSync::new("127.0.0.1", replay.port());
+        assert_eq!(client.version(), None);
+        assert_eq!(exchange(&mut client), recorded);
+        assert_eq!(client.version(), Some("mock-1.0"));
+        assert!(replay.is_complete());
+        assert_eq!(replay.mismatches(), []);
+    }
+
+    #[test]
+    fn should_report_requests_missing_from_session() {
+        let (session, _) = record();
+        let replay = ReplayServer::start(session).unwrap();
+
+        let mut client = NetcomClientSync::new("127.0.0.1", replay.port());
+        let err = client.push_client_info("tests").unwrap_err();
+        assert!(err.needs_reconnect());
+
+        let mismatches = replay.mismatches();
+        assert_eq!(mismatches.len(), 1);
+        assert!(mismatches[0]
+            .expected
+            .as_ref()
+            .unwrap()
+            .contains("device-list"));
+        assert!(mismatches[0].actual.contains("client-info"));
+        assert!(!replay.is_complete());
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn should_replay_session_into_async_client() {
+        let (session, (devices, read, written)) =
+            tokio::task::spawn_blocking(record).await.unwrap();
+        let replay = ReplayServer::start(session).unwrap();
+
+        let mut client =
+            crate::netcom_client_async::NetcomClientAsync::new("127.0.0.1", replay.port());
+        let list = client.get_device_list().await.unwrap();
+        assert_eq!(
+            list.into_iter().map(|d| d.name).collect::<Vec<_>>(),
+            devices
+        );
+        let rdops = vec![
+            RdOp::Default {
+                p: "mode".to_string(),
+            },
+            RdOp::Default {
+                p: "speed".to_string(),
+            },
+        ];
+        assert_eq!(client.read_parameters("pump-3", rdops).await.unwrap(), read);
+        let wrops = vec![WrOp::Default {
+            p: "speed".to_string(),
+            v: 900.0,
+        }];
+        let echo = client.write_parameters("pump-3", wrops).await.unwrap();
+        assert_eq!(echo, written);
+        assert!(replay.is_complete());
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn should_record_and_replay_async_client_through_transports() {
+        let server = server();
+        let recording = Recording::new(io::sink());
+        let stream = tokio::net::TcpStream::connect(("127.0.0.1", server.port()))
+            .await
+            .unwrap();
+        let mut client =
+            crate::netcom_client_async::NetcomClientAsync::from_transport(recording.wrap(stream));
+        let rdops = vec![RdOp::Default {
+            p: "speed".to_string(),
+        }];
+        let read = client
+            .read_parameters("pump-3", rdops.clone())
+            .await
+            .unwrap();
+        assert_eq!(recording.session().frames.len(), 3);
+
+        let replay = Replay::new(recording.session());
+        let mut client =
+            crate::netcom_client_async::NetcomClientAsync::from_transport(replay.transport());
+        assert_eq!(client.read_parameters("pump-3", rdops).await.unwrap(), read);
+        assert!(replay.is_complete());
+    }
+}
@@ -0,0 +1,109 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::netcom::{NetcomError, NetcomSync, WrOp};
+
+/// Parameter values of one device at a point in time, keyed by address.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ParameterSnapshot {
+    pub device: String,
+    #[serde(with = "crate::timestamp")]
+    pub timestamp: SystemTime,
+    pub values: BTreeMap<String, Option<f64>>,
+}
+
+impl ParameterSnapshot {
+    pub fn new(device: &str, values: &HashMap<String, Option<f64>>) -> Self {
+        ParameterSnapshot {
+            device: device.to_string(),
+            timestamp: SystemTime::now(),
+            values: values.iter().map(|(k, v)| (k.clone(), *v)).collect(),
+        }
+    }
+
+    pub fn from_struct<T: NetcomSync>(device: &str, params: &T) -> Self {
+        let values = params
+            .to_wrops()
+            .into_iter()
+            .map(|op| match op {
+                WrOp::Default { p, v } => (p, Some(v)),
+                WrOp::WithType { p, v, .. } => (p, Some(v)),
+            })
+            .collect();
+
+        ParameterSnapshot {
+            device: device.to_string(),
+            timestamp: SystemTime::now(),
+            values,
+        }
+    }
+
+    pub fn apply_to<T: NetcomSync>(&self, params: &mut T) -> Result<(), NetcomError> {
+        let result: HashMap<String, Option<f64>> =
+            self.values.iter().map(|(k, v)| (k.clone(), *v)).collect();
+        params.try_apply_result(&result)
+    }
+
+    /// Write operations restoring every non-null value in the snapshot.
+    pub fn to_wrops(&self) -> Vec<WrOp> {
+        self.values
+            .iter()
+            .filter_map(|(p, v)| v.map(|v| WrOp::Default { p: p.clone(), v }))
+            .collect()
+    }
+}
+
+/// Conversion between a `NetcomMap` struct and a `ParameterSnapshot`,
+/// implemented by the derive for structs marked `#[netcom(snapshot)]`.
+pub trait NetcomSnapshot: NetcomSync + Default {
+    fn to_snapshot(&self, device: &str) -> ParameterSnapshot {
+        ParameterSnapshot::from_struct(device, self)
+    }
+
+    fn from_snapshot(snapshot: &ParameterSnapshot) -> Result<Self, NetcomError> {
+        let mut params = Self::default();
+        snapshot.apply_to(&mut params)?;
+        Ok(params)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_server::MockServer;
+    use crate::netcom::NetcomMap;
+    use crate::netcom_client_sync::NetcomClientSync;
+
+    #[derive(Default, NetcomMap)]
+    #[netcom(crate = "crate", snapshot)]
+    struct Setpoints {
+        #[param(p = "sp1")]
+        sp1: f64,
+
+        #[param(p = "sp2")]
+        sp2: f64,
+    }
+
+    #[test]
+    fn should_restore_snapshot_through_json() {
+        let server = MockServer::start()
+            .with_device("unit-1", &[("sp1", 20.0), ("sp2", 45.5)])
+            .with_device("unit-2", &[("sp1", 0.0), ("sp2", 0.0)]);
+        let mut client = NetcomClientSync::new("127.0.0.1", server.port());
+
+        let mut params = Setpoints::default();
+        client.read_struct("unit-1", &mut params).unwrap();
+        let json = serde_json::to_string(&params.to_snapshot("unit-1")).unwrap();
+
+        let snapshot: ParameterSnapshot = serde_json::from_str(&json).unwrap();
+        assert_eq!(snapshot.device, "unit-1");
+        assert_eq!(snapshot.values.get("sp2"), Some(&Some(45.5)));
+
+        let restored = Setpoints::from_snapshot(&snapshot).unwrap();
+        client.write_struct("unit-2", &restored).unwrap();
+        assert_eq!(server.value("unit-2", "sp1"), Some(20.0));
+        assert_eq!(server.value("unit-2", "sp2"), Some(45.5));
+    }
+}
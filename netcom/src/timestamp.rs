@@ -0,0 +1,54 @@
+//! Serde format of the timestamps written by the crate: RFC 3339 strings in
+//! UTC, e.g. `2024-01-31T23:59:30.250Z`. Used with
+//! `#[serde(with = "crate::timestamp")]`.
+
+use std::time::SystemTime;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{de, Deserialize, Deserializer, Serializer};
+
+pub(crate) fn serialize<S: Serializer>(t: &SystemTime, serializer: S) -> Result<S::Ok, S::Error> {
+    let t = DateTime::<Utc>::from(*t);
+    serializer.serialize_str(&t.to_rfc3339_opts(SecondsFormat::AutoSi, true))
+}
+
+pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<SystemTime, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    let t = DateTime::parse_from_rfc3339(&s).map_err(de::Error::custom)?;
+    Ok(t.with_timezone(&Utc).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Entry {
+        #[serde(with = "crate::timestamp")]
+        timestamp: SystemTime,
+    }
+
+    #[test]
+    fn should_write_rfc3339() {
+        let entry = Entry {
+            timestamp: SystemTime::UNIX_EPOCH + Duration::from_millis(1706745570250),
+        };
+
+        let json = serde_json::to_string(&entry).unwrap();
+        assert_eq!(json, r#"{"timestamp":"2024-01-31T23:59:30.250Z"}"#);
+        assert_eq!(serde_json::from_str::<Entry>(&json).unwrap(), entry);
+
+        let now = Entry {
+            timestamp: SystemTime::now(),
+        };
+        let json = serde_json::to_string(&now).unwrap();
+        assert_eq!(serde_json::from_str::<Entry>(&json).unwrap(), now);
+        assert!(serde_json::from_str::<Entry>(r#"{"timestamp":"yesterday"}"#).is_err());
+    }
+}
@@ -0,0 +1,345 @@
+//! TLS connections to the netcom server, built on rustls.
+//!
+//! The handshake runs as soon as the TCP connection is made, so that the
+//! `PROTO30` upgrade and all traffic after it are encrypted. Only the CA
+//! certificates added to `TlsOptions` are trusted.
+//!
+//! The `tls` feature enables TLS for `NetcomClientSync`; `tokio-tls` adds it
+//! to `NetcomClientAsync`.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+
+use crate::netcom::NetcomError;
+
+#[derive(Debug, Default)]
+pub struct TlsOptions {
+    roots: Vec<CertificateDer<'static>>,
+    client_auth: Option<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)>,
+    server_name: Option<String>,
+}
+
+fn invalid(what: &str, e: impl std::fmt::Display) -> NetcomError {
+    NetcomError::Tls(format!("{}: {}", what, e))
+}
+
+fn certificates(pem: &[u8]) -> Result<Vec<CertificateDer<'static>>, NetcomError> {
+    let certs = CertificateDer::pem_slice_iter(pem)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| invalid("Invalid certificate PEM", e))?;
+    if certs.is_empty() {
+        return Err(NetcomError::Tls("No certificates found in PEM".to_string()));
+    }
+    Ok(certs)
+}
+
+impl TlsOptions {
+    pub fn new() -> Self {
+        TlsOptions::default()
+    }
+
+    /// Trusts the CA certificates in the PEM bundle `pem`.
+    pub fn with_ca_pem(mut self, pem: &[u8]) -> Result<Self, NetcomError> {
+        self.roots.extend(certificates(pem)?);
+        Ok(self)
+    }
+
+    /// Trusts the CA certificates in the PEM bundle at `path`.
+    pub fn with_ca_file(self, path: impl AsRef<Path>) -> Result<Self, NetcomError> {
+        let path = path.as_ref();
+        let pem = std::fs::read(path)
+            .map_err(|e| invalid(&format!("Cannot read {}", path.display()), e))?;
+        self.with_ca_pem(&pem)
+    }
+
+    /// Presents the certificate chain in `cert_pem`, with the private key
+    /// in `key_pem`, to servers that ask for a client certificate.
+    pub fn with_client_cert_pem(
+        mut self,
+        cert_pem: &[u8],
+        key_pem: &[u8],
+    ) -> Result<Self, NetcomError> {
+        let key = PrivateKeyDer::from_pem_slice(key_pem)
+            .map_err(|e| invalid("Invalid private key PEM", e))?;
+        self.client_auth = Some((certificates(cert_pem)?, key));
+        Ok(self)
+    }
+
+    /// Verifies the server certificate against `name` instead of the host
+    /// connected to, e.g. when connecting by IP address.
+    pub fn with_server_name(mut self, name: &str) -> Self {
+        self.server_name = Some(name.to_string());
+        self
+    }
+
+    pub(crate) fn connector(&self, hostname: &str) -> Result<TlsConnector, NetcomError> {
+        if self.roots.is_empty() {
+            return Err(NetcomError::Tls("No CA certificates to trust".to_string()));
+        }
+        let mut roots = RootCertStore::empty();
+        for cert in &self.roots {
+            roots
+                .add(cert.clone())
+                .map_err(|e| invalid("Invalid CA certificate", e))?;
+        }
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let builder = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(|e| invalid("Invalid TLS configuration", e))?
+            .with_root_certificates(roots);
+        let config = match &self.client_auth {
+            Some((certs, key)) => builder
+                .with_client_auth_cert(certs.clone(), key.clone_key())
+                .map_err(|e| invalid("Invalid client certificate", e))?,
+            None => builder.with_no_client_auth(),
+        };
+
+        let name = self.server_name.as_deref().unwrap_or(hostname);
+        let server_name = ServerName::try_from(name.to_string())
+            .map_err(|e| invalid("Invalid server name", e))?;
+        Ok(TlsConnector {
+            config: Arc::new(config),
+            server_name,
+        })
+    }
+}
+
+/// Client side of the TLS handshake, shared by every connection a client
+/// makes.
+pub(crate) struct TlsConnector {
+    config: Arc<ClientConfig>,
+    server_name: ServerName<'static>,
+}
+
+impl TlsConnector {
+    /// Runs the handshake on `tcp` to completion, so that certificate
+    /// errors surface when connecting.
+    pub(crate) fn connect(
+        &self,
+        tcp: std::net::TcpStream,
+    ) -> Result<StreamOwned<ClientConnection, std::net::TcpStream>, NetcomError> {
+        let connection = ClientConnection::new(self.config.clone(), self.server_name.clone())
+            .map_err(|e| invalid("Invalid TLS configuration", e))?;
+        let mut stream = StreamOwned::new(connection, tcp);
+        while stream.conn.is_handshaking() {
+            stream
+                .conn
+                .complete_io(&mut stream.sock)
+                .map_err(NetcomError::StreamError)?;
+        }
+        Ok(stream)
+    }
+
+    #[cfg(feature = "tokio-tls")]
+    pub(crate) async fn connect_async(
+        &self,
+        tcp: tokio::net::TcpStream,
+    ) -> Result<tokio_rustls::client::TlsStream<tokio::net::TcpStream>, NetcomError> {
+        tokio_rustls::TlsConnector::from(self.config.clone())
+            .connect(self.server_name.clone(), tcp)
+            .await
+            .map_err(NetcomError::StreamError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rcgen::{BasicConstraints, CertificateParams, CertifiedIssuer, IsCa, KeyPair};
+    use rustls::server::WebPkiClientVerifier;
+    use rustls::ServerConfig;
+    use tokio_rustls::TlsAcceptor;
+
+    use super::*;
+    use crate::mock_server::MockServer;
+    use crate::netcom::RdOp;
+    #[cfg(feature = "tokio-tls")]
+    use crate::netcom_client_async::NetcomClientAsync;
+    use crate::netcom_client_sync::NetcomClientSync;
+
+    /// A CA with a server certificate for `netcom.test` and a client
+    /// certificate, all in PEM.
+    #[cfg_attr(not(feature = "tokio-tls"), allow(dead_code))]
+    struct Pki {
+        ca: String,
+        server_cert: String,
+        server_key: String,
+        client_cert: String,
+        client_key: String,
+    }
+
+    fn ca() -> CertifiedIssuer<'static, KeyPair> {
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        CertifiedIssuer::self_signed(params, KeyPair::generate().unwrap()).unwrap()
+    }
+
+    fn pki() -> Pki {
+        let ca = ca();
+        let server_key = KeyPair::generate().unwrap();
+        let server_cert = CertificateParams::new(vec!["netcom.test".to_string()])
+            .unwrap()
+            .signed_by(&server_key, &ca)
+            .unwrap();
+        let client_key = KeyPair::generate().unwrap();
+        let client_cert = CertificateParams::new(vec!["scada-1".to_string()])
+            .unwrap()
+            .signed_by(&client_key, &ca)
+            .unwrap();
+
+        Pki {
+            ca: ca.pem(),
+            server_cert: server_cert.pem(),
+            server_key: server_key.serialize_pem(),
+            client_cert: client_cert.pem(),
+            client_key: client_key.serialize_pem(),
+        }
+    }
+
+    /// Starts a TLS-terminating proxy in front of `mock`, requiring client
+    /// certificates signed by the CA if `client_auth` is set.
+    async fn tls_server(mock: &MockServer, pki: &Pki, client_auth: bool) -> u16 {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .unwrap();
+        let builder = if client_auth {
+            let mut roots = RootCertStore::empty();
+            roots.add_parsable_certificates(certificates(pki.ca.as_bytes()).unwrap());
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .build()
+                .unwrap();
+            builder.with_client_cert_verifier(verifier)
+        } else {
+            builder.with_no_client_auth()
+        };
+        let config = builder
+            .with_single_cert(
+                certificates(pki.server_cert.as_bytes()).unwrap(),
+                PrivateKeyDer::from_pem_slice(pki.server_key.as_bytes()).unwrap(),
+            )
+            .unwrap();
+
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let upstream = mock.port();
+        tokio::spawn(async move {
+            while let Ok((tcp, _)) = listener.accept().await {
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    let Ok(mut tls) = acceptor.accept(tcp).await else {
+                        return;
+                    };
+                    let mut plain = tokio::net::TcpStream::connect(("127.0.0.1", upstream))
+                        .await
+                        .unwrap();
+                    let _ = tokio::io::copy_bidirectional(&mut tls, &mut plain).await;
+                });
+            }
+        });
+        port
+    }
+
+    fn speed() -> Vec<RdOp> {
+        vec![RdOp::Default {
+            p: "speed".to_string(),
+        }]
+    }
+
+    fn options(pki: &Pki) -> TlsOptions {
+        TlsOptions::new()
+            .with_ca_pem(pki.ca.as_bytes())
+            .unwrap()
+            .with_server_name("netcom.test")
+    }
+
+    #[cfg(feature = "tokio-tls")]
+    #[tokio::test]
+    async fn should_upgrade_over_tls() {
+        let mock = MockServer::start().with_device("pump-3", &[("speed", 1200.0)]);
+        let pki = pki();
+        let port = tls_server(&mock, &pki, false).await;
+
+        let mut client = NetcomClientAsync::new_tls("127.0.0.1", port, &options(&pki)).unwrap();
+        let result = client.read_parameters("pump-3", speed()).await.unwrap();
+        assert_eq!(result.get("speed"), Some(&Some(1200.0)));
+        assert_eq!(client.version(), Some("mock-1.0"));
+
+        // Reconnects open a new TLS session.
+        client.disconnect();
+        assert!(client.read_parameters("pump-3", speed()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn should_upgrade_sync_client_over_tls() {
+        let mock = MockServer::start().with_device("pump-3", &[("speed", 1200.0)]);
+        let pki = pki();
+        let port = tls_server(&mock, &pki, false).await;
+        let options = options(&pki);
+
+        let result = tokio::task::spawn_blocking(move || {
+            let mut client = NetcomClientSync::new_tls("127.0.0.1", port, &options).unwrap();
+            client.read_parameters("pump-3", speed())
+        })
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(result.get("speed"), Some(&Some(1200.0)));
+    }
+
+    #[cfg(feature = "tokio-tls")]
+    #[tokio::test]
+    async fn should_reject_untrusted_or_misnamed_server() {
+        let mock = MockServer::start().with_device("pump-3", &[("speed", 1200.0)]);
+        let pki = pki();
+        let port = tls_server(&mock, &pki, false).await;
+
+        let other_ca = TlsOptions::new()
+            .with_ca_pem(ca().pem().as_bytes())
+            .unwrap()
+            .with_server_name("netcom.test");
+        let mut client = NetcomClientAsync::new_tls("127.0.0.1", port, &other_ca).unwrap();
+        let err = client.read_parameters("pump-3", speed()).await.unwrap_err();
+        assert_eq!(err.kind(), "stream");
+
+        // The certificate is not valid for the address connected to.
+        let no_override = TlsOptions::new().with_ca_pem(pki.ca.as_bytes()).unwrap();
+        let mut client = NetcomClientAsync::new_tls("127.0.0.1", port, &no_override).unwrap();
+        assert!(client.read_parameters("pump-3", speed()).await.is_err());
+        assert_eq!(mock.request_count("read"), 0);
+    }
+
+    #[cfg(feature = "tokio-tls")]
+    #[tokio::test]
+    async fn should_present_client_certificate() {
+        let mock = MockServer::start().with_device("pump-3", &[("speed", 1200.0)]);
+        let pki = pki();
+        let port = tls_server(&mock, &pki, true).await;
+
+        let mut client = NetcomClientAsync::new_tls("127.0.0.1", port, &options(&pki)).unwrap();
+        assert!(client.read_parameters("pump-3", speed()).await.is_err());
+
+        let options = options(&pki)
+            .with_client_cert_pem(pki.client_cert.as_bytes(), pki.client_key.as_bytes())
+            .unwrap();
+        let mut client = NetcomClientAsync::new_tls("127.0.0.1", port, &options).unwrap();
+        assert!(client.read_parameters("pump-3", speed()).await.is_ok());
+    }
+
+    #[test]
+    fn should_reject_invalid_options() {
+        assert!(matches!(
+            TlsOptions::new().with_ca_pem(b"not a certificate"),
+            Err(NetcomError::Tls(_))
+        ));
+        assert!(matches!(
+            NetcomClientSync::new_tls("127.0.0.1", 7878, &TlsOptions::new()),
+            Err(NetcomError::Tls(_))
+        ));
+    }
+}
@@ -0,0 +1,217 @@
+//! Byte streams the clients run the netcom protocol over.
+//!
+//! `NetcomClientSync` accepts any `Read + Write` stream and
+//! `NetcomClientAsync` any `AsyncRead + AsyncWrite` stream. Clients created
+//! from an address open a new stream whenever they (re)connect; a client
+//! handed a stream can connect once, since the stream cannot be reopened.
+
+use std::io::{Read, Write};
+#[cfg(unix)]
+use std::path::PathBuf;
+
+#[cfg(feature = "tokio")]
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::netcom::NetcomError;
+#[cfg(feature = "tls")]
+use crate::tls::{TlsConnector, TlsOptions};
+
+pub trait Transport: Read + Write + Send {}
+
+impl<T: Read + Write + Send> Transport for T {}
+
+#[cfg(feature = "tokio")]
+pub trait AsyncTransport: AsyncRead + AsyncWrite + Unpin + Send {}
+
+#[cfg(feature = "tokio")]
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> AsyncTransport for T {}
+
+/// Where a client gets its stream from when it connects.
+pub(crate) enum Endpoint<T: ?Sized> {
+    Tcp {
+        hostname: String,
+        port: u16,
+    },
+    #[cfg(unix)]
+    Unix(PathBuf),
+    #[cfg(feature = "tls")]
+    Tls {
+        hostname: String,
+        port: u16,
+        connector: TlsConnector,
+    },
+    /// A stream supplied by the caller, taken by the first connect.
+    Supplied(Option<Box<T>>),
+}
+
+impl<T: ?Sized> Endpoint<T> {
+    pub(crate) fn tcp(hostname: &str, port: u16) -> Self {
+        Endpoint::Tcp {
+            hostname: hostname.to_string(),
+            port,
+        }
+    }
+
+    #[cfg(feature = "tls")]
+    pub(crate) fn tls(
+        hostname: &str,
+        port: u16,
+        options: &TlsOptions,
+    ) -> Result<Self, NetcomError> {
+        Ok(Endpoint::Tls {
+            hostname: hostname.to_string(),
+            port,
+            connector: options.connector(hostname)?,
+        })
+    }
+}
+
+impl Endpoint<dyn Transport> {
+    pub(crate) fn open(&mut self) -> Result<Box<dyn Transport>, NetcomError> {
+        match self {
+            Endpoint::Tcp { hostname, port } => {
+                let host = format!("{}:{}", hostname, port);
+                let stream =
+                    std::net::TcpStream::connect(host).map_err(NetcomError::StreamError)?;
+                Ok(Box::new(stream))
+            }
+            #[cfg(unix)]
+            Endpoint::Unix(path) => {
+                let stream = std::os::unix::net::UnixStream::connect(path)
+                    .map_err(NetcomError::StreamError)?;
+                Ok(Box::new(stream))
+            }
+            #[cfg(feature = "tls")]
+            Endpoint::Tls {
+                hostname,
+                port,
+                connector,
+            } => {
+                let host = format!("{}:{}", hostname, port);
+                let tcp = std::net::TcpStream::connect(host).map_err(NetcomError::StreamError)?;
+                Ok(Box::new(connector.connect(tcp)?))
+            }
+            Endpoint::Supplied(stream) => stream.take().ok_or(NetcomError::NotConnected),
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Endpoint<dyn AsyncTransport> {
+    pub(crate) async fn open(&mut self) -> Result<Box<dyn AsyncTransport>, NetcomError> {
+        match self {
+            Endpoint::Tcp { hostname, port } => {
+                let host = format!("{}:{}", hostname, port);
+                let stream = tokio::net::TcpStream::connect(host)
+                    .await
+                    .map_err(NetcomError::StreamError)?;
+                Ok(Box::new(stream))
+            }
+            #[cfg(unix)]
+            Endpoint::Unix(path) => {
+                let stream = tokio::net::UnixStream::connect(path)
+                    .await
+                    .map_err(NetcomError::StreamError)?;
+                Ok(Box::new(stream))
+            }
+            #[cfg(feature = "tokio-tls")]
+            Endpoint::Tls {
+                hostname,
+                port,
+                connector,
+            } => {
+                let host = format!("{}:{}", hostname, port);
+                let tcp = tokio::net::TcpStream::connect(host)
+                    .await
+                    .map_err(NetcomError::StreamError)?;
+                Ok(Box::new(connector.connect_async(tcp).await?))
+            }
+            // Only `NetcomClientAsync::new_tls` opens TLS endpoints.
+            #[cfg(all(feature = "tls", not(feature = "tokio-tls")))]
+            Endpoint::Tls { .. } => unreachable!("async TLS needs the tokio-tls feature"),
+            Endpoint::Supplied(stream) => stream.take().ok_or(NetcomError::NotConnected),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_server::MockServer;
+    use crate::netcom::RdOp;
+    use crate::netcom_client_sync::NetcomClientSync;
+
+    fn speed() -> Vec<RdOp> {
+        vec![RdOp::Default {
+            p: "speed".to_string(),
+        }]
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn should_connect_over_unix_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("netcom.sock");
+        let _server = MockServer::start()
+            .with_device("pump-3", &[("speed", 1200.0)])
+            .with_unix_socket(&path);
+
+        let mut client = NetcomClientSync::new_unix(&path);
+        let result = client.read_parameters("pump-3", speed()).unwrap();
+        assert_eq!(result.get("speed"), Some(&Some(1200.0)));
+
+        // A new stream is opened after a disconnect.
+        client.disconnect();
+        assert!(client.read_parameters("pump-3", speed()).is_ok());
+    }
+
+    #[test]
+    fn should_use_supplied_transport_once() {
+        let server = MockServer::start().with_device("pump-3", &[("speed", 1200.0)]);
+        let stream = std::net::TcpStream::connect(("127.0.0.1", server.port())).unwrap();
+
+        let mut client = NetcomClientSync::from_transport(stream);
+        assert!(client.read_parameters("pump-3", speed()).is_ok());
+        assert_eq!(client.version(), Some("mock-1.0"));
+
+        client.disconnect();
+        assert!(matches!(
+            client.read_parameters("pump-3", speed()),
+            Err(NetcomError::NotConnected)
+        ));
+    }
+
+    #[cfg(all(unix, feature = "tokio"))]
+    #[tokio::test]
+    async fn should_connect_async_client_over_unix_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("netcom.sock");
+        let _server = MockServer::start()
+            .with_device("pump-3", &[("speed", 1200.0)])
+            .with_unix_socket(&path);
+
+        let mut client = crate::netcom_client_async::NetcomClientAsync::new_unix(&path);
+        let result = client.read_parameters("pump-3", speed()).await.unwrap();
+        assert_eq!(result.get("speed"), Some(&Some(1200.0)));
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn should_run_over_in_memory_duplex() {
+        let server = MockServer::start().with_device("pump-3", &[("speed", 1200.0)]);
+        // A small buffer, so that requests take several writes.
+        let (mut client, mut remote) = crate::netcom_client_async::NetcomClientAsync::duplex(16);
+        tokio::spawn(async move {
+            let mut tcp = tokio::net::TcpStream::connect(("127.0.0.1", server.port()))
+                .await
+                .unwrap();
+            let _ = tokio::io::copy_bidirectional(&mut remote, &mut tcp).await;
+            drop(server);
+        });
+
+        let result = client.read_parameters("pump-3", speed()).await.unwrap();
+        assert_eq!(result.get("speed"), Some(&Some(1200.0)));
+        let devices = client.get_device_list().await.unwrap();
+        assert_eq!(devices[0].name, "pump-3");
+    }
+}
@@ -0,0 +1,260 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::netcom::{RdOp, WrOp};
+
+#[derive(Clone, Debug)]
+pub struct VerifyOptions {
+    /// Largest difference between the requested and actual value that still
+    /// counts as accepted.
+    pub tolerance: f64,
+    /// Read the parameters back after writing, rather than trusting the
+    /// values echoed by the write.
+    pub read_back: bool,
+    /// How many more times to write parameters that were rejected.
+    pub retries: u32,
+    pub retry_delay: Duration,
+}
+
+impl Default for VerifyOptions {
+    fn default() -> Self {
+        VerifyOptions {
+            tolerance: 0.0,
+            read_back: false,
+            retries: 0,
+            retry_delay: Duration::from_millis(100),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WriteStatus {
+    /// The device holds the requested value.
+    Accepted,
+    /// The device holds a different value, usually limited to its range.
+    /// Clamped writes are not retried, as the device would clamp them again.
+    Clamped,
+    /// The device answered the write, or the read back, with no value.
+    Rejected,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct WriteOutcome {
+    pub param: String,
+    pub requested: f64,
+    /// The echoed or read-back value after the last attempt.
+    pub actual: Option<f64>,
+    pub status: WriteStatus,
+    pub attempts: u32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct WriteReport {
+    pub device: String,
+    /// One entry per written parameter, in the order requested.
+    pub params: Vec<WriteOutcome>,
+}
+
+impl WriteReport {
+    pub fn is_accepted(&self) -> bool {
+        self.params
+            .iter()
+            .all(|o| o.status == WriteStatus::Accepted)
+    }
+
+    pub fn with_status(&self, status: WriteStatus) -> impl Iterator<Item = &WriteOutcome> {
+        self.params.iter().filter(move |o| o.status == status)
+    }
+}
+
+/// State of a verified write across its attempts, shared by both clients.
+pub(crate) struct Verification {
+    wrops: Vec<WrOp>,
+    outcomes: Vec<WriteOutcome>,
+}
+
+impl Verification {
+    pub(crate) fn new(wrops: Vec<WrOp>) -> Self {
+        let outcomes = wrops
+            .iter()
+            .map(|op| {
+                let (p, v) = match op {
+                    WrOp::Default { p, v } => (p, v),
+                    WrOp::WithType { p, v, .. } => (p, v),
+                };
+                WriteOutcome {
+                    param: p.clone(),
+                    requested: *v,
+                    actual: None,
+                    status: WriteStatus::Rejected,
+                    attempts: 0,
+                }
+            })
+            .collect();
+        Verification { wrops, outcomes }
+    }
+
+    /// The writes still to be made: all of them at first, then the rejected
+    /// ones.
+    pub(crate) fn pending(&self) -> Vec<WrOp> {
+        self.wrops
+            .iter()
+            .zip(&self.outcomes)
+            .filter(|(_, o)| o.attempts == 0 || o.status == WriteStatus::Rejected)
+            .map(|(op, _)| op.clone())
+            .collect()
+    }
+
+    /// Reads of the pending parameters, with the same types as the writes.
+    pub(crate) fn read_back(pending: &[WrOp]) -> Vec<RdOp> {
+        pending
+            .iter()
+            .map(|op| match op {
+                WrOp::Default { p, .. } => RdOp::Default { p: p.clone() },
+                WrOp::WithType { p, t, .. } => RdOp::WithType {
+                    p: p.clone(),
+                    t: t.clone(),
+                },
+            })
+            .collect()
+    }
+
+    /// Records the echo of the pending writes and, if read back, the
+    /// values read. A write whose echo is null was rejected, whatever is
+    /// read back. Returns whether another attempt should be made.
+    pub(crate) fn record(
+        &mut self,
+        pending: &[WrOp],
+        echo: &HashMap<String, Option<f64>>,
+        read: Option<&HashMap<String, Option<f64>>>,
+        options: &VerifyOptions,
+    ) -> bool {
+        let mut retry = false;
+        for op in pending {
+            let p = match op {
+                WrOp::Default { p, .. } => p,
+                WrOp::WithType { p, .. } => p,
+            };
+            let echoed = echo.get(p).copied().flatten();
+            for o in self.outcomes.iter_mut().filter(|o| &o.param == p) {
+                o.attempts += 1;
+                o.actual = match read {
+                    Some(read) => read.get(p).copied().flatten(),
+                    None => echoed,
+                };
+                o.status = match (echoed, o.actual) {
+                    (None, _) | (_, None) => WriteStatus::Rejected,
+                    (_, Some(v)) if (v - o.requested).abs() <= options.tolerance => {
+                        WriteStatus::Accepted
+                    }
+                    _ => WriteStatus::Clamped,
+                };
+                retry |= o.status == WriteStatus::Rejected && o.attempts <= options.retries;
+            }
+        }
+        retry
+    }
+
+    pub(crate) fn report(self, device: &str) -> WriteReport {
+        WriteReport {
+            device: device.to_string(),
+            params: self.outcomes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_server::MockServer;
+    use crate::netcom_client_sync::NetcomClientSync;
+
+    fn wrops() -> Vec<WrOp> {
+        vec![
+            WrOp::Default {
+                p: "sp".to_string(),
+                v: 21.5,
+            },
+            WrOp::Default {
+                p: "limit".to_string(),
+                v: 150.0,
+            },
+            WrOp::WithType {
+                p: "serial".to_string(),
+                t: "u32".to_string(),
+                v: 1234.0,
+            },
+        ]
+    }
+
+    fn server() -> MockServer {
+        MockServer::start()
+            .with_device("unit-1", &[("sp", 20.0), ("limit", 80.0), ("serial", 1.0)])
+            .with_range("unit-1", "limit", 0.0, 100.0)
+            .with_read_only("unit-1", "serial")
+    }
+
+    #[test]
+    fn should_classify_writes() {
+        let server = server();
+        let mut client = NetcomClientSync::new("127.0.0.1", server.port());
+
+        let report = client
+            .write_verified("unit-1", wrops(), &VerifyOptions::default())
+            .unwrap();
+        assert!(!report.is_accepted());
+        let statuses: Vec<_> = report
+            .params
+            .iter()
+            .map(|o| (o.param.as_str(), o.actual, o.status))
+            .collect();
+        assert_eq!(
+            statuses,
+            [
+                ("sp", Some(21.5), WriteStatus::Accepted),
+                ("limit", Some(100.0), WriteStatus::Clamped),
+                ("serial", None, WriteStatus::Rejected),
+            ]
+        );
+    }
+
+    #[test]
+    fn should_retry_rejected_writes() {
+        let server = server();
+        let mut client = NetcomClientSync::new("127.0.0.1", server.port());
+        let options = VerifyOptions {
+            read_back: true,
+            retries: 2,
+            retry_delay: Duration::from_millis(1),
+            ..Default::default()
+        };
+
+        let report = client.write_verified("unit-1", wrops(), &options).unwrap();
+        assert_eq!(server.request_count("write"), 3);
+        assert_eq!(server.request_count("read"), 3);
+        let attempts: Vec<_> = report.params.iter().map(|o| o.attempts).collect();
+        assert_eq!(attempts, [1, 1, 3]);
+        // Read back, the rejected parameter shows its unchanged value.
+        assert_eq!(report.params[2].actual, Some(1.0));
+        assert_eq!(report.params[2].status, WriteStatus::Rejected);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn should_verify_writes_with_async_client() {
+        let server = server();
+        let mut client =
+            crate::netcom_client_async::NetcomClientAsync::new("127.0.0.1", server.port());
+        let options = VerifyOptions {
+            tolerance: 0.01,
+            ..Default::default()
+        };
+
+        let report = client
+            .write_verified("unit-1", wrops()[..1].to_vec(), &options)
+            .await
+            .unwrap();
+        assert!(report.is_accepted());
+        assert_eq!(server.value("unit-1", "sp"), Some(21.5));
+    }
+}